@@ -0,0 +1,375 @@
+use std::{
+    panic::{catch_unwind, resume_unwind, AssertUnwindSafe},
+    pin::Pin,
+    task::{Context, Poll},
+    thread,
+};
+
+use futures_core::Stream;
+use tokio::{
+    runtime::Handle,
+    sync::{mpsc, oneshot},
+};
+
+use crate::{
+    db::DB,
+    errors::{Error, Result},
+    read_tx::ReadData,
+    tx::Tx,
+};
+
+// Number of items a cursor reads ahead of the stream
+const CURSOR_BUFFER_SIZE: usize = 256;
+
+type Job = Box<dyn FnOnce(&DB) + Send>;
+
+/// A handle to a [`DB`] for use with the tokio runtime.
+///
+/// Writable transactions are run one at a time on a dedicated thread, and read-only transactions are run
+/// using [`spawn_blocking`](tokio::task::spawn_blocking), so syncing the database file to disk never blocks the executor.
+/// Your closures get a normal [`Tx`], so there's nothing new to learn, and nothing borrowed from the
+/// transaction has to live across an `.await`.
+///
+/// Cloning an `AsyncDB` is cheap, and every clone shares the same writer thread.
+/// The writer thread stops once every clone has been dropped.
+/// Only available with the `tokio` feature.
+///
+/// # Examples
+///
+/// ```no_run
+/// use jammdb::{AsyncDB, DB};
+/// # use jammdb::Error;
+///
+/// # async fn run() -> Result<(), Error> {
+/// let db = AsyncDB::new(DB::open("my.db")?);
+/// db.update(|tx| {
+///     tx.get_or_create_bucket("users")?.put("pj", "tatlow")?;
+///     Ok::<_, Error>(())
+/// })
+/// .await?;
+/// let name = db
+///     .view(|tx| Ok::<_, Error>(tx.get_bucket("users")?.get_kv("pj").map(|kv| kv.value().to_vec())))
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct AsyncDB {
+    db: DB,
+    writer: mpsc::UnboundedSender<Job>,
+}
+
+impl AsyncDB {
+    /// Wraps the database, and starts the writer thread.
+    pub fn new(db: DB) -> AsyncDB {
+        let (writer, mut jobs) = mpsc::unbounded_channel::<Job>();
+        let writer_db = db.clone();
+        thread::Builder::new()
+            .name(String::from("jammdb-writer"))
+            .spawn(move || {
+                while let Some(job) = jobs.blocking_recv() {
+                    job(&writer_db);
+                }
+            })
+            .expect("failed to spawn writer thread");
+        AsyncDB { db, writer }
+    }
+
+    /// Returns the wrapped database.
+    pub fn db(&self) -> &DB {
+        &self.db
+    }
+
+    /// Runs the closure in a writable transaction on the writer thread,
+    /// and commits it if the closure returns `Ok`.
+    ///
+    /// See [`DB::update`](struct.DB.html#method.update) for details.
+    /// If the closure panics, the transaction is rolled back and the panic is resumed in the calling task.
+    pub async fn update<T, E, F>(&self, f: F) -> std::result::Result<T, E>
+    where
+        F: FnOnce(&Tx) -> std::result::Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: From<Error> + Send + 'static,
+    {
+        let (done, result) = oneshot::channel();
+        let job: Job = Box::new(move |db| {
+            let _ = done.send(catch_unwind(AssertUnwindSafe(|| db.update(f))));
+        });
+        if self.writer.send(job).is_err() {
+            return Err(Error::Cancelled("writer thread stopped").into());
+        }
+        match result.await {
+            Ok(Ok(result)) => result,
+            Ok(Err(panic)) => resume_unwind(panic),
+            Err(_) => Err(Error::Cancelled("writer thread stopped").into()),
+        }
+    }
+
+    /// Runs the closure in a read-only transaction on tokio's blocking thread pool.
+    ///
+    /// See [`DB::view`](struct.DB.html#method.view) for details.
+    /// If the closure panics, the panic is resumed in the calling task.
+    pub async fn view<T, E, F>(&self, f: F) -> std::result::Result<T, E>
+    where
+        F: FnOnce(&Tx) -> std::result::Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: From<Error> + Send + 'static,
+    {
+        let db = self.db.clone();
+        match tokio::task::spawn_blocking(move || db.view(f)).await {
+            Ok(result) => result,
+            Err(e) => match e.try_into_panic() {
+                Ok(panic) => resume_unwind(panic),
+                Err(_) => Err(Error::Cancelled("read-only transaction was cancelled").into()),
+            },
+        }
+    }
+
+    /// Returns a [`Stream`] over the data in the bucket at the given path.
+    ///
+    /// The path is the name of a root level bucket, followed by the names of any nested buckets.
+    /// The data is read from a [`ReadTx`](struct.ReadTx.html) snapshot on tokio's blocking thread pool,
+    /// a little ahead of the stream, and the snapshot is held until the stream is finished or dropped.
+    /// The database file can't be shrunk while a snapshot is open, so don't leave streams sitting around unfinished.
+    ///
+    /// If the bucket doesn't exist, or this isn't called from within a tokio runtime, the stream returns a single error.
+    /// Data that can't be read is returned as an error, the same as [`ReadBucket::iter`](struct.ReadBucket.html#method.iter).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::{AsyncDB, AsyncData, DB};
+    /// # use jammdb::Error;
+    ///
+    /// # async fn run() -> Result<(), Error> {
+    /// let db = AsyncDB::new(DB::open("my.db")?);
+    /// let mut cursor = db.cursor(["users"]);
+    /// while let Some(data) = cursor.next().await {
+    ///     if let AsyncData::KeyValue(key, value) = data? {
+    ///         println!("{:?} = {:?}", key, value);
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn cursor<I, T>(&self, bucket_path: I) -> AsyncCursor
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<[u8]>,
+    {
+        let path: Vec<Vec<u8>> = bucket_path
+            .into_iter()
+            .map(|name| name.as_ref().to_vec())
+            .collect();
+        let (sender, receiver) = mpsc::channel(CURSOR_BUFFER_SIZE);
+        let runtime = match Handle::try_current() {
+            Ok(runtime) => runtime,
+            Err(_) => {
+                let _ = sender.try_send(Err(Error::Cancelled("no tokio runtime")));
+                return AsyncCursor { receiver };
+            }
+        };
+        let db = self.db.clone();
+        runtime.spawn_blocking(move || {
+            let send = |data| sender.blocking_send(data).is_ok();
+            let tx = match db.read_tx() {
+                Ok(tx) => tx,
+                Err(e) => {
+                    send(Err(e));
+                    return;
+                }
+            };
+            let b = path
+                .split_first()
+                .map_or(Err(Error::BucketMissing), |(root, nested)| {
+                    nested
+                        .iter()
+                        .try_fold(tx.get_bucket(root)?, |b, name| b.get_bucket(name))
+                });
+            let b = match b {
+                Ok(b) => b,
+                Err(e) => {
+                    send(Err(e));
+                    return;
+                }
+            };
+            for data in b.iter() {
+                // stop once the stream has been dropped
+                if !send(data.map(AsyncData::from)) {
+                    return;
+                }
+            }
+        });
+        AsyncCursor { receiver }
+    }
+}
+
+/// Owned data returned by an [`AsyncCursor`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsyncData {
+    /// The name of a nested bucket.
+    Bucket(Vec<u8>),
+    /// A key / value pair.
+    KeyValue(Vec<u8>, Vec<u8>),
+}
+
+impl AsyncData {
+    /// Returns the key, or the name of the bucket.
+    pub fn key(&self) -> &[u8] {
+        match self {
+            AsyncData::Bucket(name) => name,
+            AsyncData::KeyValue(key, _) => key,
+        }
+    }
+}
+
+impl From<ReadData<'_>> for AsyncData {
+    fn from(data: ReadData) -> Self {
+        match data {
+            ReadData::Bucket(name) => AsyncData::Bucket(name.to_vec()),
+            ReadData::KeyValue(key, value) => AsyncData::KeyValue(key.to_vec(), value.into_owned()),
+        }
+    }
+}
+
+/// A [`Stream`] over the data in a bucket, created by [`AsyncDB::cursor`](struct.AsyncDB.html#method.cursor).
+pub struct AsyncCursor {
+    receiver: mpsc::Receiver<Result<AsyncData>>,
+}
+
+impl AsyncCursor {
+    /// Returns the next piece of data in the bucket, or `None` once there is no more data.
+    pub async fn next(&mut self) -> Option<Result<AsyncData>> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for AsyncCursor {
+    type Item = Result<AsyncData>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testutil::RandomFile, OpenOptions};
+
+    fn open(random_file: &RandomFile) -> Result<AsyncDB> {
+        let db = OpenOptions::new().pagesize(1024).open(random_file)?;
+        Ok(AsyncDB::new(db))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_update_view() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = open(&random_file)?;
+        let handles: Vec<_> = (0..10_u32)
+            .map(|i| {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    db.update(move |tx| {
+                        tx.get_or_create_bucket("abc")?
+                            .put(i.to_be_bytes(), i.to_string())?;
+                        Ok::<_, Error>(())
+                    })
+                    .await
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap()?;
+        }
+        let result = db
+            .update(|tx| {
+                tx.get_bucket("abc")?.put("rolled back", "value")?;
+                tx.create_bucket("abc")?;
+                Ok(())
+            })
+            .await;
+        assert_eq!(result, Err(Error::BucketExists));
+
+        let count = db
+            .view(|tx| Ok::<_, Error>(tx.get_bucket("abc")?.kv_pairs().count()))
+            .await?;
+        assert_eq!(count, 10);
+
+        // panics are passed along to the caller
+        let panic = {
+            let db = db.clone();
+            tokio::spawn(async move { db.update(|_| -> Result<()> { panic!("oh no") }).await })
+                .await
+        };
+        assert!(panic.unwrap_err().is_panic());
+        // and the transaction is rolled back without breaking the writer
+        db.update(|tx| tx.get_bucket("abc")?.put("after", "panic").map(|_| ()))
+            .await
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cursor() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = open(&random_file)?;
+        db.update(|tx| {
+            let b = tx.create_bucket("abc")?;
+            for i in 0..1000_u32 {
+                b.put(i.to_be_bytes(), i.to_string())?;
+            }
+            b.create_bucket("nested")?.put("key", "value")?;
+            Ok::<_, Error>(())
+        })
+        .await?;
+
+        let mut cursor = db.cursor(["abc"]);
+        let mut count = 0_u32;
+        while let Some(data) = cursor.next().await {
+            match data? {
+                AsyncData::KeyValue(key, value) => {
+                    assert_eq!(key, count.to_be_bytes());
+                    assert_eq!(value, count.to_string().as_bytes());
+                    count += 1;
+                }
+                AsyncData::Bucket(name) => assert_eq!(name, b"nested"),
+            }
+        }
+        assert_eq!(count, 1000);
+
+        let mut cursor = db.cursor(["abc", "nested"]);
+        assert_eq!(
+            cursor.next().await,
+            Some(Ok(AsyncData::KeyValue(b"key".to_vec(), b"value".to_vec())))
+        );
+        assert_eq!(cursor.next().await, None);
+
+        let mut cursor = db.cursor(["missing"]);
+        assert_eq!(cursor.next().await, Some(Err(Error::BucketMissing)));
+        assert_eq!(cursor.next().await, None);
+
+        // dropping a cursor early releases its snapshot
+        let mut cursor = db.cursor(["abc"]);
+        assert!(cursor.next().await.is_some());
+        drop(cursor);
+        db.update(|tx| {
+            let b = tx.get_bucket("abc")?;
+            for i in 0..10_000_u32 {
+                b.put(i.to_be_bytes(), vec![0; 1024])?;
+            }
+            Ok::<_, Error>(())
+        })
+        .await
+    }
+
+    #[test]
+    fn test_cursor_without_runtime() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = open(&random_file)?;
+        let mut cursor = db.cursor(["abc"]);
+        assert_eq!(
+            cursor.receiver.try_recv().ok(),
+            Some(Err(Error::Cancelled("no tokio runtime")))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Condvar, Mutex},
+    time::Duration,
+};
+
+use crate::{
+    db::DB,
+    errors::{Error, Result},
+    tx::Tx,
+};
+
+// Default maximum number of calls in a single batch
+pub(crate) const DEFAULT_MAX_BATCH_SIZE: usize = 1000;
+// Default amount of time to wait for more calls before starting a batch
+pub(crate) const DEFAULT_MAX_BATCH_DELAY: Duration = Duration::from_millis(10);
+
+type BatchFn = Box<dyn Fn(&Tx) -> Result<()> + Send>;
+
+struct Call {
+    f: BatchFn,
+    done: mpsc::Sender<Outcome>,
+}
+
+// What a caller gets back once their call has been run
+enum Outcome {
+    Done(Result<()>),
+    // The call failed, so the caller has to run it again in a transaction of its own.
+    // It runs on the caller's own thread so a panic only affects the caller.
+    Solo(BatchFn),
+}
+
+// Why a batch's shared transaction was rolled back
+enum Failure {
+    // the call at this index returned an error or panicked
+    Call(usize),
+    // the transaction couldn't be started or committed
+    Tx,
+}
+
+impl From<Error> for Failure {
+    fn from(_: Error) -> Failure {
+        Failure::Tx
+    }
+}
+
+#[derive(Default)]
+struct Pending {
+    calls: Vec<Call>,
+    // true while a caller is waiting to run the pending calls
+    has_leader: bool,
+}
+
+// Calls to DB::batch that are waiting to be run
+#[derive(Default)]
+pub(crate) struct BatchQueue {
+    pending: Mutex<Pending>,
+    full: Condvar,
+}
+
+impl DB {
+    /// Runs the closure in a writable transaction that may be shared with other concurrent calls to `batch`.
+    ///
+    /// Every commit has to wait for the data to be synced to disk, so when many threads write small
+    /// amounts of data at the same time most of their time is spent waiting on each other.
+    /// Calls to `batch` are queued up for a short time (see [`max_batch_delay`](struct.OpenOptions.html#method.max_batch_delay)
+    /// and [`max_batch_size`](struct.OpenOptions.html#method.max_batch_size)), and then run one after another
+    /// in a single transaction, so they share a single commit.
+    ///
+    /// If a closure returns an error or panics, the shared transaction is rolled back, the failed closure is run again
+    /// in a transaction of its own on the calling thread so it gets its own result (or its own panic),
+    /// and the rest of the batch is retried without it.
+    /// This means a closure may be called more than once, so it should only make changes through the transaction
+    /// it is given. The call only returns once the closure's changes have been committed, or have failed.
+    ///
+    /// Batching only helps when there are multiple threads calling `batch` at the same time,
+    /// a single thread should use [`update`](#method.update) or [`tx`](#method.tx) instead
+    /// since each call to `batch` waits for other calls to join it.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::DB;
+    /// # use jammdb::Error;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let db = DB::open("my.db")?;
+    /// let handles: Vec<_> = (0..10_u64)
+    ///     .map(|i| {
+    ///         let db = db.clone();
+    ///         std::thread::spawn(move || {
+    ///             db.batch(move |tx| {
+    ///                 tx.get_or_create_bucket("numbers")?.put(i.to_be_bytes(), i.to_string())?;
+    ///                 Ok(())
+    ///             })
+    ///         })
+    ///     })
+    ///     .collect();
+    /// for handle in handles {
+    ///     handle.join().unwrap()?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn batch<F>(&self, f: F) -> Result<()>
+    where
+        F: Fn(&Tx) -> Result<()> + Send + 'static,
+    {
+        let queue = &self.inner.batch;
+        let (done, result) = mpsc::channel();
+        let leader = {
+            let mut pending = queue.pending.lock()?;
+            pending.calls.push(Call {
+                f: Box::new(f),
+                done,
+            });
+            if pending.calls.len() >= self.inner.flags.max_batch_size {
+                queue.full.notify_one();
+            }
+            !std::mem::replace(&mut pending.has_leader, true)
+        };
+        // The first caller waits for the others to join, and then runs the whole batch.
+        if leader {
+            let calls = {
+                let pending = queue.pending.lock()?;
+                let (mut pending, _) = queue.full.wait_timeout_while(
+                    pending,
+                    self.inner.flags.max_batch_delay,
+                    |pending| pending.calls.len() < self.inner.flags.max_batch_size,
+                )?;
+                pending.has_leader = false;
+                std::mem::take(&mut pending.calls)
+            };
+            self.run_batch(calls);
+        }
+        match result.recv() {
+            Ok(Outcome::Done(result)) => result,
+            Ok(Outcome::Solo(f)) => self.update(|tx| f(tx)),
+            Err(_) => Err(Error::Sync("batch was not run")),
+        }
+    }
+
+    fn run_batch(&self, mut calls: Vec<Call>) {
+        while !calls.is_empty() {
+            let result = self.update(|tx| {
+                for (i, call) in calls.iter().enumerate() {
+                    // the transaction is rolled back either way, so a panic is just another failure
+                    match panic::catch_unwind(AssertUnwindSafe(|| (call.f)(tx))) {
+                        Ok(Ok(())) => (),
+                        _ => return Err(Failure::Call(i)),
+                    }
+                }
+                Ok(())
+            });
+            match result {
+                Ok(()) => {
+                    for call in calls.drain(..) {
+                        let _ = call.done.send(Outcome::Done(Ok(())));
+                    }
+                }
+                // the failed call runs on its own, then the rest are tried again
+                Err(Failure::Call(i)) => {
+                    let call = calls.remove(i);
+                    let _ = call.done.send(Outcome::Solo(call.f));
+                }
+                // every call runs on its own to get its own result
+                Err(Failure::Tx) => {
+                    for call in calls.drain(..) {
+                        let _ = call.done.send(Outcome::Solo(call.f));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Barrier};
+
+    use super::*;
+    use crate::{testutil::RandomFile, OpenOptions};
+
+    #[test]
+    fn test_batch() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new()
+            .pagesize(1024)
+            .max_batch_delay(Duration::from_millis(500))
+            .open(&random_file)?;
+        db.update(|tx| tx.create_bucket("abc").map(|_| ()))?;
+        let tx_id = || db.inner.meta().unwrap().tx_id;
+        let start_tx_id = tx_id();
+
+        let threads = 20_u64;
+        let barrier = Arc::new(Barrier::new(threads as usize));
+        let handles: Vec<_> = (0..threads)
+            .map(|i| {
+                let db = db.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    db.batch(move |tx| {
+                        let b = tx.get_bucket("abc")?;
+                        b.put(i.to_be_bytes(), i.to_string())?;
+                        if i % 5 == 0 {
+                            // fails after making changes, so they have to be rolled back
+                            tx.create_bucket("abc")?;
+                        }
+                        Ok(())
+                    })
+                })
+            })
+            .collect();
+        for (i, handle) in handles.into_iter().enumerate() {
+            let result = handle.join().unwrap();
+            if i % 5 == 0 {
+                assert_eq!(result, Err(Error::BucketExists));
+            } else {
+                assert_eq!(result, Ok(()));
+            }
+        }
+        // each failed call forces another attempt, but the successful calls still share a commit
+        assert!(tx_id() - start_tx_id < threads / 2);
+
+        let tx = db.tx(false)?;
+        let b = tx.get_bucket("abc")?;
+        for i in 0..threads {
+            let kv = b.get_kv(i.to_be_bytes());
+            if i % 5 == 0 {
+                assert!(kv.is_none());
+            } else {
+                assert_eq!(kv.unwrap().value(), i.to_string().as_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_panic() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new()
+            .pagesize(1024)
+            .max_batch_delay(Duration::from_millis(500))
+            .open(&random_file)?;
+        db.update(|tx| tx.create_bucket("abc").map(|_| ()))?;
+
+        let threads = 10_u64;
+        let barrier = Arc::new(Barrier::new(threads as usize));
+        let handles: Vec<_> = (0..threads)
+            .map(|i| {
+                let db = db.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    db.batch(move |tx| {
+                        tx.get_bucket("abc")?.put(i.to_be_bytes(), i.to_string())?;
+                        if i == 3 {
+                            panic!("oh no");
+                        }
+                        Ok(())
+                    })
+                })
+            })
+            .collect();
+        for (i, handle) in handles.into_iter().enumerate() {
+            let result = handle.join();
+            if i == 3 {
+                // the panic only reaches the thread that made the call
+                assert!(result.is_err());
+            } else {
+                assert_eq!(result.unwrap(), Ok(()));
+            }
+        }
+
+        db.batch(|tx| tx.get_bucket("abc")?.put("after", "panic").map(|_| ()))?;
+        let tx = db.tx(false)?;
+        let b = tx.get_bucket("abc")?;
+        for i in 0..threads {
+            assert_eq!(b.get_kv(i.to_be_bytes()).is_some(), i != 3);
+        }
+        assert!(b.get_kv("after").is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_batch_max_size() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new()
+            .pagesize(1024)
+            .max_batch_size(1)
+            // a batch with one call doesn't wait for others
+            .max_batch_delay(Duration::from_secs(60))
+            .open(&random_file)?;
+        db.batch(|tx| tx.create_bucket("abc").map(|_| ()))?;
+        assert_eq!(
+            db.batch(|tx| tx.create_bucket("abc").map(|_| ())),
+            Err(Error::BucketExists)
+        );
+        assert!(db.tx(false)?.get_bucket("abc").is_ok());
+        Ok(())
+    }
+}
@@ -3,13 +3,14 @@ use std::{
     collections::HashMap,
     marker::PhantomData,
     mem::{align_of, size_of},
-    ops::RangeBounds,
     rc::Rc,
 };
 
 use crate::{
     bytes::{Bytes, ToBytes},
-    cursor::{search, Cursor, Range, ToBuckets, ToKVPairs},
+    comparator::Comparator,
+    compression::Compression,
+    cursor::{search, Cursor, KeyRange, Prefix, Range, ToBuckets, ToKVPairs},
     data::{Data, KVPair},
     errors::{Error, Result},
     freelist::TxFreelist,
@@ -108,6 +109,11 @@ impl<'b, 'tx> Bucket<'b, 'tx> {
     /// # Ok(())
     /// # }
     /// ```
+    ///
+    /// # Errors
+    ///
+    /// The value is not replaced if the current one can't be decompressed, and that error is returned instead,
+    /// the same as [`try_get`](#method.try_get).
     pub fn put<'a, T: ToBytes<'tx>, S: ToBytes<'tx>>(
         &'a self,
         key: T,
@@ -123,22 +129,54 @@ impl<'b, 'tx> Bucket<'b, 'tx> {
         Ok(b.put(key, value)?.map(|v| v.into()))
     }
 
+    /// Gets the data for the given key, if it exists.
+    ///
+    /// Values that can't be read are returned as `None`, just like keys that don't exist.
+    /// Use [`try_get`](#method.try_get) to tell them apart.
     pub fn get<'a, T: AsRef<[u8]>>(&'a self, key: T) -> Option<Data<'b, 'tx>> {
+        self.try_get(key).ok().flatten()
+    }
+
+    /// Gets the data for the given key, returning an error if its value can't be read.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::{DB, Data};
+    /// # use jammdb::Error;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let db = DB::open("my.db")?;
+    /// let mut tx = db.tx(false)?;
+    /// let bucket = tx.get_bucket("my-bucket")?;
+    ///
+    /// if let Some(Data::KeyValue(kv)) = bucket.try_get("some-key")? {
+    ///     println!("{:?}", kv.value());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`Error::Codec`](enum.Error.html#variant.Codec) error if the value was compressed with a codec whose feature isn't enabled,
+    /// and an [`Error::InvalidDB`](enum.Error.html#variant.InvalidDB) error if it can't be decompressed.
+    /// Returns an [`Error::Corruption`](enum.Error.html#variant.Corruption) error if one of the bucket's pages doesn't match its checksum.
+    pub fn try_get<T: AsRef<[u8]>>(&self, key: T) -> Result<Option<Data<'b, 'tx>>> {
         let mut b = self.inner.borrow_mut();
         if b.deleted {
             panic!("Cannot get data from a deleted bucket.");
         }
-        b.get(key).map(|data| data.into())
+        b.get(key)?.map(Data::from_leaf).transpose()
     }
 
+    /// Gets the key / value pair for the given key, if it exists and isn't a bucket.
+    ///
+    /// Like [`get`](#method.get), values that can't be read are returned as `None`.
     pub fn get_kv<'a, T: AsRef<[u8]>>(&'a self, key: T) -> Option<KVPair<'b, 'tx>> {
-        let mut b = self.inner.borrow_mut();
-        if b.deleted {
-            panic!("Cannot get data from a deleted bucket.");
-        }
-        match b.get(key) {
-            Some(data) => data.into(),
-            None => None,
+        match self.get(key) {
+            Some(Data::KeyValue(kv)) => Some(kv),
+            _ => None,
         }
     }
 
@@ -165,6 +203,7 @@ impl<'b, 'tx> Bucket<'b, 'tx> {
     /// # Ok(())
     /// # }
     /// ```
+    #[allow(mismatched_lifetime_syntaxes)]
     pub fn delete<T: AsRef<[u8]>>(&self, key: T) -> Result<KVPair> {
         if !self.writable {
             return Err(Error::ReadOnlyTx);
@@ -209,7 +248,7 @@ impl<'b, 'tx> Bucket<'b, 'tx> {
         if b.deleted {
             panic!("Cannot get bucket from a deleted bucket.");
         }
-        let inner = b.get_bucket(name)?;
+        let inner = b.get_bucket(name, Some(Comparator::BYTEWISE))?;
         Ok(Bucket {
             inner,
             freelist: self.freelist.clone(),
@@ -254,7 +293,61 @@ impl<'b, 'tx> Bucket<'b, 'tx> {
         if b.deleted {
             panic!("Cannot create bucket in a deleted bucket.");
         }
-        let inner = b.create_bucket(name)?;
+        let inner = b.create_bucket(name, BucketOptions::default())?;
+        Ok(Bucket {
+            inner,
+            freelist: self.freelist.clone(),
+            writable: self.writable,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Gets an already created bucket, using the given options.
+    ///
+    /// Returns an error if
+    /// 1. the given key does not exist
+    /// 2. the key is for key / value data, not a bucket
+    /// 3. the bucket was created with a different [`Comparator`]
+    ///
+    /// See [`Comparator`] for an example.
+    pub fn get_bucket_with<'a, T: ToBytes<'tx>>(
+        &'a self,
+        name: T,
+        options: BucketOptions,
+    ) -> Result<Bucket<'b, 'tx>> {
+        let mut b = self.inner.borrow_mut();
+        if b.deleted {
+            panic!("Cannot get bucket from a deleted bucket.");
+        }
+        let inner = b.get_bucket(name, Some(options.comparator))?;
+        Ok(Bucket {
+            inner,
+            freelist: self.freelist.clone(),
+            writable: self.writable,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Creates a new bucket, using the given options.
+    ///
+    /// Returns an error if
+    /// 1. the given key already exists
+    /// 2. It is in a read-only transaction
+    ///
+    /// See [`Comparator`] for an example.
+    pub fn create_bucket_with<'a, T: ToBytes<'tx>>(
+        &'a self,
+        name: T,
+        options: BucketOptions,
+    ) -> Result<Bucket<'b, 'tx>> {
+        if !self.writable {
+            return Err(Error::ReadOnlyTx);
+        }
+        let mut b = self.inner.borrow_mut();
+        if b.deleted {
+            panic!("Cannot create bucket in a deleted bucket.");
+        }
+        let inner = b.create_bucket(name, options)?;
         Ok(Bucket {
             inner,
             freelist: self.freelist.clone(),
@@ -299,7 +392,7 @@ impl<'b, 'tx> Bucket<'b, 'tx> {
         if b.deleted {
             panic!("Cannot get or create bucket from a deleted bucket.");
         }
-        let inner = b.get_or_create_bucket(name)?;
+        let inner = b.get_or_create_bucket(name, BucketOptions::default())?;
         Ok(Bucket {
             inner,
             freelist: self.freelist.clone(),
@@ -427,18 +520,49 @@ impl<'b, 'tx> Bucket<'b, 'tx> {
     }
 
     /// Iterator over the sub-buckets in this bucket.
-    pub fn buckets<'a>(&'a self) -> impl Iterator<Item = (BucketName<'b, 'tx>, Bucket<'b, 'tx>)> {
+    pub fn buckets<'a>(
+        &'a self,
+    ) -> impl DoubleEndedIterator<Item = (BucketName<'b, 'tx>, Bucket<'b, 'tx>)> {
         self.cursor().to_buckets()
     }
 
     /// Iterator over the key / value pairs in this bucket.
-    pub fn kv_pairs<'a>(&'a self) -> impl Iterator<Item = KVPair<'b, 'tx>> {
+    pub fn kv_pairs<'a>(&'a self) -> impl DoubleEndedIterator<Item = KVPair<'b, 'tx>> {
         self.cursor().to_kv_pairs()
     }
 
-    pub fn range<'a, R>(&'a self, r: R) -> Range<'a, 'b, 'tx, R>
+    /// Iterator over the data in this bucket whose keys fall within the given range.
+    ///
+    /// The bounds can be any type that can be referenced as bytes (`&[u8]`, `Vec<u8>`, `String`, `[u8; 8]`, ...),
+    /// see [`KeyRange`] for the supported range types.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::ops::Bound;
+    /// use jammdb::DB;
+    /// # use jammdb::Error;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let db = DB::open("my.db")?;
+    /// let mut tx = db.tx(false)?;
+    /// let bucket = tx.get_bucket("my-bucket")?;
+    ///
+    /// // every key from "a" up to but not including "f"
+    /// for data in bucket.range("a".to_string().."f".to_string()) {
+    ///     println!("{:?}", data.key());
+    /// }
+    /// // every key after "a" (exclusive) up to and including "f", in reverse
+    /// let bounds = (Bound::Excluded(b"a".to_vec()), Bound::Included(b"f".to_vec()));
+    /// for data in bucket.range(bounds).rev() {
+    ///     println!("{:?}", data.key());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn range<'r, R>(&self, r: R) -> Range<'r, 'b, 'tx, R>
     where
-        R: RangeBounds<&'a [u8]>,
+        R: KeyRange,
     {
         Range {
             c: self.cursor(),
@@ -446,6 +570,37 @@ impl<'b, 'tx> Bucket<'b, 'tx> {
             _phantom: PhantomData,
         }
     }
+
+    /// Iterator over the data in this bucket whose keys start with the given prefix.
+    ///
+    /// If the bucket uses a custom [`Comparator`], it must sort every key that starts with the prefix
+    /// together, right after the prefix itself, or some of them will be missed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::DB;
+    /// # use jammdb::Error;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let db = DB::open("my.db")?;
+    /// let mut tx = db.tx(false)?;
+    /// let bucket = tx.get_bucket("my-bucket")?;
+    ///
+    /// // every key that starts with "user/1234/"
+    /// for data in bucket.prefix("user/1234/") {
+    ///     println!("{:?}", data.key());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Prefix<'b, 'tx, P> {
+        Prefix {
+            c: self.cursor(),
+            prefix,
+            done: false,
+        }
+    }
 }
 
 // and we'll implement IntoIterator
@@ -458,22 +613,62 @@ impl<'b, 'tx> IntoIterator for Bucket<'b, 'tx> {
     }
 }
 
+/// Options for creating or opening a [`Bucket`].
+///
+/// Used with [`create_bucket_with`](struct.Tx.html#method.create_bucket_with) and [`get_bucket_with`](struct.Tx.html#method.get_bucket_with).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BucketOptions {
+    /// The [`Comparator`] used to sort the bucket's keys.
+    pub comparator: Comparator,
+    /// The [`Compression`] used for the bucket's values.
+    pub compression: Compression,
+}
+
+impl BucketOptions {
+    /// Returns a new BucketOptions, with the default values.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the [`Comparator`] used to sort the bucket's keys.
+    ///
+    /// A bucket's comparator can't be changed after it is created.
+    pub fn comparator(mut self, comparator: Comparator) -> Self {
+        self.comparator = comparator;
+        self
+    }
+
+    /// Sets the [`Compression`] used for values put into the bucket.
+    ///
+    /// The compression is saved with the bucket when it is created, and opening an existing bucket
+    /// with a different compression has no effect.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+}
+
 pub(crate) struct InnerBucket<'b> {
     pub(crate) meta: BucketMeta,
+    pub(crate) comparator: Comparator,
     root: PageNodeID,
     pub(crate) deleted: bool,
-    dirty: bool,
+    pub(crate) dirty: bool,
     buckets: HashMap<Bytes<'b>, Rc<RefCell<InnerBucket<'b>>>>,
     pub(crate) nodes: Vec<Rc<RefCell<Node<'b>>>>,
     // Maps a PageID to it's NodeID, so we don't create multiple nodes for a single page
     page_node_ids: HashMap<PageID, NodeID>,
     // Maps PageIDs to their parent's PageID
     page_parents: HashMap<PageID, PageID>,
-    pages: Pages,
+    pub(crate) pages: Pages,
 }
 
 impl<'b> InnerBucket<'b> {
-    pub(crate) fn from_meta(meta: BucketMeta, pages: Pages) -> InnerBucket<'b> {
+    pub(crate) fn from_meta(
+        meta: BucketMeta,
+        comparator: Comparator,
+        pages: Pages,
+    ) -> InnerBucket<'b> {
         debug_assert!(
             meta.root_page > 1,
             "bucket cannot have root page {}, reserved for meta",
@@ -481,6 +676,7 @@ impl<'b> InnerBucket<'b> {
         );
         InnerBucket {
             meta,
+            comparator,
             root: PageNodeID::Page(meta.root_page),
             deleted: false,
             dirty: false,
@@ -492,13 +688,24 @@ impl<'b> InnerBucket<'b> {
         }
     }
 
-    fn new_child(&mut self, name: Bytes<'b>) -> RefMut<InnerBucket<'b>> {
+    #[allow(mismatched_lifetime_syntaxes)]
+    fn new_child(&mut self, name: Bytes<'b>, options: BucketOptions) -> RefMut<InnerBucket<'b>> {
         self.dirty = true;
-        let n = Node::new(0, Page::TYPE_LEAF, self.pages.pagesize);
+        let n = Node::new(
+            0,
+            Page::TYPE_LEAF,
+            self.pages.node_pagesize(),
+            options.comparator,
+        );
         let mut page_node_ids = HashMap::new();
         page_node_ids.insert(0, 0);
         let b = InnerBucket {
-            meta: BucketMeta::default(),
+            meta: BucketMeta {
+                comparator: options.comparator.id(),
+                compression: options.compression.flag() as u64,
+                ..BucketMeta::default()
+            },
+            comparator: options.comparator,
             root: PageNodeID::Node(0),
             deleted: false,
             dirty: true,
@@ -513,6 +720,13 @@ impl<'b> InnerBucket<'b> {
         b.borrow_mut()
     }
 
+    // The compression used for values put into the bucket
+    pub(crate) fn compression(&self) -> Result<Compression> {
+        let flag = self.meta.compression as u8;
+        Compression::from_flag(flag)
+            .ok_or_else(|| Error::InvalidDB(format!("Invalid compression flag {}", flag)))
+    }
+
     pub(crate) fn add_page_parent(&mut self, page: PageID, parent: PageID) {
         debug_assert!(
             self.meta.root_page == parent || self.page_parents.contains_key(&parent),
@@ -522,27 +736,30 @@ impl<'b> InnerBucket<'b> {
         self.page_parents.insert(page, parent);
     }
 
-    pub(crate) fn page_node<'a>(&'a self, id: PageNodeID) -> PageNode<'b> {
+    pub(crate) fn page_node<'a>(&'a self, id: PageNodeID) -> Result<PageNode<'b>> {
         match id {
             PageNodeID::Page(page) => {
                 if let Some(node_id) = self.page_node_ids.get(&page) {
-                    PageNode::Node(self.nodes[*node_id as usize].clone())
+                    Ok(PageNode::Node(self.nodes[*node_id as usize].clone()))
                 } else {
-                    PageNode::Page(self.pages.page(page))
+                    Ok(PageNode::Page(self.pages.page(page)?))
                 }
             }
-            PageNodeID::Node(node) => PageNode::Node(self.nodes[node as usize].clone()),
+            PageNodeID::Node(node) => Ok(PageNode::Node(self.nodes[node as usize].clone())),
         }
     }
 
-    pub fn get<'a, T: AsRef<[u8]>>(&'a mut self, key: T) -> Option<Leaf<'b>> {
-        let (exists, stack) = search(key.as_ref(), self.meta.root_page, self);
+    pub fn get<'a, T: AsRef<[u8]>>(&'a mut self, key: T) -> Result<Option<Leaf<'b>>> {
+        if !self.comparator.is_known() {
+            return Ok(None);
+        }
+        let (exists, stack) = search(key.as_ref(), self.meta.root_page, self)?;
         let last = stack.last().unwrap();
         if exists {
-            let page_node = self.page_node(last.id);
-            page_node.val(last.index)
+            let page_node = self.page_node(last.id)?;
+            Ok(page_node.val(last.index))
         } else {
-            None
+            Ok(None)
         }
     }
 
@@ -552,11 +769,11 @@ impl<'b> InnerBucket<'b> {
         value: S,
     ) -> Result<Option<(Bytes<'b>, Bytes<'b>)>> {
         let k = key.to_bytes();
-        let v = value.to_bytes();
+        let (v, c) = self.compression()?.compress(value.to_bytes())?;
 
-        match self.put_leaf(Leaf::Kv(k, v))? {
+        match self.put_leaf(Leaf::Kv(k, v, c))? {
             Some(data) => match data {
-                Leaf::Kv(k, v) => Ok(Some((k, v))),
+                Leaf::Kv(k, v, _) => Ok(Some((k, v))),
                 _ => panic!("Unexpected data"),
             },
             None => Ok(None),
@@ -564,23 +781,26 @@ impl<'b> InnerBucket<'b> {
     }
 
     fn delete<'a, T: AsRef<[u8]>>(&'a mut self, key: T) -> Result<(Bytes<'b>, Bytes<'b>)> {
-        let (exists, stack) = search(key.as_ref(), self.meta.root_page, self);
+        if !self.comparator.is_known() {
+            return Err(Error::ComparatorRequired);
+        }
+        let (exists, stack) = search(key.as_ref(), self.meta.root_page, self)?;
         let last = stack.last().unwrap();
         if exists {
-            let page_node = self.page_node(last.id);
-            let data = page_node.val(last.index).unwrap();
-            if data.is_kv() {
-                let current_id = last.id;
-                let index = last.index;
-                self.dirty = true;
-                let node = self.node(current_id, None);
-                let mut node = node.borrow_mut();
-                match node.delete(index) {
-                    Leaf::Kv(k, v) => Ok((k, v)),
-                    _ => panic!("Unexpected data"),
+            let page_node = self.page_node(last.id)?;
+            match page_node.val(last.index).unwrap() {
+                Leaf::Kv(k, v, c) => {
+                    // make sure the value can be returned before deleting it
+                    let v = c.decompress(v)?;
+                    let current_id = last.id;
+                    let index = last.index;
+                    self.dirty = true;
+                    let node = self.node(current_id, None)?;
+                    let mut node = node.borrow_mut();
+                    node.delete(index);
+                    Ok((k, v))
                 }
-            } else {
-                Err(Error::IncompatibleValue)
+                _ => Err(Error::IncompatibleValue),
             }
         } else {
             Err(Error::KeyValueMissing)
@@ -588,20 +808,27 @@ impl<'b> InnerBucket<'b> {
     }
 
     fn put_leaf<'a>(&'a mut self, leaf: Leaf<'b>) -> Result<Option<Leaf<'b>>> {
-        let (exists, stack) = search(leaf.key(), self.meta.root_page, self);
+        if !self.comparator.is_known() {
+            return Err(Error::ComparatorRequired);
+        }
+        let (exists, stack) = search(leaf.key(), self.meta.root_page, self)?;
         let last = stack.last().unwrap();
         let current_data = if exists {
-            let page_node = self.page_node(last.id);
+            let page_node = self.page_node(last.id)?;
             let current = page_node.val(last.index).unwrap();
             if current.is_kv() != leaf.is_kv() {
                 return Err(Error::IncompatibleValue);
             }
-            Some(current)
+            // make sure the old value can be returned before replacing it
+            match current {
+                Leaf::Kv(k, v, c) => Some(Leaf::Kv(k, c.decompress(v)?, Compression::None)),
+                current => Some(current),
+            }
         } else {
             self.meta.next_int += 1;
             None
         };
-        let node = self.node(last.id, None);
+        let node = self.node(last.id, None)?;
         let mut node = node.borrow_mut();
         node.insert_data(leaf);
         self.dirty = true;
@@ -609,56 +836,104 @@ impl<'b> InnerBucket<'b> {
         Ok(current_data)
     }
 
-    pub(crate) fn create_bucket<T: ToBytes<'b>>(&mut self, name: T) -> Result<Rc<RefCell<Self>>> {
-        self.bucket_getter(name.to_bytes(), true, true)
+    pub(crate) fn create_bucket<T: ToBytes<'b>>(
+        &mut self,
+        name: T,
+        options: BucketOptions,
+    ) -> Result<Rc<RefCell<Self>>> {
+        self.bucket_getter(name.to_bytes(), Some(options), true, true)
     }
 
+    // Gets a bucket, making sure it was created with the given comparator.
+    // Without a comparator, the bucket is opened with whatever comparator it was created with,
+    // which can only be used for iterating over a bucket with a custom comparator.
     pub(crate) fn get_bucket<'a, T: ToBytes<'b>>(
         &'a mut self,
         name: T,
+        comparator: Option<Comparator>,
     ) -> Result<Rc<RefCell<Self>>> {
-        self.bucket_getter(name.to_bytes(), false, false)
+        let options = comparator.map(|c| BucketOptions::new().comparator(c));
+        self.bucket_getter(name.to_bytes(), options, false, false)
+    }
+
+    // Opens a nested bucket that was found by iterating over this one, so it doesn't have to be searched for.
+    pub(crate) fn open_bucket(&mut self, name: Bytes<'b>, meta: BucketMeta) -> Rc<RefCell<Self>> {
+        let pages = self.pages.clone();
+        self.buckets
+            .entry(name)
+            .or_insert_with(|| {
+                let comparator = Comparator::for_id(meta.comparator);
+                Rc::new(RefCell::new(Self::from_meta(meta, comparator, pages)))
+            })
+            .clone()
     }
 
     pub(crate) fn get_or_create_bucket<T: ToBytes<'b>>(
         &mut self,
         name: T,
+        options: BucketOptions,
     ) -> Result<Rc<RefCell<Self>>> {
-        self.bucket_getter(name.to_bytes(), true, false)
+        self.bucket_getter(name.to_bytes(), Some(options), true, false)
     }
 
+    // The options are only used to create a new bucket, except for the comparator which is always checked.
     fn bucket_getter<'a>(
         &'a mut self,
         name: Bytes<'b>,
+        options: Option<BucketOptions>,
         should_create: bool,
         must_create: bool,
     ) -> Result<Rc<RefCell<InnerBucket<'b>>>> {
-        if !self.buckets.contains_key(&name) {
-            let (exists, stack) = search(name.as_ref(), self.meta.root_page, self);
+        let comparator = options.map(|o| o.comparator);
+        if let Some(b) = self.buckets.get(&name) {
+            if must_create {
+                return Err(Error::BucketExists);
+            }
+            if let Some(comparator) = comparator {
+                let mut b = b.borrow_mut();
+                if b.meta.comparator != comparator.id() {
+                    return Err(Error::ComparatorMismatch(comparator.name()));
+                }
+                // the bucket may have been opened without its comparator
+                b.comparator = comparator;
+            }
+        } else {
+            if !self.comparator.is_known() {
+                return Err(Error::ComparatorRequired);
+            }
+            let (exists, stack) = search(name.as_ref(), self.meta.root_page, self)?;
             let last = stack.last().unwrap();
             if !exists {
                 if should_create {
+                    options.unwrap_or_default().compression.check_enabled()?;
+                    let node = self.node(last.id, None)?;
                     self.meta.next_int += 1;
                     let leaf = {
-                        let b = self.new_child(name.clone());
+                        let b = self.new_child(name.clone(), options.unwrap_or_default());
                         let meta = b.meta;
                         Leaf::Bucket(name.clone(), meta)
                     };
-                    let node = self.node(last.id, None);
                     let mut node = node.borrow_mut();
                     node.insert_data(leaf);
                 } else {
                     return Err(Error::BucketMissing);
                 }
             } else {
-                let page_node = self.page_node(last.id);
+                let page_node = self.page_node(last.id)?;
                 match page_node.val(last.index) {
                     Some(leaf) => match leaf {
                         Leaf::Bucket(name, meta) => {
                             if must_create {
                                 return Err(Error::BucketExists);
                             }
-                            let b = Self::from_meta(meta, self.pages.clone());
+                            let comparator = match comparator {
+                                Some(c) if c.id() != meta.comparator => {
+                                    return Err(Error::ComparatorMismatch(c.name()))
+                                }
+                                Some(c) => c,
+                                None => Comparator::for_id(meta.comparator),
+                            };
+                            let b = Self::from_meta(meta, comparator, self.pages.clone());
                             self.buckets.insert(name.clone(), Rc::new(RefCell::new(b)));
                         }
                         _ => return Err(Error::IncompatibleValue),
@@ -666,8 +941,6 @@ impl<'b> InnerBucket<'b> {
                     None => return Err(Error::BucketMissing),
                 }
             }
-        } else if must_create {
-            return Err(Error::BucketExists);
         }
         Ok(self.buckets.get(&name).unwrap().clone())
     }
@@ -678,23 +951,22 @@ impl<'b> InnerBucket<'b> {
         freelist: &mut TxFreelist,
     ) -> Result<()> {
         let name = name.to_bytes();
+        if !self.comparator.is_known() {
+            return Err(Error::ComparatorRequired);
+        }
         // make sure the bucket is in our map
-        self.get_bucket(&name)?;
+        self.get_bucket(&name, None)?;
 
-        // remove the bucket from the map so we won't have a reference to it anymore
-        let bucket = self.buckets.remove(&name).unwrap();
-        let mut b = bucket.borrow_mut();
-        // Mark it as deleted in case there is still a Bucket or cursor with a reference to this bucket.
-        b.deleted = true;
+        // read every page we need before changing anything, so an unreadable page leaves the bucket intact
         // check that the bucket wasn't just created and never comitted
-        let mut remaining_pages = Vec::new();
-        if b.meta.root_page != 0 {
+        let root_page = self.buckets[&name].borrow().meta.root_page;
+        let mut freed_pages = Vec::new();
+        if root_page != 0 {
             // create a stack of pages to free and keep going until
-            // we've freed every reachable page starting from this bucket's root page
-            remaining_pages.push(b.meta.root_page);
+            // we've found every reachable page starting from this bucket's root page
+            let mut remaining_pages = vec![root_page];
             while let Some(page_id) = remaining_pages.pop() {
-                let page = self.pages.page(page_id);
-                let num_pages = page.overflow + 1;
+                let page = self.pages.page(page_id)?;
                 match page.page_type {
                     // every branch element's page much be freed
                     Page::TYPE_BRANCH => {
@@ -713,51 +985,53 @@ impl<'b> InnerBucket<'b> {
                     }
                     _ => (),
                 }
-                freelist.free(page_id, num_pages);
+                freed_pages.push((page_id, page.overflow + 1));
             }
         }
-        // delete the element from this bucket
-        let (exists, stack) = search(name.as_ref(), self.meta.root_page, self);
+        // find the element in this bucket
+        let (exists, stack) = search(name.as_ref(), self.meta.root_page, self)?;
         let last = stack.last().unwrap();
-        if exists {
-            let page_node = self.page_node(last.id);
-            let data = page_node.val(last.index).unwrap();
-
-            if !data.is_kv() {
-                self.dirty = true;
-                let current_id = last.id;
-                let index = last.index;
-                let node = self.node(current_id, None);
-                let mut node = node.borrow_mut();
-                node.delete(index);
-                Ok(())
-            } else {
-                Err(Error::IncompatibleValue)
-            }
-        } else {
-            panic!("Did not find data for bucket we already deleted")
+        if !exists {
+            panic!("Did not find data for bucket we already found")
+        }
+        if self.page_node(last.id)?.val(last.index).unwrap().is_kv() {
+            return Err(Error::IncompatibleValue);
+        }
+        let node = self.node(last.id, None)?;
+
+        // remove the bucket from the map so we won't have a reference to it anymore
+        let bucket = self.buckets.remove(&name).unwrap();
+        // Mark it as deleted in case there is still a Bucket or cursor with a reference to this bucket.
+        bucket.borrow_mut().deleted = true;
+        for (page_id, num_pages) in freed_pages {
+            freelist.free(page_id, num_pages);
         }
+        // delete the element from this bucket
+        self.dirty = true;
+        node.borrow_mut().delete(last.index);
+        Ok(())
     }
 
     pub(crate) fn node<'a>(
         &'a mut self,
         id: PageNodeID,
         parent: Option<&mut Node>,
-    ) -> Rc<RefCell<Node<'b>>> {
+    ) -> Result<Rc<RefCell<Node<'b>>>> {
         let id: NodeID = match id {
             PageNodeID::Page(page_id) => {
                 if let Some(node_id) = self.page_node_ids.get(&page_id) {
-                    return self.nodes[*node_id as usize].clone();
+                    return Ok(self.nodes[*node_id as usize].clone());
                 }
                 debug_assert!(
                     self.meta.root_page == page_id || self.page_parents.contains_key(&page_id),
                     "cannot find reference to page ID \"{}\"",
                     page_id,
                 );
+                let page = self.pages.page(page_id)?;
                 let node_id = self.nodes.len() as u64;
                 self.page_node_ids.insert(page_id, node_id);
                 let n: Node =
-                    Node::from_page(node_id, self.pages.page(page_id), self.pages.pagesize);
+                    Node::from_page(node_id, page, self.pages.node_pagesize(), self.comparator);
                 self.nodes.push(Rc::new(RefCell::new(n)));
                 // If this node is not for the root page, then recursively create nodes for the parent pages
                 if self.meta.root_page != page_id {
@@ -768,7 +1042,8 @@ impl<'b> InnerBucket<'b> {
                         parent.insert_child(node_id, node_key);
                         n.parent = Some(parent.id);
                     } else {
-                        let parent = self.node(PageNodeID::Page(self.page_parents[&page_id]), None);
+                        let parent =
+                            self.node(PageNodeID::Page(self.page_parents[&page_id]), None)?;
                         let mut parent = parent.borrow_mut();
                         parent.insert_child(node_id, node_key);
                         n.parent = Some(parent.id);
@@ -778,13 +1053,13 @@ impl<'b> InnerBucket<'b> {
             }
             PageNodeID::Node(id) => id,
         };
-        self.nodes.get_mut(id as usize).unwrap().clone()
+        Ok(self.nodes.get_mut(id as usize).unwrap().clone())
     }
 
     pub(crate) fn new_node<'a>(&'a mut self, data: NodeData<'b>) -> Rc<RefCell<Node<'b>>> {
         debug_assert!(data.len() >= 2);
         let node_id = self.nodes.len() as u64;
-        let n = Node::with_data(node_id, data, self.pages.pagesize);
+        let n = Node::with_data(node_id, data, self.pages.node_pagesize(), self.comparator);
         self.nodes.push(Rc::new(RefCell::new(n)));
         self.nodes[node_id as usize].clone()
     }
@@ -815,16 +1090,14 @@ impl<'b> InnerBucket<'b> {
         }
 
         // merge emptyish nodes with siblings
-        self.merge_nodes(tx_freelist);
-
-        Ok(())
+        self.merge_nodes(tx_freelist)
     }
 
-    fn merge_nodes(&mut self, tx_freelist: &mut TxFreelist) {
+    fn merge_nodes(&mut self, tx_freelist: &mut TxFreelist) -> Result<()> {
         // If we haven't initialized any nodes yet, make sure we have the root node.
         // If there is even one node, we are guarunteed to hage loaded the root node too.
         if self.page_node_ids.is_empty() {
-            self.node(PageNodeID::Page(self.meta.root_page), None);
+            self.node(PageNodeID::Page(self.meta.root_page), None)?;
         }
         let mut stack: Vec<(bool, u64)> = vec![(false, self.page_node_ids[&self.meta.root_page])];
 
@@ -876,10 +1149,10 @@ impl<'b> InnerBucket<'b> {
                         }
                         // check if there is any data left to copy
                         // find the child's branch element in the parent node's data
-                        let index = match branches.binary_search_by_key(
-                            &node.original_key.clone().unwrap().as_ref(),
-                            |b| b.key(),
-                        ) {
+                        let original_key = node.original_key.clone().unwrap();
+                        let index = match branches.binary_search_by(|b| {
+                            self.comparator.compare(b.key(), original_key.as_ref())
+                        }) {
                             Ok(i) => i,
                             _ => panic!("child branch not found"),
                         };
@@ -895,11 +1168,11 @@ impl<'b> InnerBucket<'b> {
 
                             self.page_parents.insert(sibling_page, parent.page_id);
                             let sibling =
-                                self.node(PageNodeID::Page(sibling_page), Some(&mut parent));
+                                self.node(PageNodeID::Page(sibling_page), Some(&mut parent))?;
 
                             let mut sibling = sibling.borrow_mut();
                             // Copy this node's data over to it's sibling
-                            sibling.data.merge(&mut node.data);
+                            sibling.data.merge(&mut node.data, self.comparator);
                             if !node.children.is_empty() {
                                 // Move all children nodes over to that sibling too
                                 for child in node.children.iter() {
@@ -932,6 +1205,7 @@ impl<'b> InnerBucket<'b> {
                 }
             }
         }
+        Ok(())
     }
 
     // Make sure none of the nodes are too full, creating other nodes as needed.
@@ -965,20 +1239,32 @@ impl<'b> InnerBucket<'b> {
     }
 }
 
-pub const META_SIZE: usize = std::mem::size_of::<BucketMeta>();
+// Buckets using the default comparator and no compression only store the root page and next int,
+// so files written by older versions can still be read, and vice versa.
+pub(crate) const SHORT_META_SIZE: usize = 16;
+pub(crate) const META_SIZE: usize = size_of::<BucketMeta>();
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub(crate) struct BucketMeta {
     pub(crate) root_page: PageID,
     pub(crate) next_int: u64,
+    // id of the bucket's comparator, or 0 for the default bytewise comparator
+    pub(crate) comparator: u64,
+    // the compression flag for values put into the bucket
+    pub(crate) compression: u64,
 }
 
 impl AsRef<[u8]> for BucketMeta {
     #[inline]
     fn as_ref(&self) -> &[u8] {
         let ptr = self as *const BucketMeta as *const u8;
-        unsafe { std::slice::from_raw_parts(ptr, META_SIZE) }
+        let size = if self.comparator == 0 && self.compression == 0 {
+            SHORT_META_SIZE
+        } else {
+            META_SIZE
+        };
+        unsafe { std::slice::from_raw_parts(ptr, size) }
     }
 }
 
@@ -989,14 +1275,13 @@ impl From<&[u8]> for BucketMeta {
     // that is aligned property, copy the data from value over,
     // and cast our BucketMeta from there.
     fn from(value: &[u8]) -> Self {
-        const SIZE: usize = size_of::<BucketMeta>();
         const ALIGN: usize = align_of::<BucketMeta>();
-        debug_assert_eq!(SIZE, value.len());
-        let mut buf = [0_u8; SIZE + ALIGN];
+        debug_assert!(value.len() == SHORT_META_SIZE || value.len() == META_SIZE);
+        let mut buf = [0_u8; META_SIZE + ALIGN];
         let ptr = buf.as_mut_ptr();
         unsafe {
             let ptr = ptr.add(ptr.align_offset(ALIGN));
-            std::ptr::copy(value.as_ptr(), ptr, SIZE);
+            std::ptr::copy(value.as_ptr(), ptr, value.len().min(META_SIZE));
             *(ptr as *const BucketMeta)
         }
     }
@@ -1005,17 +1290,32 @@ impl From<&[u8]> for BucketMeta {
 #[cfg(test)]
 mod tests {
 
+    use std::ops::Bound;
+
     use super::*;
-    use crate::{testutil::RandomFile, DB};
+    use crate::{testutil::RandomFile, OpenOptions, DB};
 
     #[test]
     fn bytes() {
-        let meta = BucketMeta {
+        let mut meta = BucketMeta {
             root_page: 3,
             next_int: 1,
+            comparator: 0,
+            compression: 0,
         };
         let bytes = meta.as_ref();
         assert_eq!(bytes, &[3, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(BucketMeta::from(bytes), meta);
+        meta.comparator = 2;
+        let bytes = meta.as_ref();
+        assert_eq!(
+            bytes,
+            &[
+                3, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0, 0, 0, 0
+            ]
+        );
+        assert_eq!(BucketMeta::from(bytes), meta);
     }
 
     macro_rules! deleted_bucket_test {
@@ -1187,6 +1487,14 @@ mod tests {
                     assert!(data.unwrap().key() == k);
                 }
                 assert!(bucket_iter.next().is_none());
+                // the same range iterated in reverse
+                let mut bucket_iter = b.range($range).rev();
+                for k in $keys.iter().rev() {
+                    let data = bucket_iter.next();
+                    assert!(data.is_some());
+                    assert!(data.unwrap().key() == k.as_bytes());
+                }
+                assert!(bucket_iter.next().is_none());
             };
         }
         let a = "a".as_bytes();
@@ -1208,6 +1516,93 @@ mod tests {
         iter_test!(..e, ["a", "b", "c", "d"]);
         iter_test!(.., ["a", "b", "c", "d", "e", "f"]);
 
+        // excluded start bounds, both on and between existing keys
+        iter_test!(
+            (Bound::Excluded(b.to_vec()), Bound::Unbounded),
+            ["c", "d", "e", "f"]
+        );
+        iter_test!(
+            (Bound::Excluded(aa.to_vec()), Bound::Excluded(e.to_vec())),
+            ["b", "c", "d"]
+        );
+        iter_test!(
+            (Bound::Excluded(a.to_vec()), Bound::Included(e.to_vec())),
+            ["b", "c", "d", "e"]
+        );
+        iter_test!(
+            (Bound::Excluded(d.to_vec()), Bound::Excluded(e.to_vec())),
+            [] as [&str; 0]
+        );
+        iter_test!(
+            (Bound::Excluded(e.to_vec()), Bound::Excluded(d.to_vec())),
+            [] as [&str; 0]
+        );
+        iter_test!(e..d, [] as [&str; 0]);
+        iter_test!(d..d, [] as [&str; 0]);
+        iter_test!(d..=d, ["d"]);
+
+        // owned and fixed size bounds
+        iter_test!("b".to_string().."e".to_string(), ["b", "c", "d"]);
+        iter_test!(b"aa".to_vec()..=b"e".to_vec(), ["b", "c", "d", "e"]);
+        iter_test!(*b"c".., ["c", "d", "e", "f"]);
+        iter_test!(
+            (
+                Bound::Excluded("a".to_string()),
+                Bound::Included("zzz".to_string())
+            ),
+            ["b", "c", "d", "e", "f"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefix() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket("abc")?;
+            for user in 0..50 {
+                for item in 0..20 {
+                    b.put(format!("user/{:03}/{:03}", user, item), "value")?;
+                }
+            }
+            b.put([0x01, 0xFE], "a")?;
+            b.put([0x01, 0xFF], "b")?;
+            b.put([0x01, 0xFF, 0x00], "c")?;
+            b.put([0x01, 0xFF, 0xFF], "d")?;
+            b.put([0x02], "e")?;
+            tx.commit()?;
+        }
+        let tx = db.tx(false)?;
+        let b = tx.get_bucket("abc")?;
+
+        let keys: Vec<Vec<u8>> = b.prefix("user/025/").map(|d| d.key().to_vec()).collect();
+        let expected: Vec<Vec<u8>> = (0..20)
+            .map(|item| format!("user/025/{:03}", item).into_bytes())
+            .collect();
+        assert_eq!(keys, expected);
+        assert_eq!(b.prefix("user/").count(), 1000);
+        assert_eq!(b.prefix("user/0").to_kv_pairs().count(), 1000);
+        assert_eq!(b.prefix("user/1").count(), 0);
+        assert_eq!(b.prefix("zzz").count(), 0);
+        assert_eq!(b.prefix([]).count(), 1005);
+
+        // prefixes ending in 0xFF
+        let values: Vec<Vec<u8>> = b
+            .prefix([0x01, 0xFF])
+            .to_kv_pairs()
+            .map(|kv| kv.value().to_vec())
+            .collect();
+        assert_eq!(values, vec![b"b".to_vec(), b"c".to_vec(), b"d".to_vec()]);
+        let values: Vec<Vec<u8>> = b
+            .prefix([0x01])
+            .to_kv_pairs()
+            .map(|kv| kv.value().to_vec())
+            .collect();
+        assert_eq!(values.len(), 4);
+        assert_eq!(b.prefix([0x01, 0xFF, 0xFF]).count(), 1);
         Ok(())
     }
 }
@@ -0,0 +1,362 @@
+use std::{
+    collections::HashMap,
+    fs::OpenOptions as FileOpenOptions,
+    io::{Error as IOError, ErrorKind},
+    path::Path,
+};
+
+use crate::{
+    bucket::{Bucket, BucketOptions},
+    comparator::Comparator,
+    data::Data,
+    db::{OpenOptions, DB},
+    errors::{Error, Result},
+    tx::Tx,
+};
+
+// Number of bytes to copy into the new database before committing
+#[cfg(not(test))]
+const MAX_TX_SIZE: u64 = 64 * 1024 * 1024;
+#[cfg(test)]
+const MAX_TX_SIZE: u64 = 16 * 1024;
+
+/// The result of compacting a database.
+///
+/// Returned by [`DB::compact_into`](struct.DB.html#method.compact_into).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactReport {
+    /// Size of the original database file in bytes.
+    pub src_size: u64,
+    /// Size of the compacted database file in bytes.
+    pub dst_size: u64,
+}
+
+impl DB {
+    /// Copies every bucket and key / value pair into a new, densely packed database file.
+    ///
+    /// The database file never shrinks on its own, so after deleting a lot of data
+    /// you can use this to reclaim the unused space. The new file is created at `path`
+    /// using the given [`OpenOptions`], and must not already exist.
+    /// The copy is made from a read-only transaction, so writes to this database
+    /// can continue while it runs, but they won't be included in the new file.
+    ///
+    /// Compressed buckets are compressed the same way in the new file.
+    /// Buckets created with a custom [`Comparator`](struct.Comparator.html) need it to be copied,
+    /// so use [`compact_into_with`](#method.compact_into_with) if there are any.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::{DB, OpenOptions};
+    /// # use jammdb::Error;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let db = DB::open("my.db")?;
+    /// let report = db.compact_into("my-compacted.db", OpenOptions::new())?;
+    /// println!("{} bytes -> {} bytes", report.src_size, report.dst_size);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn compact_into<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: OpenOptions,
+    ) -> Result<CompactReport> {
+        self.compact_into_with(path, options, &[])
+    }
+
+    /// Like [`compact_into`](#method.compact_into), but for databases with buckets that use custom comparators.
+    ///
+    /// Each bucket is copied using the comparator it was created with, which must be one of the given `comparators`,
+    /// otherwise a [`ComparatorRequired`](enum.Error.html#variant.ComparatorRequired) error is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::{Comparator, DB, OpenOptions};
+    /// # use jammdb::Error;
+    ///
+    /// const REVERSE: Comparator = Comparator::new("reverse", |a, b| b.cmp(a));
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let db = DB::open("my.db")?;
+    /// db.compact_into_with("my-compacted.db", OpenOptions::new(), &[REVERSE])?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn compact_into_with<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: OpenOptions,
+        comparators: &[Comparator],
+    ) -> Result<CompactReport> {
+        let path = path.as_ref();
+        if path.exists() {
+            return Err(IOError::new(
+                ErrorKind::AlreadyExists,
+                format!("cannot compact into existing file {:?}", path),
+            )
+            .into());
+        }
+        let src_size = self.inner.data.lock()?.len() as u64;
+        let (num_pages, pagesize) = {
+            let dst = options.open(path)?;
+            let src_tx = self.tx(false)?;
+            let mut c = Compactor::new(&dst);
+            c.known_comparators = comparators;
+            let mut bucket_path = Vec::new();
+            // iterating stops at a page that can't be read, so walk the root bucket
+            // with try_next first to return the error instead of leaving buckets out
+            let root = src_tx.root();
+            let mut cursor = root.cursor();
+            while let Some(data) = cursor.try_next() {
+                data?;
+            }
+            for (name, b) in root.buckets() {
+                bucket_path.push(name.name().to_vec());
+                c.copy_bucket(&b, &mut bucket_path)?;
+                bucket_path.pop();
+            }
+            let root_next_int = src_tx.inner.borrow().root.borrow().meta.next_int;
+            c.set_next_int(&[], root_next_int)?;
+            c.commit()?;
+            let tx = dst.tx(false)?;
+            let num_pages = tx.inner.borrow().meta.num_pages;
+            (num_pages, dst.pagesize())
+        };
+        // The file grows in large chunks, so trim off everything past the last page.
+        let dst_size = num_pages * pagesize;
+        let file = FileOpenOptions::new().write(true).open(path)?;
+        file.set_len(dst_size)?;
+        file.sync_all()?;
+        Ok(CompactReport { src_size, dst_size })
+    }
+}
+
+// Copies data into a new database, committing every MAX_TX_SIZE bytes
+pub(crate) struct Compactor<'a> {
+    db: &'a DB,
+    tx: Option<Tx<'a>>,
+    size: u64,
+    // comparators that source buckets may have been created with
+    known_comparators: &'a [Comparator],
+    // comparators of the buckets created in the new database, by path
+    comparators: HashMap<Vec<Vec<u8>>, Comparator>,
+}
+
+impl<'a> Compactor<'a> {
+    pub(crate) fn new(db: &'a DB) -> Compactor<'a> {
+        Compactor {
+            db,
+            tx: None,
+            size: 0,
+            known_comparators: &[],
+            comparators: HashMap::new(),
+        }
+    }
+
+    fn tx(&mut self) -> Result<&Tx<'a>> {
+        if self.tx.is_none() {
+            self.tx = Some(self.db.tx(true)?);
+        }
+        Ok(self.tx.as_ref().unwrap())
+    }
+
+    pub(crate) fn commit(&mut self) -> Result<()> {
+        if let Some(tx) = self.tx.take() {
+            tx.commit()?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+
+    // copies the contents of the given bucket into the bucket at the same path in the new database
+    fn copy_bucket(&mut self, src: &Bucket, path: &mut Vec<Vec<u8>>) -> Result<()> {
+        let options = {
+            let mut inner = src.inner.borrow_mut();
+            // buckets found by iterating are opened without their comparator, so look it up
+            let id = inner.meta.comparator;
+            let comparator = if id == 0 {
+                Comparator::BYTEWISE
+            } else {
+                match self.known_comparators.iter().find(|c| c.id() == id) {
+                    Some(c) => *c,
+                    None => return Err(Error::ComparatorRequired),
+                }
+            };
+            inner.comparator = comparator;
+            BucketOptions::new()
+                .comparator(comparator)
+                .compression(inner.compression()?)
+        };
+        self.bucket(path, Some(options))?;
+        // values that can't be decompressed aren't skipped, so nothing is lost without an error
+        let mut cursor = src.cursor();
+        while let Some(data) = cursor.try_next() {
+            if let Data::KeyValue(kv) = data? {
+                let (k, v) = kv.kv();
+                self.put(path, k, v)?;
+            }
+        }
+        // the loop above read every page in the bucket, so this doesn't skip anything
+        for (name, b) in src.buckets() {
+            path.push(name.name().to_vec());
+            self.copy_bucket(&b, path)?;
+            path.pop();
+        }
+        // putting data increments next_int, so overwrite it once everything has been copied
+        self.set_next_int(path, src.next_int())
+    }
+
+    // puts the key / value pair into the bucket at the given path,
+    // committing the transaction if it has gotten too big
+    pub(crate) fn put(&mut self, path: &[Vec<u8>], k: &[u8], v: &[u8]) -> Result<()> {
+        self.size += (k.len() + v.len()) as u64;
+        self.bucket(path, None)?.put(k.to_vec(), v.to_vec())?;
+        if self.size > MAX_TX_SIZE {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    // sets next_int for the bucket at the given path, or the root bucket if the path is empty
+    pub(crate) fn set_next_int(&mut self, path: &[Vec<u8>], next_int: u64) -> Result<()> {
+        if path.is_empty() {
+            let tx = self.tx()?.inner.borrow();
+            let mut root = tx.root.borrow_mut();
+            root.meta.next_int = next_int;
+            root.dirty = true;
+        } else {
+            let b = self.bucket(path, None)?;
+            let mut inner = b.inner.borrow_mut();
+            inner.meta.next_int = next_int;
+            inner.dirty = true;
+        }
+        Ok(())
+    }
+
+    // finds the bucket at the given path in the current transaction,
+    // or creates the last bucket in the path if options are given
+    pub(crate) fn bucket(
+        &mut self,
+        path: &[Vec<u8>],
+        create: Option<BucketOptions>,
+    ) -> Result<Bucket<'_, 'a>> {
+        if let Some(options) = create {
+            self.comparators.insert(path.to_vec(), options.comparator);
+        }
+        let options: Vec<BucketOptions> = (1..=path.len())
+            .map(|i| {
+                let comparator = self.comparators.get(&path[..i]).copied();
+                BucketOptions::new().comparator(comparator.unwrap_or_default())
+            })
+            .collect();
+        let tx = self.tx()?;
+        let mut b = match create {
+            Some(options) if path.len() == 1 => tx.create_bucket_with(path[0].clone(), options)?,
+            _ => tx.get_bucket_with(path[0].clone(), options[0])?,
+        };
+        for (i, name) in path.iter().enumerate().skip(1) {
+            b = match create {
+                Some(options) if i == path.len() - 1 => {
+                    b.create_bucket_with(name.clone(), options)?
+                }
+                _ => b.get_bucket_with(name.clone(), options[i])?,
+            };
+        }
+        Ok(b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::RandomFile;
+
+    #[test]
+    fn test_compact_multiple_txs() -> Result<()> {
+        let random_file = RandomFile::new();
+        let compacted_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket("abc")?;
+            let nested = b.create_bucket("def")?;
+            for i in 0..1000_u32 {
+                b.put(i.to_be_bytes(), vec![0; 64])?;
+                nested.put(i.to_be_bytes(), vec![1; 64])?;
+            }
+            tx.create_bucket("ghi")?;
+            tx.commit()?;
+        }
+        // the data is bigger than MAX_TX_SIZE, so it has to be copied in several transactions
+        let report = db.compact_into(&compacted_file, OpenOptions::new().pagesize(1024))?;
+        assert!(report.dst_size < report.src_size);
+
+        let compacted = OpenOptions::new().pagesize(1024).open(&compacted_file)?;
+        compacted.check()?;
+        let tx = compacted.tx(false)?;
+        let b = tx.get_bucket("abc")?;
+        let nested = b.get_bucket("def")?;
+        assert_eq!(b.kv_pairs().count(), 1000);
+        assert_eq!(nested.kv_pairs().count(), 1000);
+        assert_eq!(b.next_int(), 1001);
+        assert_eq!(nested.next_int(), 1000);
+        assert_eq!(tx.get_bucket("ghi")?.next_int(), 0);
+        assert_eq!(tx.inner.borrow().root.borrow().meta.next_int, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_comparators() -> Result<()> {
+        const REVERSE: Comparator = Comparator::new("reverse", |a, b| b.cmp(a));
+        let random_file = RandomFile::new();
+        let compacted_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        let reverse = BucketOptions::new().comparator(REVERSE);
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket_with("abc", reverse)?;
+            let nested = b.create_bucket_with("def", reverse)?;
+            let bytewise = nested.create_bucket("ghi")?;
+            for i in 0..1000_u32 {
+                b.put(i.to_be_bytes(), vec![0; 64])?;
+                nested.put(i.to_be_bytes(), vec![1; 64])?;
+                bytewise.put(i.to_be_bytes(), vec![2; 64])?;
+            }
+            tx.commit()?;
+        }
+        assert_eq!(
+            db.compact_into(&compacted_file, OpenOptions::new().pagesize(1024))
+                .err(),
+            Some(Error::ComparatorRequired)
+        );
+        std::fs::remove_file(&compacted_file)?;
+        db.compact_into_with(
+            &compacted_file,
+            OpenOptions::new().pagesize(1024),
+            &[REVERSE],
+        )?;
+
+        let compacted = OpenOptions::new().pagesize(1024).open(&compacted_file)?;
+        compacted.check()?;
+        let tx = compacted.tx(false)?;
+        let b = tx.get_bucket_with("abc", reverse)?;
+        let nested = b.get_bucket_with("def", reverse)?;
+        let bytewise = nested.get_bucket("ghi")?;
+        let reversed: Vec<Vec<u8>> = (0..1000_u32)
+            .rev()
+            .map(|i| i.to_be_bytes().to_vec())
+            .collect();
+        assert_eq!(
+            b.kv_pairs().map(|kv| kv.key().to_vec()).collect::<Vec<_>>(),
+            reversed
+        );
+        assert_eq!(nested.kv_pairs().count(), 1000);
+        assert_eq!(
+            bytewise.get_kv(10_u32.to_be_bytes()).unwrap().value(),
+            &[2; 64]
+        );
+        Ok(())
+    }
+}
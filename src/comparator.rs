@@ -0,0 +1,400 @@
+use std::{cmp::Ordering, fmt, hash::Hasher};
+
+use fnv::FnvHasher;
+
+/// Decides the order of the keys in a bucket.
+///
+/// By default, keys are sorted by their bytes. A bucket created with [`create_bucket_with`](struct.Tx.html#method.create_bucket_with)
+/// can use a different order, like case-insensitive names or numbers written out as text.
+/// The comparator's name is saved with the bucket, so opening the bucket with [`get_bucket_with`](struct.Tx.html#method.get_bucket_with)
+/// and a comparator with a different name (or with [`get_bucket`](struct.Tx.html#method.get_bucket), which uses the default comparator)
+/// returns a [`ComparatorMismatch`](enum.Error.html#variant.ComparatorMismatch) error.
+///
+/// The function must never change the order it puts keys in, so if you need a different order,
+/// create a new bucket with a new name for your comparator and copy the data over.
+/// Keys that compare as [`Equal`](Ordering::Equal) are treated as the same key, so putting one replaces the other.
+///
+/// Iterating over a bucket works without its comparator, so buckets returned by
+/// [`Tx::buckets`](struct.Tx.html#method.buckets) can be read with a cursor,
+/// but keys can't be looked up until you open the bucket with its comparator:
+/// [`get`](struct.Bucket.html#method.get) finds nothing, [`prefix`](struct.Bucket.html#method.prefix)
+/// and any bounded [`range`](struct.Bucket.html#method.range) are empty, and anything that returns a `Result`
+/// returns a [`ComparatorRequired`](enum.Error.html#variant.ComparatorRequired) error.
+///
+/// # Examples
+///
+/// ```no_run
+/// use jammdb::{BucketOptions, Comparator, DB};
+/// # use jammdb::Error;
+///
+/// const CASE_INSENSITIVE: Comparator = Comparator::new("case-insensitive", |a, b| {
+///     a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase())
+/// });
+///
+/// # fn main() -> Result<(), Error> {
+/// let db = DB::open("my.db")?;
+/// let tx = db.tx(true)?;
+/// let names = tx.create_bucket_with("names", BucketOptions::new().comparator(CASE_INSENSITIVE))?;
+/// names.put("Kanan", "Jarrus")?;
+/// assert!(names.get("KANAN").is_some());
+/// tx.commit()?;
+///
+/// let tx = db.tx(false)?;
+/// assert!(tx.get_bucket("names").is_err());
+/// let names = tx.get_bucket_with("names", BucketOptions::new().comparator(CASE_INSENSITIVE))?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Copy)]
+pub struct Comparator {
+    name: &'static str,
+    compare: fn(&[u8], &[u8]) -> Ordering,
+    kind: Kind,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Bytewise,
+    Custom,
+    // a bucket opened without its comparator
+    Unknown,
+}
+
+impl Comparator {
+    /// Sorts keys by their bytes. This is the default.
+    pub const BYTEWISE: Comparator = Comparator {
+        name: "bytewise",
+        compare: bytewise,
+        kind: Kind::Bytewise,
+    };
+
+    // Used for buckets opened without their comparator.
+    pub(crate) const UNKNOWN: Comparator = Comparator {
+        name: "unknown",
+        compare: unknown,
+        kind: Kind::Unknown,
+    };
+
+    /// Creates a comparator with a name that identifies it, and a function that compares two keys.
+    pub const fn new(name: &'static str, compare: fn(&[u8], &[u8]) -> Ordering) -> Comparator {
+        Comparator {
+            name,
+            compare,
+            kind: Kind::Custom,
+        }
+    }
+
+    /// Returns the comparator's name.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    // The identity saved in the bucket's metadata, 0 is always the bytewise comparator.
+    pub(crate) fn id(&self) -> u64 {
+        if self.kind == Kind::Bytewise {
+            return 0;
+        }
+        let mut hasher = FnvHasher::default();
+        hasher.write(self.name.as_bytes());
+        hasher.finish().max(1)
+    }
+
+    // Buckets opened without their comparator can be iterated over, but not searched.
+    pub(crate) fn is_known(&self) -> bool {
+        self.kind != Kind::Unknown
+    }
+
+    #[inline]
+    pub(crate) fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        (self.compare)(a, b)
+    }
+
+    pub(crate) fn for_id(id: u64) -> Comparator {
+        if id == 0 {
+            Self::BYTEWISE
+        } else {
+            Self::UNKNOWN
+        }
+    }
+}
+
+impl Default for Comparator {
+    fn default() -> Self {
+        Self::BYTEWISE
+    }
+}
+
+impl fmt::Debug for Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Comparator").field(&self.name).finish()
+    }
+}
+
+fn bytewise(a: &[u8], b: &[u8]) -> Ordering {
+    a.cmp(b)
+}
+
+fn unknown(_: &[u8], _: &[u8]) -> Ordering {
+    panic!("Cannot search a bucket that was opened without its comparator, use get_bucket_with.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testutil::RandomFile, BucketOptions, Data, Error, OpenOptions, ReadData, Result, DB,
+    };
+
+    const CASE_INSENSITIVE: Comparator = Comparator::new("case-insensitive", |a, b| {
+        a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase())
+    });
+
+    // sorts numbers written out as text by their value
+    const NUMERIC: Comparator = Comparator::new("numeric", |a, b| {
+        a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+    });
+
+    fn numeric() -> BucketOptions {
+        BucketOptions::new().comparator(NUMERIC)
+    }
+
+    fn keys<'b, 'tx>(data: impl Iterator<Item = Data<'b, 'tx>>) -> Vec<u64> {
+        data.map(|d| std::str::from_utf8(d.key()).unwrap().parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_comparator_id() {
+        assert_eq!(Comparator::BYTEWISE.id(), 0);
+        // only the real bytewise comparator gets its id
+        assert_ne!(Comparator::new("bytewise", |a, b| b.cmp(a)).id(), 0);
+        let a = Comparator::new("a", bytewise);
+        let b = Comparator::new("b", bytewise);
+        assert_ne!(a.id(), 0);
+        assert_ne!(a.id(), b.id());
+        // the id is saved in the file, so it must never change
+        assert_eq!(a.id(), 0xaf63dc4c8601ec8c);
+        assert_eq!(format!("{:?}", a), "Comparator(\"a\")");
+    }
+
+    #[test]
+    fn test_case_insensitive() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = DB::open(&random_file)?;
+        let options = BucketOptions::new().comparator(CASE_INSENSITIVE);
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket_with("names", options)?;
+            b.put("Kanan", "Jarrus")?;
+            b.put("ezra", "Bridger")?;
+            b.put("HERA", "Syndulla")?;
+            // replaces the existing key, since they are equal
+            b.put("kanan", "Caleb Dume")?;
+            assert_eq!(b.get_kv("KANAN").unwrap().value(), b"Caleb Dume");
+            let names: Vec<Vec<u8>> = b.cursor().map(|d| d.key().to_vec()).collect();
+            assert_eq!(
+                names,
+                vec![b"ezra".to_vec(), b"HERA".to_vec(), b"kanan".to_vec()]
+            );
+            tx.commit()?;
+        }
+        let tx = db.tx(false)?;
+        assert_eq!(
+            tx.get_bucket("names").err(),
+            Some(Error::ComparatorMismatch("bytewise"))
+        );
+        let b = tx.get_bucket_with("names", options)?;
+        assert_eq!(b.get_kv("hera").unwrap().value(), b"Syndulla");
+        assert_eq!(b.range("F"..="hera").count(), 1);
+        assert_eq!(b.prefix("ka").count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_numeric() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket_with("numbers", numeric())?;
+            // enough keys to split the bucket across many pages
+            for i in (0..2000_u64).rev() {
+                b.put(i.to_string(), i.to_string())?;
+            }
+            tx.commit()?;
+        }
+        {
+            let tx = db.tx(false)?;
+            let b = tx.get_bucket_with("numbers", numeric())?;
+            assert_eq!(keys(b.cursor()), (0..2000).collect::<Vec<_>>());
+            assert_eq!(keys(b.cursor().rev()), (0..2000).rev().collect::<Vec<_>>());
+            assert_eq!(keys(b.range("9".."12")), vec![9, 10, 11]);
+            assert_eq!(
+                keys(b.range("95"..="105").rev()),
+                (95..=105).rev().collect::<Vec<_>>()
+            );
+            // both ends of the cursor meet in the middle
+            let mut c = b.cursor();
+            assert_eq!(keys(c.by_ref().take(1000)).len(), 1000);
+            assert_eq!(keys(c.rev()), (1000..2000).rev().collect::<Vec<_>>());
+            assert!(db.verify(Default::default())?.is_ok());
+        }
+        {
+            // deleting most of the keys merges the pages back together
+            let tx = db.tx(true)?;
+            let b = tx.get_bucket_with("numbers", numeric())?;
+            for i in (0..2000_u64).filter(|i| i % 100 != 0) {
+                b.delete(i.to_string())?;
+            }
+            tx.commit()?;
+        }
+        let tx = db.tx(false)?;
+        let b = tx.get_bucket_with("numbers", numeric())?;
+        assert_eq!(keys(b.cursor()), (0..2000).step_by(100).collect::<Vec<_>>());
+        assert!(db.verify(Default::default())?.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mismatch() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = DB::open(&random_file)?;
+        {
+            let tx = db.tx(true)?;
+            tx.create_bucket("bytes")?;
+            let b = tx.create_bucket_with("numbers", numeric())?;
+            b.create_bucket_with("nested", BucketOptions::new().comparator(CASE_INSENSITIVE))?;
+            b.put("10", "ten")?;
+            // opening it again in the same transaction checks the comparator too
+            assert_eq!(
+                tx.get_bucket("numbers").err(),
+                Some(Error::ComparatorMismatch("bytewise"))
+            );
+            assert_eq!(
+                tx.create_bucket_with("numbers", numeric()).err(),
+                Some(Error::BucketExists)
+            );
+            tx.commit()?;
+        }
+        let tx = db.tx(true)?;
+        assert_eq!(
+            tx.get_bucket_with("bytes", numeric()).err(),
+            Some(Error::ComparatorMismatch("numeric"))
+        );
+        assert!(tx.get_bucket_with("bytes", BucketOptions::new()).is_ok());
+        let b = tx.get_bucket_with("numbers", numeric())?;
+        assert_eq!(
+            b.get_bucket_with("nested", numeric()).err(),
+            Some(Error::ComparatorMismatch("numeric"))
+        );
+        // buckets can be iterated over and deleted without their comparator
+        for (name, b) in tx.buckets() {
+            if name.name() == b"numbers" {
+                assert_eq!(b.kv_pairs().count(), 1);
+            }
+        }
+        tx.delete_bucket("numbers")?;
+        tx.commit()?;
+
+        let tx = db.read_tx()?;
+        assert_eq!(tx.buckets().count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_comparator() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket_with("numbers", numeric())?;
+            b.create_bucket("nested")?;
+            for i in 0..1000_u64 {
+                b.put(i.to_string(), i.to_string())?;
+            }
+            tx.commit()?;
+        }
+        {
+            let tx = db.tx(true)?;
+            let (_, b) = tx.buckets().next().unwrap();
+            assert_eq!(b.get("10"), None);
+            assert_eq!(b.put("10", "ten").err(), Some(Error::ComparatorRequired));
+            assert_eq!(b.delete("10").err(), Some(Error::ComparatorRequired));
+            assert_eq!(
+                b.get_bucket("nested").err(),
+                Some(Error::ComparatorRequired)
+            );
+            assert_eq!(b.range("9".."12").count(), 0);
+            assert_eq!(b.prefix("1").count(), 0);
+            assert_eq!(b.range(..).count(), 1001);
+            // both ends of the cursor still meet in the middle
+            let mut c = b.cursor();
+            assert_eq!(c.by_ref().take(500).count(), 500);
+            assert_eq!(c.rev().count(), 501);
+            // nested buckets can still be reached by iterating
+            assert_eq!(b.buckets().count(), 1);
+            // nothing was poisoned, so the transaction can still be used
+            tx.commit()?;
+        }
+        let tx = db.read_tx()?;
+        let (_, b) = tx.buckets().next().unwrap()?;
+        assert_eq!(b.get("10")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_tx() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket_with("numbers", numeric())?;
+            for i in 0..1000_u64 {
+                b.put(i.to_string(), i.to_string())?;
+            }
+            tx.commit()?;
+        }
+        let tx = db.read_tx()?;
+        assert_eq!(
+            tx.get_bucket("numbers").err(),
+            Some(Error::ComparatorMismatch("bytewise"))
+        );
+        let b = tx.get_bucket_with("numbers", numeric())?;
+        for i in 0..1000_u64 {
+            assert_eq!(
+                b.get_kv(i.to_string())?.as_deref(),
+                Some(i.to_string().as_bytes())
+            );
+        }
+        let (_, b) = tx.buckets().next().unwrap()?;
+        let numbers: Vec<u64> = b
+            .iter()
+            .map(|d| match d.unwrap() {
+                ReadData::KeyValue(k, _) => std::str::from_utf8(k).unwrap().parse().unwrap(),
+                ReadData::Bucket(_) => panic!("unexpected bucket"),
+            })
+            .collect();
+        assert_eq!(numbers, (0..1000).collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = DB::open(&random_file)?;
+        db.update(|tx| -> Result<()> {
+            let b = tx.create_bucket_with("numbers", numeric())?;
+            for i in 0..100_u64 {
+                b.put(i.to_string(), i.to_string())?;
+            }
+            Ok(())
+        })?;
+        let dst = RandomFile::new();
+        db.compact_into_with(&dst, OpenOptions::new(), &[CASE_INSENSITIVE, NUMERIC])?;
+        let compacted = DB::open(&dst)?;
+        let tx = compacted.tx(false)?;
+        let b = tx.get_bucket_with("numbers", numeric())?;
+        assert_eq!(keys(b.cursor()), (0..100).collect::<Vec<_>>());
+        Ok(())
+    }
+}
@@ -0,0 +1,390 @@
+use std::{borrow::Cow, rc::Rc};
+
+use crate::{
+    bytes::Bytes,
+    errors::{Error, Result},
+};
+
+/// How the values in a bucket are compressed.
+///
+/// Set with [`BucketOptions::compression`](struct.BucketOptions.html#method.compression) when creating a bucket.
+/// Values are compressed when they are put into the bucket, and decompressed when you read them,
+/// so [`KVPair::value`](struct.KVPair.html#method.value) always returns the original bytes.
+/// Values that don't get any smaller are stored as they are.
+///
+/// Each value is flagged with the compression it was stored with, so buckets can hold a mix of compressed and
+/// uncompressed values, and database files without any compressed values can still be read by older versions of jammdb.
+///
+/// Each codec needs its feature to be enabled. Without it, creating a bucket that uses it returns a
+/// [`Codec`](enum.Error.html#variant.Codec) error, and so does reading a value that was compressed with it.
+/// Values that can't be decompressed (including corrupted ones) are returned as errors wherever a `Result` is returned,
+/// like [`ReadBucket::get`](struct.ReadBucket.html#method.get) or [`Bucket::delete`](struct.Bucket.html#method.delete),
+/// but [`Bucket::get`](struct.Bucket.html#method.get) doesn't find them and cursors skip over them.
+///
+/// # Examples
+///
+/// ```no_run
+/// use jammdb::{BucketOptions, Compression, DB};
+/// # use jammdb::Error;
+///
+/// # fn main() -> Result<(), Error> {
+/// let db = DB::open("my.db")?;
+/// let tx = db.tx(true)?;
+/// let options = BucketOptions::new().compression(Compression::Lz4);
+/// let docs = tx.create_bucket_with("docs", options)?;
+/// docs.put("1", r#"{"name": "Ezra", "padawan": true, "padawan": true, "padawan": true}"#)?;
+/// tx.commit()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Values are stored as they are. This is the default.
+    #[default]
+    None,
+    /// [LZ4](https://lz4.org/), which is very fast. Needs the `lz4` feature.
+    Lz4,
+    /// [Zstandard](https://facebook.github.io/zstd/), which compresses better than LZ4.
+    /// Needs the `zstd` feature.
+    Zstd,
+}
+
+// Zstandard's default compression level
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+impl Compression {
+    const FLAG_NONE: u8 = 0;
+    const FLAG_LZ4: u8 = 1;
+    const FLAG_ZSTD: u8 = 2;
+
+    // The flag saved with each value, and in the bucket's metadata.
+    pub(crate) fn flag(&self) -> u8 {
+        match self {
+            Compression::None => Self::FLAG_NONE,
+            Compression::Lz4 => Self::FLAG_LZ4,
+            Compression::Zstd => Self::FLAG_ZSTD,
+        }
+    }
+
+    // Checks if the flag belongs to a codec, even one that is not enabled.
+    pub(crate) fn valid_flag(flag: u8) -> bool {
+        Self::from_flag(flag).is_some()
+    }
+
+    pub(crate) fn from_flag(flag: u8) -> Option<Compression> {
+        match flag {
+            Self::FLAG_NONE => Some(Compression::None),
+            Self::FLAG_LZ4 => Some(Compression::Lz4),
+            Self::FLAG_ZSTD => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    // Makes sure the codec's feature is enabled.
+    pub(crate) fn check_enabled(&self) -> Result<()> {
+        let feature: Option<&str> = match self {
+            #[cfg(not(feature = "lz4"))]
+            Compression::Lz4 => Some("lz4"),
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => Some("zstd"),
+            _ => None,
+        };
+        match feature {
+            Some(feature) => Err(Error::Codec(format!(
+                "{:?} compression needs the \"{}\" feature",
+                self, feature
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    // Compresses the value, and returns the compression that was actually used.
+    // Values that don't get smaller are left alone.
+    pub(crate) fn compress<'a>(&self, value: Bytes<'a>) -> Result<(Bytes<'a>, Compression)> {
+        self.check_enabled()?;
+        let compressed: Option<Vec<u8>> = match self {
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Some(lz4_flex::compress_prepend_size(value.as_ref())),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Some(zstd::bulk::compress(value.as_ref(), ZSTD_LEVEL)?),
+            _ => None,
+        };
+        match compressed {
+            Some(compressed) if compressed.len() < value.size() => {
+                Ok((Bytes::Vec(Rc::new(compressed)), *self))
+            }
+            _ => Ok((value, Compression::None)),
+        }
+    }
+
+    pub(crate) fn decompress<'a>(&self, value: Bytes<'a>) -> Result<Bytes<'a>> {
+        match self.decompress_vec(value.as_ref())? {
+            Some(decompressed) => Ok(Bytes::Vec(Rc::new(decompressed))),
+            None => Ok(value),
+        }
+    }
+
+    pub(crate) fn decompress_slice<'a>(&self, value: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        match self.decompress_vec(value)? {
+            Some(decompressed) => Ok(Cow::Owned(decompressed)),
+            None => Ok(Cow::Borrowed(value)),
+        }
+    }
+
+    // Returns None if the value isn't compressed.
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    fn decompress_vec(&self, value: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.check_enabled()?;
+        let decompressed: Option<std::result::Result<Vec<u8>, String>> = match self {
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                Some(lz4_flex::decompress_size_prepended(value).map_err(|e| e.to_string()))
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Some(zstd::decode_all(value).map_err(|e| e.to_string())),
+            // codecs that aren't enabled were already rejected
+            _ => None,
+        };
+        match decompressed {
+            Some(Ok(decompressed)) => Ok(Some(decompressed)),
+            Some(Err(e)) => Err(Error::InvalidDB(format!(
+                "Invalid {:?} compressed value: {}",
+                self, e
+            ))),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    use crate::{testutil::RandomFile, BucketOptions, Data, OpenOptions, ReadData};
+
+    #[test]
+    fn test_flags() {
+        assert_eq!(Compression::default(), Compression::None);
+        assert_eq!(Compression::from_flag(0), Some(Compression::None));
+        assert!(Compression::valid_flag(2));
+        assert!(!Compression::valid_flag(3));
+        let value = Bytes::Slice(b"abc");
+        let (compressed, c) = Compression::None.compress(value.clone()).unwrap();
+        assert_eq!(c, Compression::None);
+        assert_eq!(c.decompress(compressed).unwrap(), value);
+    }
+
+    #[test]
+    #[cfg(feature = "lz4")]
+    fn test_lz4() {
+        assert_eq!(Compression::from_flag(1), Some(Compression::Lz4));
+        let value = Bytes::Vec(Rc::new(vec![7; 1000]));
+        let (compressed, c) = Compression::Lz4.compress(value.clone()).unwrap();
+        assert_eq!(c, Compression::Lz4);
+        assert!(compressed.size() < 100);
+        assert_eq!(c.decompress(compressed).unwrap(), value);
+        // small values are left alone
+        let (compressed, c) = Compression::Lz4.compress(Bytes::Slice(b"abc")).unwrap();
+        assert_eq!(c, Compression::None);
+        assert_eq!(compressed, Bytes::Slice(b"abc"));
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_zstd() {
+        assert_eq!(Compression::from_flag(2), Some(Compression::Zstd));
+        let value = Bytes::Vec(Rc::new(vec![7; 1000]));
+        let (compressed, c) = Compression::Zstd.compress(value.clone()).unwrap();
+        assert_eq!(c, Compression::Zstd);
+        assert!(compressed.size() < 100);
+        assert_eq!(c.decompress(compressed).unwrap(), value);
+        let (compressed, c) = Compression::Zstd.compress(Bytes::Slice(b"abc")).unwrap();
+        assert_eq!(c, Compression::None);
+        assert_eq!(compressed, Bytes::Slice(b"abc"));
+    }
+
+    #[test]
+    #[cfg(not(feature = "lz4"))]
+    fn test_lz4_disabled() -> Result<()> {
+        assert_eq!(Compression::from_flag(1), Some(Compression::Lz4));
+        assert!(matches!(
+            Compression::Lz4.decompress(Bytes::Slice(b"abc")),
+            Err(Error::Codec(_))
+        ));
+        let random_file = crate::testutil::RandomFile::new();
+        let db = crate::DB::open(&random_file)?;
+        let tx = db.tx(true)?;
+        let options = crate::BucketOptions::new().compression(Compression::Lz4);
+        assert!(matches!(
+            tx.create_bucket_with("docs", options),
+            Err(Error::Codec(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "lz4")]
+    fn test_corrupt_value() -> Result<()> {
+        let random_file = RandomFile::new();
+        let value = json(0);
+        {
+            let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+            let tx = db.tx(true)?;
+            let b =
+                tx.create_bucket_with("users", BucketOptions::new().compression(Compression::Lz4))?;
+            b.put("corrupt", value.clone())?;
+            b.put("ok", json(1))?;
+            tx.commit()?;
+        }
+        // the compressed value starts with its size, so make it too small to decompress into
+        let mut data = std::fs::read(&random_file)?;
+        let compressed = lz4_flex::compress_prepend_size(value.as_bytes());
+        let offset = data
+            .windows(compressed.len())
+            .position(|w| w == compressed.as_slice())
+            .unwrap();
+        data[offset..offset + 4].copy_from_slice(&1_u32.to_le_bytes());
+        std::fs::write(&random_file, data)?;
+
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        {
+            let tx = db.tx(true)?;
+            let b = tx.get_bucket("users")?;
+            assert!(b.get("corrupt").is_none());
+            assert!(matches!(b.try_get("corrupt"), Err(Error::InvalidDB(_))));
+            assert!(matches!(b.delete("corrupt"), Err(Error::InvalidDB(_))));
+            // it isn't replaced either, since the old value can't be returned
+            assert!(matches!(b.put("corrupt", "new"), Err(Error::InvalidDB(_))));
+            assert!(matches!(b.try_get("corrupt"), Err(Error::InvalidDB(_))));
+            // cursors skip over it, unless you ask for errors
+            let keys: Vec<Vec<u8>> = b.cursor().map(|d| d.key().to_vec()).collect();
+            assert_eq!(keys, vec![b"ok".to_vec()]);
+            assert_eq!(b.cursor().rev().count(), 1);
+            let mut cursor = b.cursor();
+            assert!(matches!(cursor.try_next(), Some(Err(Error::InvalidDB(_)))));
+            assert_eq!(cursor.try_next().unwrap()?.key(), b"ok");
+            assert!(cursor.try_next().is_none());
+            let mut cursor = b.cursor();
+            assert_eq!(cursor.try_next_back().unwrap()?.key(), b"ok");
+            assert!(matches!(
+                cursor.try_next_back(),
+                Some(Err(Error::InvalidDB(_)))
+            ));
+            assert!(matches!(
+                db.compact_into(RandomFile::new(), OpenOptions::new()),
+                Err(Error::InvalidDB(_))
+            ));
+        }
+        {
+            let tx = db.read_tx()?;
+            let b = tx.get_bucket("users")?;
+            assert!(matches!(b.get("corrupt"), Err(Error::InvalidDB(_))));
+            let mut iter = b.iter();
+            assert!(matches!(iter.next(), Some(Err(Error::InvalidDB(_)))));
+            assert_eq!(iter.next().unwrap()?.key(), b"ok");
+            assert!(iter.next().is_none());
+        }
+        Ok(())
+    }
+
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    fn json(i: u64) -> String {
+        format!(
+            r#"{{"id": {}, "name": "user-{}", "roles": ["admin", "admin", "admin", "admin"], "bio": "{}"}}"#,
+            i,
+            i,
+            "lorem ipsum ".repeat(50)
+        )
+    }
+
+    #[cfg(any(feature = "lz4", feature = "zstd"))]
+    fn check_bucket(c: Compression) -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        let options = BucketOptions::new().compression(c);
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket_with("users", options)?;
+            for i in 0..500_u64 {
+                b.put(i.to_be_bytes(), json(i))?;
+            }
+            // values that don't get smaller aren't compressed
+            b.put("small", "abc")?;
+            // the old value is returned decompressed
+            let old = b.put(0_u64.to_be_bytes(), json(1000))?.unwrap();
+            assert_eq!(old.value(), json(0).as_bytes());
+            tx.commit()?;
+        }
+        let raw_size = 500 * json(0).len() as u64;
+        assert!(db.stats()?.buckets.leaf_inuse < raw_size / 4);
+        assert!(db.verify(Default::default())?.is_ok());
+        {
+            // compression is saved with the bucket
+            let tx = db.tx(true)?;
+            let b = tx.get_bucket("users")?;
+            b.put(500_u64.to_be_bytes(), json(500))?;
+            assert_eq!(
+                b.get_kv(0_u64.to_be_bytes()).unwrap().value(),
+                json(1000).as_bytes()
+            );
+            let kv = b.delete(1_u64.to_be_bytes())?;
+            assert_eq!(kv.value(), json(1).as_bytes());
+            tx.commit()?;
+        }
+        let tx = db.tx(false)?;
+        let b = tx.get_bucket("users")?;
+        for data in b.range(2_u64.to_be_bytes()..=500_u64.to_be_bytes()) {
+            if let Data::KeyValue(kv) = data {
+                let i = u64::from_be_bytes(kv.key().try_into().unwrap());
+                assert_eq!(kv.value(), json(i).as_bytes());
+            }
+        }
+        assert_eq!(b.get_kv("small").unwrap().value(), b"abc");
+
+        let tx = db.read_tx()?;
+        let b = tx.get_bucket("users")?;
+        assert!(matches!(
+            b.get_kv(2_u64.to_be_bytes())?,
+            Some(Cow::Owned(_))
+        ));
+        assert!(matches!(b.get_kv("small")?, Some(Cow::Borrowed(b"abc"))));
+        let count = b
+            .iter()
+            .filter(|data| match data.as_ref().unwrap() {
+                ReadData::KeyValue(key, value) if key.len() == 8 => {
+                    let i = u64::from_be_bytes((*key).try_into().unwrap());
+                    i == 0 || value.as_ref() == json(i).as_bytes()
+                }
+                _ => false,
+            })
+            .count();
+        assert_eq!(count, 500);
+
+        // compacting keeps the values compressed
+        let compacted = RandomFile::new();
+        db.compact_into(&compacted, OpenOptions::new().pagesize(1024))?;
+        let db = OpenOptions::new().pagesize(1024).open(&compacted)?;
+        assert!(db.stats()?.buckets.leaf_inuse < raw_size / 4);
+        let tx = db.tx(false)?;
+        let b = tx.get_bucket("users")?;
+        assert_eq!(
+            b.get_kv(2_u64.to_be_bytes()).unwrap().value(),
+            json(2).as_bytes()
+        );
+        assert_eq!(b.inner.borrow().compression()?, c);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "lz4")]
+    fn test_lz4_bucket() -> Result<()> {
+        check_bucket(Compression::Lz4)
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_zstd_bucket() -> Result<()> {
+        check_bucket(Compression::Zstd)
+    }
+}
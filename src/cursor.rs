@@ -1,13 +1,16 @@
 use std::{
     cell::RefCell,
     marker::PhantomData,
-    ops::{Bound, RangeBounds},
+    ops::{self, Bound, RangeBounds},
     rc::Rc,
 };
 
 use crate::{
     bucket::{Bucket, InnerBucket},
+    bytes::{Bytes, ToBytes},
+    comparator::Comparator,
     data::Data,
+    errors::{Error, Result},
     freelist::TxFreelist,
     page::PageID,
     page_node::PageNodeID,
@@ -22,6 +25,15 @@ use crate::{
 /// element in the bucket (sorted by key), but you can use the [`seek`](#method.seek) method to
 /// move the cursor to a certain key / prefix before beginning to iterate.
 ///
+/// Cursors can also move backwards. Use [`last`](#method.last) and [`prev`](#method.prev)
+/// to walk the bucket from the end, or call `rev()` since a cursor is also a [`DoubleEndedIterator`].
+/// [`first`](#method.first) and [`last`](#method.last) return the elements at either end of the bucket
+/// without walking over everything in between.
+///
+/// Iterating over a cursor skips values that can't be read, like compressed values that are corrupted,
+/// and stops at a page that can't be read.
+/// Use [`try_next`](#method.try_next) and [`try_next_back`](#method.try_next_back) to get an error for them instead.
+///
 /// Note that if the key you seek to exists, the cursor will begin to iterate after
 /// the
 ///
@@ -52,6 +64,11 @@ use crate::{
 /// for data in cursor {
 /// }
 ///
+/// // iterate over the bucket in reverse order
+/// for data in bucket.cursor().rev() {
+///     println!("{:?}", data.key());
+/// }
+///
 /// # Ok(())
 /// # }
 /// ```
@@ -61,6 +78,20 @@ pub struct Cursor<'b, 'tx> {
     writable: bool,
     stack: Vec<SearchPath>,
     next_called: bool,
+    back_stack: Vec<SearchPath>,
+    next_back_called: bool,
+    // Whether next or prev ran out of elements, so moving the other way
+    // returns the element at the edge instead of skipping over it.
+    past_end: bool,
+    before_start: bool,
+    // The last keys returned from either end of the cursor,
+    // so we can stop iterating once the two ends meet.
+    front_key: Option<Bytes<'tx>>,
+    back_key: Option<Bytes<'tx>>,
+    // Set once a page couldn't be read, which stops the cursor.
+    // The error is kept until try_next or try_next_back can return it.
+    failed: bool,
+    error: Option<Error>,
     _phantom: PhantomData<&'b ()>,
 }
 
@@ -72,6 +103,14 @@ impl<'b, 'tx> Cursor<'b, 'tx> {
             writable: b.writable,
             stack: Vec::new(),
             next_called: false,
+            back_stack: Vec::new(),
+            next_back_called: false,
+            past_end: false,
+            before_start: false,
+            front_key: None,
+            back_key: None,
+            failed: false,
+            error: None,
             _phantom: PhantomData,
         }
     }
@@ -83,54 +122,437 @@ impl<'b, 'tx> Cursor<'b, 'tx> {
     /// Returns whether or not the key exists in the bucket.
     pub fn seek<T: AsRef<[u8]>>(&mut self, key: T) -> bool {
         self.next_called = false;
-        let mut b = self.bucket.borrow_mut();
-        if b.deleted {
-            panic!("Cannot seek cursor on a deleted bucket.");
+        self.front_key = None;
+        self.past_end = false;
+        self.before_start = false;
+        self.reset();
+        let result = {
+            let mut b = self.bucket.borrow_mut();
+            if b.deleted {
+                panic!("Cannot seek cursor on a deleted bucket.");
+            }
+            // without the bucket's comparator there's nowhere to seek to, so start from the beginning
+            if !b.comparator.is_known() {
+                self.stack.clear();
+                return false;
+            }
+            search(key.as_ref(), b.meta.root_page, &mut b)
+        };
+        match result {
+            Ok((exists, stack)) => {
+                self.stack = stack;
+                exists
+            }
+            Err(e) => {
+                self.fail(e);
+                false
+            }
+        }
+    }
+
+    /// Moves the cursor to the first element in the bucket and returns it.
+    ///
+    /// Calling [`next`](#method.next) afterwards will return the second element.
+    pub fn first(&mut self) -> Option<Data<'b, 'tx>> {
+        self.stack.clear();
+        self.next_called = false;
+        self.front_key = None;
+        self.past_end = false;
+        self.before_start = false;
+        self.reset();
+        self.next()
+    }
+
+    /// Moves the cursor to the last element in the bucket and returns it.
+    ///
+    /// Calling [`prev`](#method.prev) afterwards will return the second to last element.
+    ///
+    /// A cursor is also an [`Iterator`], and calling `last` on a cursor you own picks
+    /// [`Iterator::last`] instead, which consumes the cursor.
+    /// Call this one through a mutable reference to keep using the cursor afterwards.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::{Cursor, DB};
+    /// # use jammdb::Error;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let db = DB::open("my.db")?;
+    /// let mut tx = db.tx(false)?;
+    /// let bucket = tx.get_bucket("my-bucket")?;
+    ///
+    /// let mut cursor = bucket.cursor();
+    /// if let Some(data) = Cursor::last(&mut cursor) {
+    ///     println!("the last key is {:?}", data.key());
+    /// }
+    /// // walk back towards the start of the bucket
+    /// while let Some(data) = cursor.prev() {
+    ///     println!("{:?}", data.key());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn last(&mut self) -> Option<Data<'b, 'tx>> {
+        self.reset();
+        let result = {
+            let b = self.bucket.borrow();
+            if b.deleted {
+                panic!("Cannot seek cursor on a deleted bucket.");
+            }
+            edge(&b, true).and_then(|mut stack| {
+                settle(&b, &mut stack, false)?;
+                Ok(stack)
+            })
+        };
+        match result {
+            Ok(stack) => self.stack = stack,
+            Err(e) => {
+                self.fail(e);
+                return None;
+            }
+        }
+        self.next_called = true;
+        self.front_key = None;
+        self.past_end = false;
+        self.before_start = false;
+        self.current()
+    }
+
+    /// Moves the cursor to the element before its current position and returns it.
+    ///
+    /// If the cursor has not been positioned yet, or [`next`](#method.next) has already run past the last element,
+    /// this behaves like [`last`](#method.last).
+    /// Returns `None` if the cursor is already at the first element.
+    pub fn prev(&mut self) -> Option<Data<'b, 'tx>> {
+        if self.failed {
+            return None;
         }
-        let (exists, stack) = search(key.as_ref(), b.meta.root_page, &mut b);
-        self.stack = stack;
-        exists
+        if self.stack.is_empty() || self.past_end {
+            return self.last();
+        }
+        match self.step_front(false) {
+            Ok(true) => (),
+            Ok(false) => {
+                self.before_start = true;
+                return None;
+            }
+            Err(e) => {
+                self.fail(e);
+                return None;
+            }
+        }
+        self.next_called = true;
+        self.front_key = None;
+        self.current()
     }
 
     /// Returns the data at the cursor's current position.
     /// You can use this to get data after doing a [`seek`](#method.seek).
     pub fn current<'a>(&'a self) -> Option<Data<'b, 'tx>> {
-        let b = self.bucket.borrow_mut();
-        if b.deleted {
-            panic!("Cannot get data from a deleted bucket.");
+        match current(&self.bucket, &self.stack) {
+            Ok(Some(Ok(data))) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Moves the cursor forward and returns the next element, like [`next`](#method.next),
+    /// but returns an error for a value that can't be read instead of skipping it.
+    ///
+    /// If a page in the bucket can't be read, this returns the error and then stops,
+    /// even if the cursor ran into that page while seeking.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::DB;
+    /// # use jammdb::Error;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let db = DB::open("my.db")?;
+    /// let mut tx = db.tx(false)?;
+    /// let bucket = tx.get_bucket("my-bucket")?;
+    ///
+    /// let mut cursor = bucket.cursor();
+    /// while let Some(data) = cursor.try_next() {
+    ///     println!("{:?}", data?.key());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn try_next(&mut self) -> Option<Result<Data<'b, 'tx>>> {
+        if self.failed {
+            return self.error.take().map(Err);
         }
-        match self.stack.last() {
-            Some(e) => {
-                let n = b.page_node(e.id);
-                n.val(e.index).map(|data| data.into())
+        let moved = if self.stack.is_empty() || self.before_start {
+            // prev ran out of elements, and left the stack at the first one
+            self.before_start = false;
+            self.edge_front()
+        } else if self.next_called {
+            self.step_front(true)
+        } else {
+            self.settle_front()
+        };
+        self.next_called = true;
+        match moved {
+            Ok(true) => (),
+            Ok(false) => {
+                self.past_end = true;
+                return None;
+            }
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        }
+        let data = match current(&self.bucket, &self.stack) {
+            Ok(Some(Ok(data))) => data,
+            Ok(Some(Err(e))) => return Some(Err(e)),
+            Ok(None) => return None,
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        };
+        if let Some(back_key) = &self.back_key {
+            if self.crossed(data.key(), back_key.as_ref()) {
+                return None;
             }
-            None => None,
         }
+        self.front_key = Some(data.key_bytes());
+        Some(Ok(data))
     }
 
-    fn seek_first(&mut self) {
-        let b = self.bucket.borrow();
-        if self.stack.is_empty() {
-            self.stack.push(SearchPath {
-                index: 0,
-                id: PageNodeID::Page(b.meta.root_page),
-            });
+    /// Moves the back of the cursor and returns the previous element, like [`next_back`](#method.next_back),
+    /// but returns an error for a value that can't be read instead of skipping it.
+    ///
+    /// Like [`try_next`](#method.try_next), this stops after returning the error for a page that can't be read.
+    pub fn try_next_back(&mut self) -> Option<Result<Data<'b, 'tx>>> {
+        if self.failed {
+            return self.error.take().map(Err);
         }
-        loop {
-            let elem = self.stack.last().unwrap();
-            let page_node = b.page_node(elem.id);
-            if page_node.leaf() {
-                break;
+        let moved = self.step_back();
+        self.next_back_called = true;
+        match moved {
+            Ok(true) => (),
+            Ok(false) => return None,
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        }
+        let data = match current(&self.bucket, &self.back_stack) {
+            Ok(Some(Ok(data))) => data,
+            Ok(Some(Err(e))) => return Some(Err(e)),
+            Ok(None) => return None,
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        };
+        if let Some(front_key) = &self.front_key {
+            if self.crossed(front_key.as_ref(), data.key()) {
+                return None;
+            }
+        }
+        self.back_key = Some(data.key_bytes());
+        Some(Ok(data))
+    }
+
+    // Moves the back of the cursor to the given key, the same way `seek` moves the front.
+    fn seek_back(&mut self, key: &[u8]) -> bool {
+        self.next_back_called = false;
+        self.back_key = None;
+        let result = {
+            let mut b = self.bucket.borrow_mut();
+            if b.deleted {
+                panic!("Cannot seek cursor on a deleted bucket.");
+            }
+            if !b.comparator.is_known() {
+                self.back_stack.clear();
+                return false;
             }
-            if page_node.len() == 0 {
-                break;
+            search(key, b.meta.root_page, &mut b)
+        };
+        match result {
+            Ok((exists, stack)) => {
+                self.back_stack = stack;
+                exists
             }
-            let page_id = page_node.index_page(elem.index);
+            Err(e) => {
+                self.fail(e);
+                false
+            }
+        }
+    }
+
+    // Moves the front of the cursor to the first element.
+    fn edge_front(&mut self) -> Result<bool> {
+        let b = self.bucket.borrow();
+        if b.deleted {
+            panic!("Cannot get data from a deleted bucket.");
+        }
+        self.stack = edge(&b, false)?;
+        settle(&b, &mut self.stack, true)
+    }
+
+    // Settles the front of the cursor on an element after a seek.
+    fn settle_front(&mut self) -> Result<bool> {
+        let b = self.bucket.borrow();
+        if b.deleted {
+            panic!("Cannot get data from a deleted bucket.");
+        }
+        settle(&b, &mut self.stack, true)
+    }
+
+    // Moves the front of the cursor one element forward or backward.
+    fn step_front(&mut self, forward: bool) -> Result<bool> {
+        let b = self.bucket.borrow();
+        if b.deleted {
+            panic!("Cannot get data from a deleted bucket.");
+        }
+        Ok(step(&b, &mut self.stack, forward)? && settle(&b, &mut self.stack, forward)?)
+    }
+
+    // Moves the back of the cursor one element backward.
+    fn step_back(&mut self) -> Result<bool> {
+        let b = self.bucket.borrow();
+        if b.deleted {
+            panic!("Cannot get data from a deleted bucket.");
+        }
+        if self.back_stack.is_empty() {
+            self.back_stack = edge(&b, true)?;
+        } else if self.next_back_called && !step(&b, &mut self.back_stack, false)? {
+            return Ok(false);
+        }
+        settle(&b, &mut self.back_stack, false)
+    }
+
+    // Stops the cursor after a page couldn't be read.
+    fn fail(&mut self, e: Error) {
+        self.stack.clear();
+        self.back_stack.clear();
+        self.failed = true;
+        self.error = Some(e);
+    }
+
+    // Clears an earlier error when the cursor is moved somewhere new.
+    fn reset(&mut self) {
+        self.failed = false;
+        self.error = None;
+    }
+
+    fn comparator(&self) -> Comparator {
+        self.bucket.borrow().comparator
+    }
+
+    // Whether the front of the cursor has reached the back.
+    // Without the bucket's comparator we can't tell which key is bigger,
+    // but both ends move one element at a time, so they meet on the same key.
+    fn crossed(&self, front: &[u8], back: &[u8]) -> bool {
+        let comparator = self.comparator();
+        if comparator.is_known() {
+            comparator.compare(front, back).is_ge()
+        } else {
+            front == back
+        }
+    }
+}
 
-            self.stack.push(SearchPath {
-                index: 0,
-                id: PageNodeID::Page(page_id),
-            });
+// Returns the data at the end of the stack.
+// The outer error is for pages that can't be read, the inner one for values that can't be.
+fn current<'b, 'tx>(
+    bucket: &Rc<RefCell<InnerBucket<'tx>>>,
+    stack: &[SearchPath],
+) -> Result<Option<Result<Data<'b, 'tx>>>> {
+    let b = bucket.borrow();
+    if b.deleted {
+        panic!("Cannot get data from a deleted bucket.");
+    }
+    match stack.last() {
+        Some(e) => {
+            let n = b.page_node(e.id)?;
+            Ok(n.val(e.index).map(Data::from_leaf))
+        }
+        None => Ok(None),
+    }
+}
+
+// Returns the path to either the first or the last element in the bucket.
+fn edge(b: &InnerBucket, last: bool) -> Result<Vec<SearchPath>> {
+    let root = b.page_node(PageNodeID::Page(b.meta.root_page))?;
+    let index = if last {
+        root.len().saturating_sub(1)
+    } else {
+        0
+    };
+    let mut stack = vec![SearchPath {
+        index,
+        id: root.id(),
+    }];
+    descend(b, &mut stack, last)?;
+    Ok(stack)
+}
+
+// Walks down from the end of the stack until we reach a leaf,
+// following either the first or the last element of every branch.
+fn descend(b: &InnerBucket, stack: &mut Vec<SearchPath>, last: bool) -> Result<()> {
+    loop {
+        let elem = stack.last().unwrap();
+        let page_node = b.page_node(elem.id)?;
+        if page_node.leaf() || page_node.len() == 0 {
+            return Ok(());
+        }
+        let page_id = page_node.index_page(elem.index);
+        let child = b.page_node(PageNodeID::Page(page_id))?;
+        let index = if last {
+            child.len().saturating_sub(1)
+        } else {
+            0
+        };
+        stack.push(SearchPath {
+            index,
+            id: child.id(),
+        });
+    }
+}
+
+// Moves the stack to the next (or previous) leaf element.
+// Returns false and leaves the stack untouched if there are no more elements in that direction.
+fn step(b: &InnerBucket, stack: &mut Vec<SearchPath>, forward: bool) -> Result<bool> {
+    let mut path = stack.clone();
+    loop {
+        let elem = match path.last_mut() {
+            Some(elem) => elem,
+            None => return Ok(false),
+        };
+        let len = b.page_node(elem.id)?.len();
+        if forward && elem.index + 1 < len {
+            elem.index += 1;
+            break;
+        }
+        if !forward && elem.index > 0 && len > 0 {
+            elem.index = (elem.index - 1).min(len - 1);
+            break;
+        }
+        path.pop();
+    }
+    descend(b, &mut path, !forward)?;
+    *stack = path;
+    Ok(true)
+}
+
+// Nodes in a writable transaction can be left empty until they are rebalanced,
+// so keep stepping until the stack points at an actual element.
+fn settle(b: &InnerBucket, stack: &mut Vec<SearchPath>, forward: bool) -> Result<bool> {
+    loop {
+        if let Some(elem) = stack.last() {
+            if elem.index < b.page_node(elem.id)?.len() {
+                return Ok(true);
+            }
+        }
+        if !step(b, stack, forward)? {
+            return Ok(false);
         }
     }
 }
@@ -140,20 +562,20 @@ pub(crate) fn search(
     key: &[u8],
     mut page_id: PageID,
     b: &mut InnerBucket,
-) -> (bool, Vec<SearchPath>) {
+) -> Result<(bool, Vec<SearchPath>)> {
     let mut stack = Vec::new();
     loop {
-        let page_node = b.page_node(PageNodeID::Page(page_id));
+        let page_node = b.page_node(PageNodeID::Page(page_id))?;
         let id = page_node.id();
-        let (index, exact) = page_node.index(key);
+        let (index, exact) = page_node.index(key, b.comparator);
         let leaf = page_node.leaf();
         stack.push(SearchPath { index, id });
         if leaf {
-            return (exact, stack);
+            return Ok((exact, stack));
         }
         let next_page_id = page_node.index_page(index);
         if next_page_id == 0 {
-            return (false, stack);
+            return Ok((false, stack));
         }
         b.add_page_parent(next_page_id, page_id);
         page_id = next_page_id;
@@ -161,6 +583,7 @@ pub(crate) fn search(
 }
 
 // Keeps track of the path we've taken to search a PageNode.
+#[derive(Clone, Copy)]
 pub(crate) struct SearchPath {
     pub(crate) index: usize,
     pub(crate) id: PageNodeID,
@@ -170,40 +593,89 @@ impl<'b, 'tx> Iterator for Cursor<'b, 'tx> {
     type Item = Data<'b, 'tx>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.stack.is_empty() {
-            self.seek_first();
-        } else if self.next_called {
-            loop {
-                {
-                    let b = self.bucket.borrow();
-                    if b.deleted {
-                        panic!("Cannot get data from a deleted bucket.");
-                    }
-                    let elem = self.stack.last_mut().unwrap();
-                    let page_node = b.page_node(elem.id);
-                    if elem.index >= (page_node.len() - 1) {
-                        if self.stack.len() == 1 {
-                            return None;
-                        }
-                        self.stack.pop();
-                        continue;
-                    } else {
-                        elem.index += 1;
-                    }
-                }
-                self.seek_first();
-                break;
+        loop {
+            // values that can't be decompressed are skipped
+            if let Ok(data) = self.try_next()? {
+                return Some(data);
             }
         }
-        self.next_called = true;
-        self.current()
+    }
+
+    // The last element can be found from the back of the cursor, without walking over everything else.
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+}
+
+impl<'b, 'tx> DoubleEndedIterator for Cursor<'b, 'tx> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            // values that can't be decompressed are skipped
+            if let Ok(data) = self.try_next_back()? {
+                return Some(data);
+            }
+        }
+    }
+}
+
+/// A range of keys that can be passed to [`range`](struct.Bucket.html#method.range).
+///
+/// Implemented for every range type in [`std::ops`] and for tuples of [`Bound`]s,
+/// as long as the ends can be referenced as bytes.
+pub trait KeyRange {
+    /// The start of the range.
+    fn start_key(&self) -> Bound<&[u8]>;
+    /// The end of the range.
+    fn end_key(&self) -> Bound<&[u8]>;
+}
+
+fn key_bound<K: AsRef<[u8]>>(bound: Bound<&K>) -> Bound<&[u8]> {
+    match bound {
+        Bound::Included(k) => Bound::Included(k.as_ref()),
+        Bound::Excluded(k) => Bound::Excluded(k.as_ref()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+macro_rules! impl_key_range {
+    ($($t:ty),*) => {
+        $(
+            impl<K: AsRef<[u8]>> KeyRange for $t {
+                fn start_key(&self) -> Bound<&[u8]> {
+                    key_bound(self.start_bound())
+                }
+                fn end_key(&self) -> Bound<&[u8]> {
+                    key_bound(self.end_bound())
+                }
+            }
+        )*
+    };
+}
+
+impl_key_range!(
+    ops::Range<K>,
+    ops::RangeInclusive<K>,
+    ops::RangeFrom<K>,
+    ops::RangeTo<K>,
+    ops::RangeToInclusive<K>,
+    (Bound<K>, Bound<K>)
+);
+
+impl KeyRange for ops::RangeFull {
+    fn start_key(&self) -> Bound<&[u8]> {
+        Bound::Unbounded
+    }
+    fn end_key(&self) -> Bound<&[u8]> {
+        Bound::Unbounded
     }
 }
 
 /// A bounded iterator over the data in a bucket.
+///
+/// Created by the [`range`](struct.Bucket.html#method.range) function on a [`Bucket`].
 pub struct Range<'r, 'b, 'tx, R>
 where
-    R: RangeBounds<&'r [u8]>,
+    R: KeyRange,
 {
     pub(crate) c: Cursor<'b, 'tx>,
     pub(crate) bounds: R,
@@ -212,45 +684,139 @@ where
 
 impl<'r, 'b, 'tx, R> Iterator for Range<'r, 'b, 'tx, R>
 where
-    R: RangeBounds<&'r [u8]>,
+    R: KeyRange,
 {
     type Item = Data<'b, 'tx>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if !self.c.next_called {
-            if let Bound::Included(s) = self.bounds.start_bound() {
-                let exists = self.c.seek(*s);
-                // if the start key is not there,
-                // skip to the key after where it should be.
-                if !exists {
-                    if let Some(data) = self.c.current() {
-                        if data.key() < *s {
-                            self.c.next();
-                        }
-                    }
+        if !self.comparable() {
+            return None;
+        }
+        let next = if !self.c.next_called {
+            let start = self.bounds.start_key();
+            if let Bound::Included(s) | Bound::Excluded(s) = start {
+                self.c.seek(s);
+            }
+            // the seek leaves us "just before" the start key,
+            // so skip forward until we are inside the range.
+            loop {
+                let data = self.c.next()?;
+                if after_start(start, data.key(), self.c.comparator()) {
+                    break data;
                 }
             }
+        } else {
+            self.c.next()?
+        };
+        if before_end(self.bounds.end_key(), next.key(), self.c.comparator()) {
+            Some(next)
+        } else {
+            None
         }
-        let next = self.c.next();
-        match next {
-            Some(data) => match self.bounds.end_bound() {
-                Bound::Excluded(e) => {
-                    if data.key() < *e {
-                        Some(data)
-                    } else {
-                        None
-                    }
+    }
+}
+
+impl<'r, 'b, 'tx, R> DoubleEndedIterator for Range<'r, 'b, 'tx, R>
+where
+    R: KeyRange,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if !self.comparable() {
+            return None;
+        }
+        let next = if !self.c.next_back_called {
+            let end = self.bounds.end_key();
+            if let Bound::Included(e) | Bound::Excluded(e) = end {
+                self.c.seek_back(e);
+            }
+            // the seek may leave us just after the end key,
+            // so skip back until we are inside the range.
+            loop {
+                let data = self.c.next_back()?;
+                if before_end(end, data.key(), self.c.comparator()) {
+                    break data;
                 }
-                Bound::Included(e) => {
-                    if data.key() <= *e {
-                        Some(data)
-                    } else {
-                        None
+            }
+        } else {
+            self.c.next_back()?
+        };
+        if after_start(self.bounds.start_key(), next.key(), self.c.comparator()) {
+            Some(next)
+        } else {
+            None
+        }
+    }
+}
+
+impl<R> Range<'_, '_, '_, R>
+where
+    R: KeyRange,
+{
+    // Keys can only be checked against the bounds with the bucket's comparator,
+    // so a bucket opened without it can only be iterated over in full.
+    fn comparable(&self) -> bool {
+        self.c.comparator().is_known()
+            || matches!(
+                (self.bounds.start_key(), self.bounds.end_key()),
+                (Bound::Unbounded, Bound::Unbounded)
+            )
+    }
+}
+
+fn after_start(start: Bound<&[u8]>, key: &[u8], comparator: Comparator) -> bool {
+    match start {
+        Bound::Included(s) => comparator.compare(key, s).is_ge(),
+        Bound::Excluded(s) => comparator.compare(key, s).is_gt(),
+        Bound::Unbounded => true,
+    }
+}
+
+fn before_end(end: Bound<&[u8]>, key: &[u8], comparator: Comparator) -> bool {
+    match end {
+        Bound::Included(e) => comparator.compare(key, e).is_le(),
+        Bound::Excluded(e) => comparator.compare(key, e).is_lt(),
+        Bound::Unbounded => true,
+    }
+}
+
+/// An iterator over the data in a bucket whose keys start with a given prefix.
+///
+/// Created by the [`prefix`](struct.Bucket.html#method.prefix) function on a [`Bucket`].
+pub struct Prefix<'b, 'tx, P: AsRef<[u8]>> {
+    pub(crate) c: Cursor<'b, 'tx>,
+    pub(crate) prefix: P,
+    pub(crate) done: bool,
+}
+
+impl<'b, 'tx, P: AsRef<[u8]>> Iterator for Prefix<'b, 'tx, P> {
+    type Item = Data<'b, 'tx>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || !self.c.comparator().is_known() {
+            return None;
+        }
+        let prefix = self.prefix.as_ref();
+        let next = if !self.c.next_called {
+            self.c.seek(prefix);
+            // the seek leaves us "just before" the prefix,
+            // so skip forward until we reach it.
+            loop {
+                match self.c.next() {
+                    Some(data) if self.c.comparator().compare(data.key(), prefix).is_lt() => {
+                        continue
                     }
+                    next => break next,
                 }
-                Bound::Unbounded => Some(data),
-            },
-            None => None,
+            }
+        } else {
+            self.c.next()
+        };
+        match next {
+            Some(data) if data.key().starts_with(prefix) => Some(data),
+            _ => {
+                self.done = true;
+                None
+            }
         }
     }
 }
@@ -273,26 +839,43 @@ where
     fn next(&mut self) -> Option<Self::Item> {
         for data in self.i.by_ref() {
             if let Data::Bucket(bucket_data) = data {
-                let mut b = self.bucket.borrow_mut();
-                if let Ok(r) = b.get_bucket(&bucket_data) {
-                    return Some((
-                        bucket_data,
-                        Bucket {
-                            writable: self.writable,
-                            freelist: self.freelist.clone(),
-                            inner: r,
-                            _phantom: PhantomData,
-                        },
-                    ));
-                } else {
-                    panic!("Could not find bucket")
-                }
+                return Some(self.open(bucket_data));
             }
         }
         None
     }
 }
 
+impl<'b, 'tx: 'b, I> DoubleEndedIterator for Buckets<'b, 'tx, I>
+where
+    I: DoubleEndedIterator<Item = Data<'b, 'tx>>,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        for data in self.i.by_ref().rev() {
+            if let Data::Bucket(bucket_data) = data {
+                return Some(self.open(bucket_data));
+            }
+        }
+        None
+    }
+}
+
+impl<'b, 'tx: 'b, I> Buckets<'b, 'tx, I> {
+    fn open(&self, bucket_data: BucketName<'b, 'tx>) -> (BucketName<'b, 'tx>, Bucket<'b, 'tx>) {
+        let mut b = self.bucket.borrow_mut();
+        let r = b.open_bucket((&bucket_data).to_bytes(), bucket_data.meta);
+        (
+            bucket_data,
+            Bucket {
+                writable: self.writable,
+                freelist: self.freelist.clone(),
+                inner: r,
+                _phantom: PhantomData,
+            },
+        )
+    }
+}
+
 pub trait ToBuckets<'b, 'tx: 'b>: Iterator<Item = Data<'b, 'tx>> + Sized {
     fn to_buckets(self) -> Buckets<'b, 'tx, Self>;
 }
@@ -314,7 +897,7 @@ impl<'b, 'tx: 'b> ToBuckets<'b, 'tx> for Cursor<'b, 'tx> {
 
 impl<'r, 'b, 'tx: 'b, R> ToBuckets<'b, 'tx> for Range<'r, 'b, 'tx, R>
 where
-    R: RangeBounds<&'r [u8]>,
+    R: KeyRange,
 {
     fn to_buckets(self) -> Buckets<'b, 'tx, Self> {
         let freelist = self.c.freelist.clone();
@@ -330,6 +913,21 @@ where
     }
 }
 
+impl<'b, 'tx: 'b, P: AsRef<[u8]>> ToBuckets<'b, 'tx> for Prefix<'b, 'tx, P> {
+    fn to_buckets(self) -> Buckets<'b, 'tx, Self> {
+        let freelist = self.c.freelist.clone();
+        let bucket = self.c.bucket.clone();
+        let writable = self.c.writable;
+        Buckets {
+            i: self,
+            bucket,
+            freelist,
+            writable,
+            _phantom: PhantomData,
+        }
+    }
+}
+
 /// An iterator over a bucket's key / value pairs.
 pub struct KVPairs<I> {
     pub(crate) i: I,
@@ -351,6 +949,20 @@ where
     }
 }
 
+impl<'b, 'tx, I> DoubleEndedIterator for KVPairs<I>
+where
+    I: DoubleEndedIterator<Item = Data<'b, 'tx>>,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        for data in self.i.by_ref().rev() {
+            if let Data::KeyValue(kv) = data {
+                return Some(kv);
+            }
+        }
+        None
+    }
+}
+
 pub trait ToKVPairs<'b, 'tx>: Iterator<Item = Data<'b, 'tx>> + Sized {
     fn to_kv_pairs(self) -> KVPairs<Self>;
 }
@@ -363,15 +975,22 @@ impl<'b, 'tx> ToKVPairs<'b, 'tx> for Cursor<'b, 'tx> {
 
 impl<'r, 'b, 'tx, R> ToKVPairs<'b, 'tx> for Range<'r, 'b, 'tx, R>
 where
-    R: RangeBounds<&'r [u8]>,
+    R: KeyRange,
 {
     fn to_kv_pairs(self) -> KVPairs<Self> {
         KVPairs { i: self }
     }
 }
 
+impl<'b, 'tx, P: AsRef<[u8]>> ToKVPairs<'b, 'tx> for Prefix<'b, 'tx, P> {
+    fn to_kv_pairs(self) -> KVPairs<Self> {
+        KVPairs { i: self }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::Cursor;
     use crate::{db::DB, errors::Result, testutil::RandomFile};
 
     #[test]
@@ -430,6 +1049,25 @@ mod tests {
             // There should be no more buckets
             assert!(kvpairs.next().is_none());
         }
+        // Make sure we can iterate over everything in reverse
+        {
+            let tx = db.tx(false)?;
+            let b = tx.get_bucket("abc")?;
+            let names: Vec<Vec<u8>> = b.buckets().rev().map(|(n, _)| n.name().to_vec()).collect();
+            assert_eq!(names, vec![b"f".to_vec(), b"d".to_vec(), b"b".to_vec()]);
+            let keys: Vec<Vec<u8>> = b.kv_pairs().rev().map(|kv| kv.key().to_vec()).collect();
+            assert_eq!(keys, vec![b"e".to_vec(), b"c".to_vec(), b"a".to_vec()]);
+
+            let mut cursor = b.cursor();
+            assert_eq!(Cursor::last(&mut cursor).unwrap().key(), b"f");
+            assert_eq!(cursor.prev().unwrap().key(), b"e");
+            assert_eq!(cursor.next().unwrap().key(), b"f");
+            assert!(cursor.next().is_none());
+            assert!(cursor.seek("c"));
+            assert_eq!(cursor.prev().unwrap().key(), b"b");
+            assert_eq!(cursor.first().unwrap().key(), b"a");
+            assert!(cursor.prev().is_none());
+        }
 
         db.check()
     }
@@ -1,6 +1,6 @@
-use std::marker::PhantomData;
+use std::{fmt, marker::PhantomData};
 
-use crate::{bytes::Bytes, node::Leaf, ToBytes};
+use crate::{bucket::BucketMeta, bytes::Bytes, errors::Result, node::Leaf, ToBytes};
 
 /// Key / Value or Bucket Data
 ///
@@ -44,6 +44,7 @@ impl Data<'_, '_> {
     /// Asserts that the `Data` is a `KVPair` and returns the inner data
     ///
     /// Panics if the data is a Bucket.
+    #[allow(mismatched_lifetime_syntaxes)]
     pub fn kv(&self) -> &KVPair {
         if let Self::KeyValue(kv) = self {
             return kv;
@@ -59,11 +60,21 @@ impl Data<'_, '_> {
     }
 }
 
-impl<'tx> From<Leaf<'tx>> for Data<'_, 'tx> {
-    fn from(val: Leaf<'tx>) -> Self {
-        match val {
-            Leaf::Bucket(name, _) => Data::Bucket(BucketName::new(name)),
-            Leaf::Kv(key, value) => Data::KeyValue(KVPair::new(key, value)),
+impl<'tx> Data<'_, 'tx> {
+    pub(crate) fn key_bytes(&self) -> Bytes<'tx> {
+        match self {
+            Self::Bucket(b) => b.name.clone(),
+            Self::KeyValue(kv) => kv.key.clone(),
+        }
+    }
+}
+
+impl<'tx> Data<'_, 'tx> {
+    // Fails if the value can't be decompressed.
+    pub(crate) fn from_leaf(leaf: Leaf<'tx>) -> Result<Self> {
+        match leaf {
+            Leaf::Bucket(name, meta) => Ok(Data::Bucket(BucketName::new(name, meta))),
+            Leaf::Kv(key, value, c) => Ok(Data::KeyValue(KVPair::new(key, c.decompress(value)?))),
         }
     }
 }
@@ -97,16 +108,18 @@ impl<'tx> From<Leaf<'tx>> for Data<'_, 'tx> {
 /// # Ok(())
 /// # }
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Clone)]
 pub struct BucketName<'b, 'tx> {
     name: Bytes<'tx>,
+    pub(crate) meta: BucketMeta,
     _phantom: PhantomData<&'b ()>,
 }
 
 impl<'tx> BucketName<'_, 'tx> {
-    pub(crate) fn new(name: Bytes<'tx>) -> Self {
+    pub(crate) fn new(name: Bytes<'tx>, meta: BucketMeta) -> Self {
         BucketName {
             name,
+            meta,
             _phantom: PhantomData,
         }
     }
@@ -117,6 +130,23 @@ impl<'tx> BucketName<'_, 'tx> {
     }
 }
 
+// Buckets are identified by their name alone
+impl PartialEq for BucketName<'_, '_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for BucketName<'_, '_> {}
+
+impl fmt::Debug for BucketName<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BucketName")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
 impl<'tx> ToBytes<'tx> for BucketName<'_, 'tx> {
     fn to_bytes(self) -> Bytes<'tx> {
         self.name
@@ -197,15 +227,6 @@ impl<'tx> From<(Bytes<'tx>, Bytes<'tx>)> for KVPair<'_, 'tx> {
     }
 }
 
-impl<'tx> From<Leaf<'tx>> for Option<KVPair<'_, 'tx>> {
-    fn from(val: Leaf<'tx>) -> Self {
-        match val {
-            Leaf::Bucket(_, _) => None,
-            Leaf::Kv(key, value) => Some(KVPair::new(key, value)),
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
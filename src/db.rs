@@ -3,16 +3,28 @@ use std::os::unix::fs::OpenOptionsExt;
 use std::{
     fs::{File, OpenOptions as FileOpenOptions},
     io::Write,
+    mem::size_of,
+    panic::{self, AssertUnwindSafe},
     path::Path,
-    sync::{Arc, Mutex, RwLock},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Condvar, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
 };
 
-use fs4::FileExt;
+use fs4::{lock_contended_error, FileExt};
 use memmap2::Mmap;
 use page_size::get as get_page_size;
 
 use crate::{
-    bucket::BucketMeta, errors::Result, freelist::Freelist, meta::Meta, page::Page, tx::Tx,
+    batch::{BatchQueue, DEFAULT_MAX_BATCH_DELAY, DEFAULT_MAX_BATCH_SIZE},
+    encryption::Cipher,
+    errors::{Error, Result},
+    freelist::Freelist,
+    meta::{Meta, OldMeta, RootMeta, FLAG_CHECKSUMS},
+    page::{Page, Pages},
+    tx::Tx,
 };
 
 const MAGIC_VALUE: u32 = 0x00AB_CDEF;
@@ -24,6 +36,29 @@ pub(crate) const MIN_ALLOC_SIZE: u64 = 8 * 1024 * 1024;
 // Number of pages to allocate when creating the database
 const DEFAULT_NUM_PAGES: usize = 32;
 
+/// Controls how hard a commit works to make sure its changes are on disk.
+///
+/// Set with [`OpenOptions::sync_mode`](struct.OpenOptions.html#method.sync_mode).
+/// `None` and `Periodic` trade durability for speed: if the machine crashes (or loses power)
+/// recently committed transactions may be lost. The OS is also free to write unsynced pages in any order,
+/// so a crash can leave the file corrupted, and you should check it with [`DB::verify`](struct.DB.html#method.verify) afterwards.
+/// Closing the process without syncing is safe, since the OS still writes everything eventually.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Every commit syncs the file's data and metadata to disk before returning. This is the default.
+    #[default]
+    Full,
+    /// Every commit syncs the file's data to disk using `fdatasync`, which skips updating metadata
+    /// like the file's modification time, but still makes sure the committed pages can be read back.
+    MetaOnly,
+    /// Commits never sync the file, leaving it to the OS to write the changes eventually.
+    /// Useful for bulk loads and tests. You can call [`DB::sync`](struct.DB.html#method.sync) when you're done.
+    None,
+    /// Commits never sync the file, but a background thread syncs it on this interval,
+    /// so at most this much recent work can be lost.
+    Periodic(Duration),
+}
+
 /// Options to configure how a [`DB`] is opened.
 ///
 /// This struct acts as a builder for a [`DB`] and allows you to specify
@@ -46,9 +81,9 @@ const DEFAULT_NUM_PAGES: usize = 32;
 /// # }
 /// ```
 pub struct OpenOptions {
-    pagesize: u64,
+    pub(crate) pagesize: u64,
     num_pages: usize,
-    flags: DBFlags,
+    pub(crate) flags: DBFlags,
 }
 
 impl OpenOptions {
@@ -57,11 +92,12 @@ impl OpenOptions {
         Self::default()
     }
 
-    /// Sets the pagesize for the database
+    /// Sets the pagesize for a new database file.
     ///
-    /// By default, your OS's pagesize is used as the database's pagesize, but if the file is
-    /// moved across systems with different page sizes, it is necessary to set the correct value.
-    /// Trying to open an existing database with the incorrect page size will result in a panic.
+    /// By default, your OS's pagesize is used as the database's pagesize.
+    /// Existing databases are always opened with the pagesize they were created with, which is read from the file,
+    /// so setting the pagesize when opening an existing database has no effect
+    /// unless the first meta page is damaged, and the pagesize is needed to find the second one.
     ///
     /// # Panics
     /// Will panic if you try to set the pagesize < 1024 bytes.
@@ -97,6 +133,26 @@ impl OpenOptions {
         self
     }
 
+    /// Enables or disables checking every page against its checksum before it is used.
+    ///
+    /// Each page is checked the first time a transaction reads it. Instead of returning the wrong data, anything that
+    /// reads a corrupted page returns an [`Error::Corruption`](enum.Error.html#variant.Corruption): bucket lookups,
+    /// [`put`](struct.Bucket.html#method.put), [`try_get`](struct.Bucket.html#method.try_get),
+    /// [`try_next`](struct.Cursor.html#method.try_next) and the iterators of a [`ReadTx`](struct.ReadTx.html),
+    /// which stop after returning the error.
+    /// [`get`](struct.Bucket.html#method.get) treats the key as missing, and iterating over a cursor stops at the page.
+    /// A corrupted freelist page is found when the database is opened (or a transaction is created).
+    ///
+    /// The default is `false`, since reads are no longer straight from the memory map.
+    /// You can check every page without enabling this using [`DB::verify`](struct.DB.html#method.verify).
+    /// Databases created by versions of jammdb before checksums were added don't have them, and this setting has no effect.
+    /// The same goes for databases that have been written to by one of those versions since,
+    /// which don't keep the checksums up to date.
+    pub fn verify_checksums(mut self, verify_checksums: bool) -> Self {
+        self.flags.verify_checksums = verify_checksums;
+        self
+    }
+
     /// Enables or disables the [MAP_POPULATE flag](MAP_POPULATE) for the `mmap` call, which will cause Linux to eagerly load pages into memory.
     ///
     /// The default is `false`, but you may enable this if your database file will stay smaller than your available memory.
@@ -120,38 +176,134 @@ impl OpenOptions {
         self
     }
 
+    /// Enables or disables automatically shrinking the database file.
+    ///
+    /// When enabled, every commit gives back any free pages at the end of the file,
+    /// and the file is truncated once there is more than a few megabytes of unused space at the end.
+    /// Like growing the file, truncating it has to wait until all open read-only transactions are finished.
+    ///
+    /// The default is `false`. You can also shrink the file manually using [`DB::shrink`](struct.DB.html#method.shrink).
+    pub fn auto_shrink(mut self, auto_shrink: bool) -> Self {
+        self.flags.auto_shrink = auto_shrink;
+        self
+    }
+
+    /// Opens the database in read-only mode.
+    ///
+    /// The file is opened without write permissions, and we take a [shared lock](https://en.wikipedia.org/wiki/File_locking)
+    /// instead of an exclusive one, so any number of processes can open the same database read-only at the same time.
+    /// Opening the database writable will still wait until all of the shared locks are released.
+    /// Only read-only transactions can be created, trying to create a writable transaction will return [`Error::ReadOnlyDB`](enum.Error.html#variant.ReadOnlyDB).
+    ///
+    /// The default is `false`. The file must already exist, a new database can't be created in read-only mode.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.flags.read_only = read_only;
+        self
+    }
+
+    /// Sets how long to wait for the lock on the database file when opening it.
+    ///
+    /// By default opening the database waits as long as it takes for other processes to release their locks on the file.
+    /// With a timeout, [`open`](#method.open) gives up and returns [`Error::DatabaseLocked`](enum.Error.html#variant.DatabaseLocked) instead.
+    /// A timeout of zero only tries to get the lock once, so the open never blocks.
+    pub fn lock_timeout(mut self, lock_timeout: Duration) -> Self {
+        self.flags.lock_timeout = Some(lock_timeout);
+        self
+    }
+
+    /// Sets how commits sync the database file to disk.
+    ///
+    /// The default is [`SyncMode::Full`], see [`SyncMode`] for the other options.
+    pub fn sync_mode(mut self, sync_mode: SyncMode) -> Self {
+        self.flags.sync_mode = sync_mode;
+        self
+    }
+
+    /// Sets the maximum number of calls to [`DB::batch`](struct.DB.html#method.batch) that can share a transaction.
+    ///
+    /// Once a batch is full it is run right away, without waiting for the rest of the
+    /// [`max_batch_delay`](#method.max_batch_delay). The default is 1000.
+    ///
+    /// # Panics
+    /// Will panic if you provide a value < 1.
+    pub fn max_batch_size(mut self, max_batch_size: usize) -> Self {
+        if max_batch_size < 1 {
+            panic!("Must have a batch size of at least 1");
+        }
+        self.flags.max_batch_size = max_batch_size;
+        self
+    }
+
+    /// Sets how long a call to [`DB::batch`](struct.DB.html#method.batch) waits for other calls to join its transaction.
+    ///
+    /// The default is 10 milliseconds.
+    pub fn max_batch_delay(mut self, max_batch_delay: Duration) -> Self {
+        self.flags.max_batch_delay = max_batch_delay;
+        self
+    }
+
+    /// Encrypts the database with the given 256 bit key.
+    ///
+    /// Every page except the two meta pages is encrypted with [XChaCha20-Poly1305](https://en.wikipedia.org/wiki/ChaCha20-Poly1305)
+    /// before it is written to the file, and decrypted (and authenticated) when it is read.
+    /// The meta pages only hold the database's layout, like the pagesize and the location of the root bucket.
+    /// Decrypted pages are cached for the life of each transaction, so reads are no longer free like they are from the memory map.
+    /// Each page also gives up 40 bytes to store its nonce and authentication tag.
+    ///
+    /// A new database is encrypted when it is created, and an existing database must be opened with the same key it was created with.
+    /// Opening it with the wrong key, or without a key, returns [`Error::InvalidKey`](enum.Error.html#variant.InvalidKey).
+    /// A page that has been tampered with, or moved to another page in the file, is only found once a transaction reads it,
+    /// and is handled like a corrupted page when [verifying checksums](#method.verify_checksums), returning
+    /// [`Error::Corruption`](enum.Error.html#variant.Corruption) once another page has been decrypted with the key.
+    /// An existing unencrypted database can't be encrypted in place, but you can [`compact`](struct.DB.html#method.compact_into)
+    /// it into a new file that is opened with a key.
+    ///
+    /// Only available with the `encryption` feature.
+    #[cfg(feature = "encryption")]
+    pub fn encryption_key(mut self, key: [u8; 32]) -> Self {
+        self.flags.cipher = Some(Arc::new(Cipher::new(&key)));
+        self
+    }
+
     /// Opens the database with the current options.
     ///
     /// If the file does not exist, it will initialize an empty database with a size of (`num_pages * pagesize`) bytes.
     /// If it does exist, the file is opened with both read and write permissions, and we attempt to create an
     /// [exclusive lock](https://en.wikipedia.org/wiki/File_locking) on the file. Getting the file lock will block until the lock
-    /// is released to prevent you from having two processes modifying the file at the same time. This lock is not foolproof though,
+    /// is released (or until the [`lock_timeout`](#method.lock_timeout) runs out) to prevent you from having two processes modifying the file at the same time. This lock is not foolproof though,
     /// so it is up to the user to make sure only one process has access to the database at a time (unless it is read-only).
     ///
     /// # Errors
     ///
-    /// Will return an error if there are issues creating a new file, opening an existing file, obtaining the file lock, or creating the memory map.
-    ///
-    /// # Panics
-    ///
-    /// Will panic if the pagesize the database is opened with is not the same as the pagesize it was created with.
+    /// Will return an error if there are issues creating a new file, opening an existing file, obtaining the file lock, or creating the memory map,
+    /// or an [`InvalidKey`](enum.Error.html#variant.InvalidKey) error if the database can't be decrypted with the [encryption key](#method.encryption_key).
+    /// If neither of the meta pages at the start of the file are valid you will get a [`NoValidMeta`](enum.Error.html#variant.NoValidMeta) error,
+    /// or a [`PagesizeMismatch`](enum.Error.html#variant.PagesizeMismatch) error if only the second one is valid and it has a different [pagesize](#method.pagesize).
+    /// [`DB::recover`](struct.DB.html#method.recover) may still be able to salvage the data from a damaged file.
     pub fn open<P: AsRef<Path>>(self, path: P) -> Result<DB> {
         let path: &Path = path.as_ref();
-        let file = if !path.exists() {
+        let file = if self.flags.read_only {
+            open_file(path, false, true, false)?
+        } else if !path.exists() {
             init_file(
                 path,
                 self.pagesize,
                 self.num_pages,
                 self.flags.direct_writes,
+                self.flags.cipher.as_deref(),
             )?
         } else {
-            open_file(path, false, self.flags.direct_writes)?
+            open_file(path, false, false, self.flags.direct_writes)?
         };
 
-        let db = DBInner::open(file, self.pagesize, self.flags)?;
-        Ok(DB {
-            inner: Arc::new(db),
-        })
+        let sync_mode = self.flags.sync_mode;
+        let db = DB {
+            inner: Arc::new(DBInner::open(file, self.pagesize, self.flags)?),
+        };
+        if let SyncMode::Periodic(interval) = sync_mode {
+            db.inner.start_flusher(interval)?;
+        }
+        Ok(db)
     }
 }
 
@@ -166,8 +318,16 @@ impl Default for OpenOptions {
             num_pages: DEFAULT_NUM_PAGES,
             flags: DBFlags {
                 strict_mode: false,
+                verify_checksums: false,
                 mmap_populate: false,
                 direct_writes: false,
+                auto_shrink: false,
+                read_only: false,
+                lock_timeout: None,
+                max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+                max_batch_delay: DEFAULT_MAX_BATCH_DELAY,
+                sync_mode: SyncMode::Full,
+                cipher: None,
             },
         }
     }
@@ -175,8 +335,16 @@ impl Default for OpenOptions {
 
 pub(crate) struct DBFlags {
     pub(crate) strict_mode: bool,
+    pub(crate) verify_checksums: bool,
     pub(crate) mmap_populate: bool,
     pub(crate) direct_writes: bool,
+    pub(crate) auto_shrink: bool,
+    pub(crate) read_only: bool,
+    pub(crate) lock_timeout: Option<Duration>,
+    pub(crate) max_batch_size: usize,
+    pub(crate) max_batch_delay: Duration,
+    pub(crate) sync_mode: SyncMode,
+    pub(crate) cipher: Option<Arc<Cipher>>,
 }
 
 /// A database
@@ -217,15 +385,164 @@ impl DB {
     /// Creates a [`Tx`].
     /// This transaction is either read-only or writable depending on the `writable` parameter.
     /// Please read the docs on a [`Tx`] for more details.
+    ///
+    /// # Errors
+    ///
+    /// Will return an [`Error::ReadOnlyDB`](enum.Error.html#variant.ReadOnlyDB) if you try to create a writable transaction
+    /// on a database that was opened in [read-only mode](struct.OpenOptions.html#method.read_only).
+    #[allow(mismatched_lifetime_syntaxes)]
     pub fn tx(&self, writable: bool) -> Result<Tx> {
         Tx::new(self, writable)
     }
 
+    /// Creates a [`Tx`] without waiting for other transactions.
+    ///
+    /// Only one writable transaction can be open at a time, so [`tx`](#method.tx) waits until the current one is finished.
+    /// Instead, this returns [`Error::WriterBusy`](enum.Error.html#variant.WriterBusy) right away.
+    /// Read-only transactions only have to wait while a writer is resizing the database file,
+    /// and will also return [`Error::WriterBusy`](enum.Error.html#variant.WriterBusy) if that is happening.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::{DB, Error};
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let db = DB::open("my.db")?;
+    /// match db.try_tx(true) {
+    ///     Ok(tx) => {
+    ///         // do whatever you want with the transaction
+    ///         tx.commit()?;
+    ///     }
+    ///     Err(Error::WriterBusy) => println!("try again later"),
+    ///     Err(e) => return Err(e),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn try_tx(&self, writable: bool) -> Result<Tx<'_>> {
+        Tx::try_new(self, writable)
+    }
+
+    /// Runs the closure in a writable transaction, and commits it if the closure returns `Ok`.
+    ///
+    /// If the closure returns an error (or panics) the transaction is rolled back instead.
+    /// The closure can return any error type that a jammdb [`Error`] can be converted into,
+    /// so errors from opening or committing the transaction are returned the same way as your own.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::DB;
+    /// # use jammdb::Error;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let db = DB::open("my.db")?;
+    /// let next_id = db.update(|tx| {
+    ///     let b = tx.get_or_create_bucket("users")?;
+    ///     let id = b.next_int();
+    ///     b.put(id.to_be_bytes(), "pj")?;
+    ///     Ok::<_, Error>(id)
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn update<T, E, F>(&self, f: F) -> std::result::Result<T, E>
+    where
+        F: FnOnce(&Tx) -> std::result::Result<T, E>,
+        E: From<Error>,
+    {
+        let tx = self.tx(true)?;
+        // Roll back before the panic continues, so the transaction's lock is released cleanly
+        // instead of being poisoned for every transaction after this one.
+        match panic::catch_unwind(AssertUnwindSafe(|| f(&tx))) {
+            Ok(Ok(value)) => {
+                tx.commit()?;
+                Ok(value)
+            }
+            Ok(Err(e)) => {
+                tx.rollback();
+                Err(e)
+            }
+            Err(payload) => {
+                tx.rollback();
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+
+    /// Runs the closure in a read-only transaction.
+    ///
+    /// Like [`update`](#method.update), the closure can return any error type that a jammdb [`Error`] can be converted into.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::DB;
+    /// # use jammdb::Error;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let db = DB::open("my.db")?;
+    /// let num_users = db.view(|tx| Ok::<_, Error>(tx.get_bucket("users")?.kv_pairs().count()))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn view<T, E, F>(&self, f: F) -> std::result::Result<T, E>
+    where
+        F: FnOnce(&Tx) -> std::result::Result<T, E>,
+        E: From<Error>,
+    {
+        let tx = self.tx(false)?;
+        f(&tx)
+    }
+
     /// Returns the database's pagesize.
     pub fn pagesize(&self) -> u64 {
         self.inner.pagesize
     }
 
+    /// Gives back any unused space at the end of the database file.
+    ///
+    /// Deleting data never makes the file smaller on its own, the freed pages are kept around to be reused.
+    /// This commits a transaction that drops all of the free pages at the end of the file, and then truncates the file.
+    /// Pages that may still be used by open read-only transactions are kept, and the file can't be truncated
+    /// until those transactions are finished, so this will wait for them like any other commit that has to resize the file.
+    ///
+    /// To reclaim free pages that aren't at the end of the file, use [`compact_into`](#method.compact_into).
+    pub fn shrink(&self) -> Result<()> {
+        // Every commit moves the freelist and root pages, and the old ones may be at the end of the file.
+        // Those pages can only be given back by the commit after the one that freed them,
+        // so keep going until the file stops getting smaller for a couple of commits in a row.
+        let mut num_pages = u64::MAX;
+        let mut stalled = 0;
+        while stalled < 2 {
+            let tx = self.tx(true)?;
+            {
+                let mut inner = tx.inner.borrow_mut();
+                inner.shrink = true;
+                // rewrite the root bucket so it can move away from the end of the file
+                inner.root.borrow_mut().dirty = true;
+            }
+            tx.commit()?;
+            let new_num_pages = self.inner.meta()?.num_pages;
+            if new_num_pages < num_pages {
+                stalled = 0;
+            } else {
+                stalled += 1;
+            }
+            num_pages = new_num_pages;
+        }
+        Ok(())
+    }
+
+    /// Syncs all committed changes to disk.
+    ///
+    /// When the database is opened with a [`SyncMode`] other than `Full`, commits may return before their
+    /// changes are safely on disk. This waits for any open writable transaction to finish, then syncs the file.
+    pub fn sync(&self) -> Result<()> {
+        self.inner.sync()
+    }
+
     #[doc(hidden)]
     pub fn check(&self) -> Result<()> {
         self.tx(false)?.check()
@@ -237,32 +554,54 @@ pub(crate) struct DBInner {
     pub(crate) freelist: Mutex<Freelist>,
     pub(crate) file: Mutex<File>,
     pub(crate) open_ro_txs: Mutex<Vec<u64>>,
+    // Number of open ReadTxs, which don't hold the mmap lock
+    snapshots: Mutex<usize>,
+    snapshot_closed: Condvar,
+    pub(crate) batch: BatchQueue,
+    // Dropped along with the database to stop the background flusher
+    stop_flusher: Mutex<Option<mpsc::Sender<()>>>,
     pub(crate) flags: DBFlags,
 
     pub(crate) pagesize: u64,
+    // Whether the file has page checksums, which never changes once it is created
+    checksums: bool,
 }
 
 impl DBInner {
     pub(crate) fn open(file: File, pagesize: u64, flags: DBFlags) -> Result<DBInner> {
-        file.lock_exclusive()?;
+        lock_file(&file, flags.read_only, flags.lock_timeout)?;
         let mmap = mmap(&file, flags.mmap_populate)?;
+        // The first meta page is always at the start of the file,
+        // so we can use the pagesize it was created with instead of the one we were given.
+        let pagesize = match read_meta(&mmap, 0, pagesize) {
+            Some(meta) if meta.pagesize >= 1024 => meta.pagesize,
+            _ => pagesize,
+        };
         let mmap = Mutex::new(Arc::new(mmap));
-        let db = DBInner {
+        let mut db = DBInner {
             data: mmap,
             mmap_lock: RwLock::new(()),
             freelist: Mutex::new(Freelist::new()),
 
             file: Mutex::new(file),
             open_ro_txs: Mutex::new(Vec::new()),
+            snapshots: Mutex::new(0),
+            snapshot_closed: Condvar::new(),
+            batch: BatchQueue::default(),
+            stop_flusher: Mutex::new(None),
 
             pagesize,
             flags,
+            checksums: false,
         };
 
         {
             let meta = db.meta()?;
-            let data = db.data.lock()?;
-            let free_pages = Page::from_buf(&data, meta.freelist_page, pagesize).freelist();
+            db.checksums = meta.has_checksums();
+            let pages = db.pages(db.data.lock()?.clone());
+            // The freelist page is rewritten on every commit, so if it was encrypted with a different key
+            // (or wasn't encrypted at all) we find out here instead of in the middle of a transaction.
+            let free_pages = pages.page(meta.freelist_page)?.freelist();
 
             if !free_pages.is_empty() {
                 db.freelist.lock()?.init(free_pages);
@@ -272,9 +611,83 @@ impl DBInner {
         Ok(db)
     }
 
+    pub(crate) fn pages(&self, data: Arc<Mmap>) -> Pages {
+        Pages::new(
+            data,
+            self.pagesize,
+            self.flags.cipher.clone(),
+            self.checksums && self.flags.verify_checksums,
+        )
+    }
+
+    // keeps the pages a ReadTx can see from being reused, and the file from being resized, until it is closed
+    pub(crate) fn open_snapshot(&self, tx_id: u64) -> Result<()> {
+        let mut open_ro_txs = self.open_ro_txs.lock()?;
+        open_ro_txs.push(tx_id);
+        open_ro_txs.sort_unstable();
+        *self.snapshots.lock()? += 1;
+        Ok(())
+    }
+
+    pub(crate) fn close_snapshot(&self, tx_id: u64) {
+        self.close_ro_tx(tx_id);
+        *self.snapshots.lock().unwrap() -= 1;
+        self.snapshot_closed.notify_all();
+    }
+
+    pub(crate) fn close_ro_tx(&self, tx_id: u64) {
+        let mut open_txs = self.open_ro_txs.lock().unwrap();
+        // this shouldn't fail, but isn't the end of the world if it does
+        if let Ok(index) = open_txs.binary_search(&tx_id) {
+            open_txs.remove(index);
+        }
+    }
+
+    // syncs the file on the given interval from a background thread, until the database is dropped
+    fn start_flusher(self: &Arc<Self>, interval: Duration) -> Result<()> {
+        let (stop, stopped) = mpsc::channel::<()>();
+        *self.stop_flusher.lock()? = Some(stop);
+        let db = Arc::downgrade(self);
+        std::thread::Builder::new()
+            .name(String::from("jammdb-flusher"))
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    match db.upgrade() {
+                        // errors will show up again on the next commit, there's no one to report them to here
+                        Some(db) => {
+                            let _ = db.sync();
+                        }
+                        None => return,
+                    }
+                }
+            })?;
+        Ok(())
+    }
+
+    pub(crate) fn sync(&self) -> Result<()> {
+        // wait for any writer to finish so everything it commits is included
+        let file = self.file.lock()?;
+        file.sync_all()?;
+        Ok(())
+    }
+
     pub(crate) fn resize(&self, file: &File, new_size: u64) -> Result<Arc<Mmap>> {
-        file.allocate(new_size)?;
+        let shrinking = new_size < file.metadata()?.len();
+        if !shrinking {
+            file.allocate(new_size)?;
+        }
         let _lock = self.mmap_lock.write()?;
+        // read-only transactions may still have the end of the file mapped,
+        // so wait until we have the lock before truncating it
+        if shrinking {
+            // ReadTxs can be sent across threads, so they can't hold onto the mmap lock and we have to wait for them separately.
+            // Growing the file doesn't need to wait, since each one keeps its own map of the file.
+            let mut snapshots = self.snapshots.lock()?;
+            while *snapshots > 0 {
+                snapshots = self.snapshot_closed.wait(snapshots)?;
+            }
+            file.set_len(new_size)?;
+        }
         let mut data = self.data.lock()?;
         let mmap = mmap(file, self.flags.mmap_populate)?;
         *data = Arc::new(mmap);
@@ -283,70 +696,83 @@ impl DBInner {
 
     pub(crate) fn meta(&self) -> Result<Meta> {
         let data = self.data.lock()?;
-
-        macro_rules! check_meta {
-            ($func:ident) => {{
-                let meta1 = Page::from_buf(&data, 0, self.pagesize).$func();
+        let mut newest: Option<Meta> = None;
+        for page_id in 0..2 {
+            if let Some(meta) = read_meta(&data, page_id, self.pagesize) {
                 // Double check that we have the right pagesize before we read the second page.
-                if meta1.valid() && meta1.pagesize != self.pagesize {
-                    assert_eq!(
-                        meta1.pagesize, self.pagesize,
-                        "Invalid pagesize from meta1 {}. Expected {}.",
-                        meta1.pagesize, self.pagesize
-                    );
+                if meta.pagesize != self.pagesize {
+                    return Err(Error::PagesizeMismatch {
+                        expected: self.pagesize,
+                        found: meta.pagesize,
+                    });
                 }
-                let meta2 = Page::from_buf(&data, 1, self.pagesize).$func();
-                match (meta1.valid(), meta2.valid()) {
-                    (true, true) => {
-                        assert_eq!(
-                            meta1.pagesize, self.pagesize,
-                            "Invalid pagesize from meta1 {}. Expected {}.",
-                            meta1.pagesize, self.pagesize
-                        );
-                        assert_eq!(
-                            meta2.pagesize, self.pagesize,
-                            "Invalid pagesize from meta2 {}. Expected {}.",
-                            meta2.pagesize, self.pagesize
-                        );
-                        if meta1.tx_id > meta2.tx_id {
-                            Some(meta1)
-                        } else {
-                            Some(meta2)
-                        }
-                    }
-                    (true, false) => {
-                        assert_eq!(
-                            meta1.pagesize, self.pagesize,
-                            "Invalid pagesize from meta1 {}. Expected {}.",
-                            meta1.pagesize, self.pagesize
-                        );
-                        Some(meta1)
-                    }
-                    (false, true) => {
-                        assert_eq!(
-                            meta2.pagesize, self.pagesize,
-                            "Invalid pagesize from meta2 {}. Expected {}.",
-                            meta2.pagesize, self.pagesize
-                        );
-                        Some(meta2)
-                    }
-                    (false, false) => None,
+                if !matches!(&newest, Some(newest) if meta.tx_id < newest.tx_id) {
+                    newest = Some(meta);
                 }
-            }};
+            }
         }
+        newest.ok_or(Error::NoValidMeta)
+    }
+}
+
+// Reads the meta page with the given id, if it is valid.
+// Databases created by versions <= 0.10 use the old meta format, so we check for either one.
+pub(crate) fn read_meta(data: &[u8], page_id: u64, pagesize: u64) -> Option<Meta> {
+    let end = (page_id * pagesize) as usize + size_of::<Page>() + size_of::<OldMeta>();
+    if data.len() < end {
+        return None;
+    }
+    let page = Page::from_buf(data, page_id, pagesize);
+    if page.page_type != Page::TYPE_META {
+        return None;
+    }
+    if page.meta().valid() {
+        Some(page.meta().clone())
+    } else if page.old_meta().valid() {
+        Some(page.old_meta().into())
+    } else {
+        None
+    }
+}
+
+// How long to sleep between attempts to get the file lock
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(10);
 
-        if let Some(meta) = check_meta!(meta) {
-            Ok(meta.clone())
-        } else if let Some(old_meta) = check_meta!(old_meta) {
-            Ok(old_meta.into())
+pub(crate) fn lock_file(file: &File, shared: bool, timeout: Option<Duration>) -> Result<()> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None if shared => return Ok(FileExt::lock_shared(file)?),
+        None => return Ok(FileExt::lock_exclusive(file)?),
+    };
+    let start = Instant::now();
+    loop {
+        let result = if shared {
+            FileExt::try_lock_shared(file)
         } else {
-            panic!("NO VALID META PAGES");
+            FileExt::try_lock_exclusive(file)
+        };
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == lock_contended_error().kind() => {
+                let elapsed = start.elapsed();
+                if elapsed >= timeout {
+                    return Err(Error::DatabaseLocked);
+                }
+                std::thread::sleep(LOCK_RETRY_INTERVAL.min(timeout - elapsed));
+            }
+            Err(e) => return Err(e.into()),
         }
     }
 }
 
-fn init_file(path: &Path, pagesize: u64, num_pages: usize, direct_write: bool) -> Result<File> {
-    let mut file = open_file(path, true, direct_write)?;
+fn init_file(
+    path: &Path,
+    pagesize: u64,
+    num_pages: usize,
+    direct_write: bool,
+    cipher: Option<&Cipher>,
+) -> Result<File> {
+    let mut file = open_file(path, true, false, direct_write)?;
     file.allocate(pagesize * (num_pages as u64))?;
     let mut buf = vec![0; (pagesize * 4) as usize];
     let mut get_page = |index: u64| {
@@ -365,11 +791,12 @@ fn init_file(path: &Path, pagesize: u64, num_pages: usize, direct_write: bool) -
         m.version = VERSION;
         m.pagesize = pagesize;
         m.freelist_page = 2;
-        m.root = BucketMeta {
+        m.root = RootMeta {
             root_page: 3,
             next_int: 0,
         };
         m.num_pages = 4;
+        m.flags = FLAG_CHECKSUMS;
         m.hash = m.hash_self();
     }
 
@@ -377,11 +804,19 @@ fn init_file(path: &Path, pagesize: u64, num_pages: usize, direct_write: bool) -
     p.id = 2;
     p.page_type = Page::TYPE_FREELIST;
     p.count = 0;
+    p.update_checksum();
 
     let p = get_page(3);
     p.id = 3;
     p.page_type = Page::TYPE_LEAF;
     p.count = 0;
+    p.update_checksum();
+
+    if let Some(cipher) = cipher {
+        for block in buf[(pagesize * 2) as usize..].chunks_mut(pagesize as usize) {
+            cipher.encrypt(block)?;
+        }
+    }
 
     file.write_all(&buf[..])?;
     file.flush()?;
@@ -390,6 +825,7 @@ fn init_file(path: &Path, pagesize: u64, num_pages: usize, direct_write: bool) -
 }
 
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 mod tests {
     use super::*;
     use crate::testutil::RandomFile;
@@ -434,7 +870,6 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
     fn test_different_pagesizes() {
         assert_ne!(get_page_size(), 5000);
         let random_file = RandomFile::new();
@@ -446,13 +881,356 @@ mod tests {
                 .unwrap();
             assert_eq!(db.pagesize(), 5000);
         }
-        DB::open(&random_file).unwrap();
+        {
+            let db = DB::open(&random_file).unwrap();
+            assert_eq!(db.pagesize(), 5000);
+            let tx = db.tx(true).unwrap();
+            tx.create_bucket("abc")
+                .unwrap()
+                .put("key", "value")
+                .unwrap();
+            tx.commit().unwrap();
+        }
+        {
+            let db = OpenOptions::new()
+                .pagesize(1024)
+                .open(&random_file)
+                .unwrap();
+            assert_eq!(db.pagesize(), 5000);
+            let tx = db.tx(false).unwrap();
+            let b = tx.get_bucket("abc").unwrap();
+            assert_eq!(b.get_kv("key").unwrap().value(), b"value");
+        }
+
+        // without the first meta page, the pagesize is needed to find the second one
+        let mut file = FileOpenOptions::new()
+            .write(true)
+            .open(&random_file)
+            .unwrap();
+        file.write_all(&[0; 5000]).unwrap();
+        drop(file);
+        assert_eq!(DB::open(&random_file).err(), Some(Error::NoValidMeta));
+        let db = OpenOptions::new()
+            .pagesize(5000)
+            .open(&random_file)
+            .unwrap();
+        assert_eq!(db.pagesize(), 5000);
+    }
+
+    #[test]
+    fn test_no_valid_meta() -> Result<()> {
+        let random_file = RandomFile::new();
+        OpenOptions::new().pagesize(1024).open(&random_file)?;
+        let mut file = FileOpenOptions::new().write(true).open(&random_file)?;
+        file.write_all(&[0; 2048])?;
+        drop(file);
+        let db = OpenOptions::new().pagesize(1024).open(&random_file);
+        assert_eq!(db.err(), Some(Error::NoValidMeta));
+        Ok(())
+    }
+
+    #[test]
+    fn test_shrink() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new()
+            .pagesize(1024)
+            .strict_mode(true)
+            .open(&random_file)?;
+        let size = || random_file.path.metadata().unwrap().len();
+        {
+            let tx = db.tx(true)?;
+            tx.create_bucket("keep")?.put("key", "value")?;
+            tx.commit()?;
+        }
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket("abc")?;
+            for i in 0..20_000_u32 {
+                b.put(i.to_be_bytes(), vec![0; 512])?;
+            }
+            tx.commit()?;
+        }
+        let full_size = size();
+        assert!(full_size > 2 * MIN_ALLOC_SIZE);
+
+        // hold onto a read-only transaction that can still see the deleted data
+        let ro_tx = db.tx(false)?;
+        {
+            let tx = db.tx(true)?;
+            tx.delete_bucket("abc")?;
+            tx.commit()?;
+        }
+        let handle = {
+            let db = db.clone();
+            std::thread::spawn(move || db.shrink())
+        };
+        // the shrink can't truncate the file until we're done with our transaction
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert_eq!(ro_tx.get_bucket("abc")?.kv_pairs().count(), 20_000);
+        assert_eq!(size(), full_size);
+        drop(ro_tx);
+        handle.join().unwrap()?;
+        // the deleted pages were still pending during that shrink, so shrink again
+        assert!(size() < full_size);
+        db.shrink()?;
+        let tx = db.tx(false)?;
+        assert_eq!(size(), tx.inner.borrow().meta.num_pages * 1024);
+        assert!(size() < 10 * 1024);
+        assert_eq!(
+            tx.get_bucket("keep")?.get_kv("key").unwrap().value(),
+            b"value"
+        );
+        drop(tx);
+        db.check()?;
+
+        // make sure the file can grow again
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket("abc")?;
+            for i in 0..1000_u32 {
+                b.put(i.to_be_bytes(), vec![0; 512])?;
+            }
+            tx.commit()?;
+        }
+        db.check()?;
+        drop(db);
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        db.check()
+    }
+
+    #[test]
+    fn test_auto_shrink() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new()
+            .pagesize(1024)
+            .auto_shrink(true)
+            .strict_mode(true)
+            // commits don't sync on their own, so the meta page has to be synced before truncating
+            .sync_mode(SyncMode::None)
+            .open(&random_file)?;
+        let size = || random_file.path.metadata().unwrap().len();
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket("abc")?;
+            for i in 0..40_000_u32 {
+                b.put(i.to_be_bytes(), vec![0; 512])?;
+            }
+            tx.commit()?;
+        }
+        assert!(size() > 3 * MIN_ALLOC_SIZE);
+        {
+            let tx = db.tx(true)?;
+            tx.delete_bucket("abc")?;
+            tx.commit()?;
+        }
+        // the next commits release the deleted pages and the old freelist page, and give them back
+        for name in ["def", "ghi", "jkl", "mno"] {
+            let tx = db.tx(true)?;
+            tx.create_bucket(name)?;
+            tx.commit()?;
+        }
+        let tx = db.tx(false)?;
+        let num_pages = tx.inner.borrow().meta.num_pages;
+        assert!(num_pages < 20);
+        assert_eq!(size(), num_pages * 1024 + MIN_ALLOC_SIZE);
+        drop(tx);
+        db.check()
+    }
+
+    #[test]
+    fn test_read_only() -> Result<()> {
+        let random_file = RandomFile::new();
+        // a new database can't be created in read-only mode
+        assert!(OpenOptions::new()
+            .pagesize(1024)
+            .read_only(true)
+            .open(&random_file)
+            .is_err());
+        assert!(!random_file.path.exists());
+        {
+            let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+            let tx = db.tx(true)?;
+            tx.create_bucket("abc")?.put("key", "value")?;
+            tx.commit()?;
+        }
+        let db1 = OpenOptions::new()
+            .pagesize(1024)
+            .read_only(true)
+            .open(&random_file)?;
+        // any number of read-only handles can share the file
+        let db2 = OpenOptions::new()
+            .pagesize(1024)
+            .read_only(true)
+            .open(&random_file)?;
+        for db in [&db1, &db2] {
+            let tx = db.tx(false)?;
+            assert_eq!(
+                tx.get_bucket("abc")?.get_kv("key").unwrap().value(),
+                b"value"
+            );
+            assert_eq!(db.tx(true).err(), Some(Error::ReadOnlyDB));
+            assert_eq!(db.shrink(), Err(Error::ReadOnlyDB));
+        }
+        // but nobody can get an exclusive lock while they're open
+        let file = open_file(&random_file, false, false, false)?;
+        assert!(FileExt::try_lock_exclusive(&file).is_err());
+        drop(db1);
+        drop(db2);
+        assert!(FileExt::try_lock_exclusive(&file).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_timeout() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        let open = |read_only, timeout| {
+            OpenOptions::new()
+                .pagesize(1024)
+                .read_only(read_only)
+                .lock_timeout(timeout)
+                .open(&random_file)
+        };
+        let timeout = Duration::from_millis(50);
+        let start = Instant::now();
+        assert_eq!(open(false, timeout).err(), Some(Error::DatabaseLocked));
+        assert!(start.elapsed() >= timeout);
+        assert_eq!(open(true, timeout).err(), Some(Error::DatabaseLocked));
+        assert_eq!(
+            open(true, Duration::ZERO).err(),
+            Some(Error::DatabaseLocked)
+        );
+        drop(db);
+
+        let db = open(true, Duration::ZERO)?;
+        // read-only handles only conflict with writable ones
+        let db2 = open(true, Duration::ZERO)?;
+        assert_eq!(
+            open(false, Duration::ZERO).err(),
+            Some(Error::DatabaseLocked)
+        );
+        drop(db);
+        drop(db2);
+
+        // the lock is released while we wait for it
+        let db = open(false, Duration::ZERO)?;
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            drop(db);
+        });
+        open(false, Duration::from_secs(10))?;
+        handle.join().unwrap();
+        Ok(())
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum MyError {
+        DB(Error),
+        NotFound,
+    }
+
+    impl From<Error> for MyError {
+        fn from(e: Error) -> MyError {
+            MyError::DB(e)
+        }
+    }
+
+    #[test]
+    fn test_update_view() -> std::result::Result<(), MyError> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        let id = db.update(|tx| {
+            let b = tx.create_bucket("abc")?;
+            b.put("a", "1")?;
+            Ok::<_, Error>(b.next_int())
+        })?;
+        assert_eq!(id, 1);
+        // errors roll back the transaction
+        let result = db.update(|tx| {
+            tx.get_bucket("abc")?.put("b", "2")?;
+            tx.create_bucket("abc")?;
+            Ok(())
+        });
+        assert_eq!(result, Err(MyError::DB(Error::BucketExists)));
+        let result: std::result::Result<(), MyError> = db.update(|tx| {
+            tx.get_bucket("abc")?.put("c", "3")?;
+            Err(MyError::NotFound)
+        });
+        assert_eq!(result, Err(MyError::NotFound));
+        // and so do panics
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            db.update(|tx| {
+                tx.get_bucket("abc")?.put("d", "4")?;
+                panic!("oh no");
+                #[allow(unreachable_code)]
+                Ok::<_, Error>(())
+            })
+        }));
+        assert!(result.is_err());
+        // without leaving the database unusable
+        db.update(|tx| tx.get_bucket("abc")?.put("f", "6").map(|_| ()))?;
+
+        let keys = db.view(|tx| {
+            let keys: Vec<Vec<u8>> = tx
+                .get_bucket("abc")?
+                .kv_pairs()
+                .map(|kv| kv.key().to_vec())
+                .collect();
+            Ok::<_, MyError>(keys)
+        })?;
+        assert_eq!(keys, vec![b"a".to_vec(), b"f".to_vec()]);
+        let result = db.view(|tx| {
+            tx.get_bucket("abc")?.put("e", "5")?;
+            Ok(())
+        });
+        assert_eq!(result, Err(MyError::DB(Error::ReadOnlyTx)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_modes() -> Result<()> {
+        for sync_mode in [
+            SyncMode::Full,
+            SyncMode::MetaOnly,
+            SyncMode::None,
+            SyncMode::Periodic(Duration::from_millis(10)),
+        ] {
+            let random_file = RandomFile::new();
+            let db = OpenOptions::new()
+                .pagesize(1024)
+                .sync_mode(sync_mode)
+                .open(&random_file)?;
+            for i in 0..10_u32 {
+                db.update(|tx| {
+                    tx.get_or_create_bucket("abc")?
+                        .put(i.to_be_bytes(), i.to_string())
+                        .map(|_| ())
+                })?;
+            }
+            db.sync()?;
+            // give the flusher a chance to run
+            std::thread::sleep(Duration::from_millis(50));
+            let inner = Arc::downgrade(&db.inner);
+            drop(db);
+            // the flusher may be in the middle of a sync, but it lets go of the database once it's done
+            let start = Instant::now();
+            while inner.upgrade().is_some() {
+                assert!(start.elapsed() < Duration::from_secs(5), "{:?}", sync_mode);
+                std::thread::sleep(Duration::from_millis(1));
+            }
+
+            let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+            db.check()?;
+            let tx = db.tx(false)?;
+            assert_eq!(tx.get_bucket("abc")?.kv_pairs().count(), 10);
+        }
+        Ok(())
     }
 }
 
 // Have different mmap functions for Unix and Windows
 #[cfg(unix)]
-fn mmap(file: &File, populate: bool) -> Result<Mmap> {
+pub(crate) fn mmap(file: &File, populate: bool) -> Result<Mmap> {
     use memmap2::MmapOptions;
 
     let mut options = MmapOptions::new();
@@ -467,7 +1245,7 @@ fn mmap(file: &File, populate: bool) -> Result<Mmap> {
 
 // On Windows there is no advice to give.
 #[cfg(windows)]
-fn mmap(file: &File, populate: bool) -> Result<Mmap> {
+pub(crate) fn mmap(file: &File, populate: bool) -> Result<Mmap> {
     let mmap = unsafe { Mmap::map(file)? };
     Ok(mmap)
 }
@@ -480,9 +1258,14 @@ const O_DIRECT: libc::c_int = 0;
 
 // Have different mmap functions for Unix and Windows
 #[cfg(unix)]
-fn open_file<P: AsRef<Path>>(path: P, create: bool, direct_write: bool) -> Result<File> {
+fn open_file<P: AsRef<Path>>(
+    path: P,
+    create: bool,
+    read_only: bool,
+    direct_write: bool,
+) -> Result<File> {
     let mut open_options = FileOpenOptions::new();
-    open_options.write(true).read(true);
+    open_options.write(!read_only).read(true);
     if create {
         open_options.create_new(true);
     }
@@ -493,9 +1276,14 @@ fn open_file<P: AsRef<Path>>(path: P, create: bool, direct_write: bool) -> Resul
 }
 
 #[cfg(windows)]
-fn open_file<P: AsRef<Path>>(path: P, create: bool, direct_write: bool) -> Result<File> {
+fn open_file<P: AsRef<Path>>(
+    path: P,
+    create: bool,
+    read_only: bool,
+    direct_write: bool,
+) -> Result<File> {
     let mut open_options = FileOpenOptions::new();
-    open_options.write(true).read(true);
+    open_options.write(!read_only).read(true);
     if create {
         open_options.create_new(true);
     }
@@ -0,0 +1,303 @@
+use std::mem::size_of;
+
+#[cfg(feature = "encryption")]
+use chacha20poly1305::{
+    aead::{AeadCore, AeadInPlace, KeyInit, OsRng},
+    Tag, XChaCha20Poly1305, XNonce,
+};
+
+#[cfg(feature = "encryption")]
+use crate::errors::Error;
+use crate::{errors::Result, page::Page};
+
+// The page header is everything before Page::ptr. It is left in plain text so we know how big each block is,
+// and is authenticated along with the encrypted data.
+#[cfg_attr(not(feature = "encryption"), allow(dead_code))]
+const HEADER_SIZE: usize = size_of::<Page>() - size_of::<u64>();
+// Page::page_type comes right after the page's id
+#[cfg(feature = "encryption")]
+const PAGE_TYPE_OFFSET: usize = size_of::<u64>();
+const NONCE_SIZE: usize = 24;
+const TAG_SIZE: usize = 16;
+
+// Number of bytes at the end of each encrypted block that hold its nonce and tag.
+pub(crate) const RESERVED_SIZE: u64 = (NONCE_SIZE + TAG_SIZE) as u64;
+
+// Encrypts and decrypts every page except the meta pages,
+// using XChaCha20-Poly1305 with a random nonce for each write.
+#[cfg(feature = "encryption")]
+pub(crate) struct Cipher(XChaCha20Poly1305);
+
+// Without the encryption feature there can't be a cipher, but the rest of the database can still be written as if there could.
+#[cfg(not(feature = "encryption"))]
+pub(crate) enum Cipher {}
+
+impl Cipher {
+    #[cfg(feature = "encryption")]
+    pub(crate) fn new(key: &[u8; 32]) -> Cipher {
+        Cipher(XChaCha20Poly1305::new(key.into()))
+    }
+
+    // Encrypts the block (a page plus its overflow pages) in place.
+    #[cfg(feature = "encryption")]
+    pub(crate) fn encrypt(&self, block: &mut [u8]) -> Result<()> {
+        debug_assert!(block.len() as u64 > RESERVED_SIZE + HEADER_SIZE as u64);
+        block[PAGE_TYPE_OFFSET] |= Page::FLAG_ENCRYPTED;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let data_end = block.len() - RESERVED_SIZE as usize;
+        let (header, rest) = block.split_at_mut(HEADER_SIZE);
+        let (data, trailer) = rest.split_at_mut(data_end - HEADER_SIZE);
+        let tag = self
+            .0
+            .encrypt_in_place_detached(&nonce, header, data)
+            .map_err(|_| Error::InvalidDB(String::from("failed to encrypt page")))?;
+        trailer[..NONCE_SIZE].copy_from_slice(&nonce);
+        trailer[NONCE_SIZE..].copy_from_slice(&tag);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    pub(crate) fn encrypt(&self, _block: &mut [u8]) -> Result<()> {
+        match *self {}
+    }
+
+    // Decrypts a copy of the block into a buffer that is aligned for a Page.
+    // Returns None if the block can't be authenticated, which usually means the key is wrong.
+    #[cfg(feature = "encryption")]
+    pub(crate) fn decrypt(&self, block: &[u8]) -> Option<Box<[u64]>> {
+        if block.len() as u64 <= RESERVED_SIZE + HEADER_SIZE as u64 {
+            return None;
+        }
+        let mut buf = vec![0_u64; block.len() / 8 + 1].into_boxed_slice();
+        let bytes =
+            unsafe { std::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u8, block.len()) };
+        bytes.copy_from_slice(block);
+        let data_end = block.len() - RESERVED_SIZE as usize;
+        let nonce = XNonce::from_slice(&block[data_end..data_end + NONCE_SIZE]);
+        let tag = Tag::from_slice(&block[data_end + NONCE_SIZE..]);
+        let (header, rest) = bytes.split_at_mut(HEADER_SIZE);
+        self.0
+            .decrypt_in_place_detached(nonce, header, &mut rest[..data_end - HEADER_SIZE], tag)
+            .ok()?;
+        header[PAGE_TYPE_OFFSET] &= !Page::FLAG_ENCRYPTED;
+        Some(buf)
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    pub(crate) fn decrypt(&self, _block: &[u8]) -> Option<Box<[u64]>> {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "encryption")]
+    use super::*;
+    #[cfg(feature = "encryption")]
+    use crate::{testutil::RandomFile, Data, OpenOptions};
+
+    #[test]
+    fn test_layout() {
+        assert_eq!(super::HEADER_SIZE, 32);
+        assert_eq!(super::RESERVED_SIZE, 40);
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_cipher() {
+        let cipher = Cipher::new(&[7; 32]);
+        let mut block = vec![0_u8; 1024];
+        block[0] = 42;
+        block[PAGE_TYPE_OFFSET] = Page::TYPE_LEAF;
+        block[100..111].copy_from_slice(b"hello world");
+        cipher.encrypt(&mut block).unwrap();
+        // the header is still readable, but the data isn't
+        assert_eq!(block[0], 42);
+        assert_eq!(
+            block[PAGE_TYPE_OFFSET],
+            Page::TYPE_LEAF | Page::FLAG_ENCRYPTED
+        );
+        assert!(!block.windows(11).any(|w| w == b"hello world"));
+
+        let decrypted = cipher.decrypt(&block).unwrap();
+        let page = unsafe { &*(decrypted.as_ptr() as *const Page) };
+        assert_eq!(page.id, 42);
+        assert_eq!(page.page_type, Page::TYPE_LEAF);
+        let bytes = unsafe { std::slice::from_raw_parts(decrypted.as_ptr() as *const u8, 1024) };
+        assert_eq!(&bytes[100..111], b"hello world");
+
+        // the wrong key, or any change to the block, fails
+        assert!(Cipher::new(&[8; 32]).decrypt(&block).is_none());
+        block[0] = 43;
+        assert!(cipher.decrypt(&block).is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_encrypted_db() -> Result<()> {
+        let random_file = RandomFile::new();
+        let key = [3; 32];
+        let secret = "the cake is a lie".repeat(20);
+        {
+            let db = OpenOptions::new()
+                .pagesize(1024)
+                .encryption_key(key)
+                .open(&random_file)?;
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket("secrets")?;
+            for i in 0..200_u64 {
+                b.put(i.to_be_bytes(), secret.as_str())?;
+            }
+            tx.commit()?;
+            assert!(db.verify(Default::default())?.is_ok());
+        }
+        // nothing is stored in plain text
+        let contents = std::fs::read(&random_file)?;
+        assert!(!contents.windows(17).any(|w| w == b"the cake is a lie"));
+        assert!(!contents.windows(7).any(|w| w == b"secrets"));
+
+        {
+            let db = OpenOptions::new()
+                .pagesize(1024)
+                .encryption_key(key)
+                .open(&random_file)?;
+            let tx = db.tx(true)?;
+            let b = tx.get_bucket("secrets")?;
+            assert_eq!(b.kv_pairs().count(), 200);
+            b.delete(0_u64.to_be_bytes())?;
+            tx.commit()?;
+
+            let tx = db.read_tx()?;
+            let b = tx.get_bucket("secrets")?;
+            assert_eq!(b.iter().count(), 199);
+            assert_eq!(
+                b.get_kv(1_u64.to_be_bytes())?.as_deref(),
+                Some(secret.as_bytes())
+            );
+            let tx = db.tx(false)?;
+            let b = tx.get_bucket("secrets")?;
+            for data in b.cursor() {
+                if let Data::KeyValue(kv) = data {
+                    assert_eq!(kv.value(), secret.as_bytes());
+                }
+            }
+        }
+
+        // the wrong key, or no key at all, can't open the database
+        let result = OpenOptions::new()
+            .pagesize(1024)
+            .encryption_key([4; 32])
+            .open(&random_file);
+        assert_eq!(result.err(), Some(Error::InvalidKey));
+        let result = OpenOptions::new().pagesize(1024).open(&random_file);
+        assert_eq!(result.err(), Some(Error::InvalidKey));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_tampered_page() -> Result<()> {
+        let random_file = RandomFile::new();
+        let options = || OpenOptions::new().pagesize(1024).encryption_key([3; 32]);
+        let root_page = {
+            let db = options().open(&random_file)?;
+            db.update(|tx| tx.create_bucket("secrets")?.put("a", "1").map(|_| ()))?;
+            let tx = db.tx(false)?;
+            let b = tx.get_bucket("secrets")?;
+            let root_page = b.inner.borrow().meta.root_page;
+            root_page
+        };
+        let mut contents = std::fs::read(&random_file)?;
+        contents[root_page as usize * 1024 + 100] ^= 1;
+        std::fs::write(&random_file, contents)?;
+
+        // the page is only read once a transaction gets to it,
+        // and other pages have already been decrypted by then, so the key must be right
+        let db = options().open(&random_file)?;
+        let corruption = Some(Error::Corruption { page_id: root_page });
+        {
+            let tx = db.tx(true)?;
+            {
+                let b = tx.get_bucket("secrets")?;
+                assert!(b.get("a").is_none());
+                assert_eq!(b.try_get("a").err(), corruption);
+                assert_eq!(b.put("b", "2").err(), corruption);
+            }
+            // nothing was changed, so there's nothing to write
+            tx.commit()?;
+        }
+        let tx = db.read_tx()?;
+        let b = tx.get_bucket("secrets")?;
+        assert_eq!(b.get("a").err(), corruption);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_moved_page() -> Result<()> {
+        let random_file = RandomFile::new();
+        let options = || OpenOptions::new().pagesize(1024).encryption_key([3; 32]);
+        let (abc, def) = {
+            let db = options().open(&random_file)?;
+            db.update(|tx| {
+                tx.create_bucket("abc")?.put("a", "1")?;
+                tx.create_bucket("def")?.put("d", "4")?;
+                Ok::<_, Error>(())
+            })?;
+            let tx = db.tx(false)?;
+            let abc = tx.get_bucket("abc")?.inner.borrow().meta.root_page;
+            let def = tx.get_bucket("def")?.inner.borrow().meta.root_page;
+            (abc, def)
+        };
+        // copy one bucket's page over the other one, which still decrypts just fine
+        let mut contents = std::fs::read(&random_file)?;
+        contents.copy_within(
+            def as usize * 1024..(def as usize + 1) * 1024,
+            abc as usize * 1024,
+        );
+        std::fs::write(&random_file, contents)?;
+
+        let db = options().open(&random_file)?;
+        let tx = db.read_tx()?;
+        assert_eq!(
+            tx.get_bucket("abc")?.get("d").err(),
+            Some(Error::Corruption { page_id: abc })
+        );
+        assert!(tx.get_bucket("def")?.get("d")?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_unencrypted_db() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        db.update(|tx| {
+            let b = tx.create_bucket("abc")?;
+            for i in 0..100_u64 {
+                b.put(i.to_be_bytes(), "hello world".repeat(10))?;
+            }
+            Ok::<_, Error>(())
+        })?;
+        drop(db);
+        let result = OpenOptions::new()
+            .pagesize(1024)
+            .encryption_key([3; 32])
+            .open(&random_file);
+        assert_eq!(result.err(), Some(Error::InvalidKey));
+
+        // compacting into a new file is how you encrypt an existing database
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        let encrypted = RandomFile::new();
+        let options = || OpenOptions::new().pagesize(1024).encryption_key([3; 32]);
+        db.compact_into(&encrypted, options())?;
+        let contents = std::fs::read(&encrypted)?;
+        assert!(!contents.windows(11).any(|w| w == b"hello world"));
+        let db = options().open(&encrypted)?;
+        assert!(db.verify(Default::default())?.is_ok());
+        db.view(|tx| {
+            assert_eq!(tx.get_bucket("abc")?.kv_pairs().count(), 100);
+            Ok::<_, Error>(())
+        })
+    }
+}
@@ -15,6 +15,12 @@ pub enum Error {
     IncompatibleValue,
     /// Tried to write to a read only transaction
     ReadOnlyTx,
+    /// Tried to create a writable transaction on a database that was opened read-only
+    ReadOnlyDB,
+    /// Another process holds a lock on the database file, and it wasn't released before the [lock timeout](struct.OpenOptions.html#method.lock_timeout)
+    DatabaseLocked,
+    /// Tried to create a writable transaction with [`try_tx`](struct.DB.html#method.try_tx) while another one is open
+    WriterBusy,
     /// Wrapper around a [`std::io::Error`] that occurred while opening the file or writing to it
     Io(std::io::Error),
     /// Wrapper around a [`PoisonError`]
@@ -23,6 +29,35 @@ pub enum Error {
     InvalidDB(String),
     /// Errors that can occur during allocation
     Alloc(std::alloc::LayoutError),
+    /// A key or value couldn't be encoded or decoded, see the [`keys`](keys/index.html) module and [`TypedBucket`](struct.TypedBucket.html),
+    /// or a bucket uses a [`Compression`](enum.Compression.html) whose feature isn't enabled
+    Codec(String),
+    /// Tried to open a bucket with a different [`Comparator`](struct.Comparator.html) than the one it was created with
+    ComparatorMismatch(&'static str),
+    /// Tried to look up a key in a bucket that was opened without its [`Comparator`](struct.Comparator.html), like the buckets
+    /// returned by [`Tx::buckets`](struct.Tx.html#method.buckets), or to compact a database without one of its comparators
+    ComparatorRequired,
+    /// The database's pages couldn't be decrypted, because the [encryption key](struct.OpenOptions.html#method.encryption_key) is wrong,
+    /// or because the database is encrypted and no key was given (or a key was given but it isn't encrypted)
+    InvalidKey,
+    /// A page doesn't match its checksum, found while [verifying checksums](struct.OpenOptions.html#method.verify_checksums)
+    /// or by [`DB::verify`](struct.DB.html#method.verify)
+    Corruption {
+        /// The corrupted page
+        page_id: u64,
+    },
+    /// Neither of the meta pages at the start of the database file are valid, see [`DB::recover`](struct.DB.html#method.recover)
+    NoValidMeta,
+    /// An [`AsyncDB`](struct.AsyncDB.html) operation was cancelled before it finished, because the writer thread stopped
+    /// or the tokio runtime shut down (or there was no runtime to run it on)
+    Cancelled(&'static str),
+    /// Tried to open a database with a different [pagesize](struct.OpenOptions.html#method.pagesize) than it was created with
+    PagesizeMismatch {
+        /// The pagesize the database was opened with
+        expected: u64,
+        /// The pagesize stored in the database's meta page
+        found: u64,
+    },
 }
 
 impl StdError for Error {}
@@ -35,10 +70,32 @@ impl fmt::Display for Error {
             Error::KeyValueMissing => write!(f, "Key / Value pair does not exist"),
             Error::IncompatibleValue => write!(f, "Value not compatible"),
             Error::ReadOnlyTx => write!(f, "Cannot write in a read-only transaction"),
+            Error::ReadOnlyDB => write!(f, "Cannot write to a database opened in read-only mode"),
+            Error::DatabaseLocked => write!(f, "Database is locked by another process"),
+            Error::WriterBusy => write!(f, "Another writable transaction is already open"),
             Error::Io(e) => write!(f, "IO Error: {}", e),
             Error::Sync(s) => write!(f, "Sync Error: {}", s),
             Error::InvalidDB(s) => write!(f, "Invalid DB: {}", s),
             Error::Alloc(e) => write!(f, "Allocation error: {}", e),
+            Error::Codec(s) => write!(f, "Codec error: {}", s),
+            Error::ComparatorMismatch(name) => write!(
+                f,
+                "Bucket was created with a different comparator than {:?}",
+                name
+            ),
+            Error::ComparatorRequired => write!(f, "Bucket was opened without its comparator"),
+            Error::InvalidKey => write!(
+                f,
+                "Encryption key is missing or does not match the database"
+            ),
+            Error::Corruption { page_id } => write!(f, "Page {} is corrupted", page_id),
+            Error::NoValidMeta => write!(f, "No valid meta pages"),
+            Error::Cancelled(s) => write!(f, "Cancelled: {}", s),
+            Error::PagesizeMismatch { expected, found } => write!(
+                f,
+                "Invalid pagesize from meta page {}. Expected {}.",
+                found, expected
+            ),
         }
     }
 }
@@ -69,8 +126,28 @@ impl PartialEq for Error {
             (Error::KeyValueMissing, Error::KeyValueMissing) => true,
             (Error::IncompatibleValue, Error::IncompatibleValue) => true,
             (Error::ReadOnlyTx, Error::ReadOnlyTx) => true,
+            (Error::ReadOnlyDB, Error::ReadOnlyDB) => true,
+            (Error::DatabaseLocked, Error::DatabaseLocked) => true,
+            (Error::WriterBusy, Error::WriterBusy) => true,
             (Error::Sync(s1), Error::Sync(s2)) => s1 == s2,
             (Error::InvalidDB(s1), Error::InvalidDB(s2)) => s1 == s2,
+            (Error::Codec(s1), Error::Codec(s2)) => s1 == s2,
+            (Error::ComparatorMismatch(s1), Error::ComparatorMismatch(s2)) => s1 == s2,
+            (Error::ComparatorRequired, Error::ComparatorRequired) => true,
+            (Error::InvalidKey, Error::InvalidKey) => true,
+            (Error::Corruption { page_id: p1 }, Error::Corruption { page_id: p2 }) => p1 == p2,
+            (Error::NoValidMeta, Error::NoValidMeta) => true,
+            (Error::Cancelled(s1), Error::Cancelled(s2)) => s1 == s2,
+            (
+                Error::PagesizeMismatch {
+                    expected: e1,
+                    found: f1,
+                },
+                Error::PagesizeMismatch {
+                    expected: e2,
+                    found: f2,
+                },
+            ) => e1 == e2 && f1 == f2,
             _ => false,
         }
     }
@@ -96,6 +173,18 @@ mod tests {
             format!("{}", Error::ReadOnlyTx),
             "Cannot write in a read-only transaction"
         );
+        assert_eq!(
+            format!("{}", Error::ReadOnlyDB),
+            "Cannot write to a database opened in read-only mode"
+        );
+        assert_eq!(
+            format!("{}", Error::DatabaseLocked),
+            "Database is locked by another process"
+        );
+        assert_eq!(
+            format!("{}", Error::WriterBusy),
+            "Another writable transaction is already open"
+        );
 
         assert_eq!(
             format!(
@@ -109,5 +198,40 @@ mod tests {
             format!("{}", Error::InvalidDB(String::from("uh oh"))),
             "Invalid DB: uh oh"
         );
+        assert_eq!(
+            format!("{}", Error::Codec(String::from("bad key"))),
+            "Codec error: bad key"
+        );
+        assert_eq!(
+            format!("{}", Error::ComparatorMismatch("bytewise")),
+            "Bucket was created with a different comparator than \"bytewise\""
+        );
+        assert_eq!(
+            format!("{}", Error::ComparatorRequired),
+            "Bucket was opened without its comparator"
+        );
+        assert_eq!(
+            format!("{}", Error::InvalidKey),
+            "Encryption key is missing or does not match the database"
+        );
+        assert_eq!(
+            format!("{}", Error::Corruption { page_id: 42 }),
+            "Page 42 is corrupted"
+        );
+        assert_eq!(format!("{}", Error::NoValidMeta), "No valid meta pages");
+        assert_eq!(
+            format!("{}", Error::Cancelled("writer thread stopped")),
+            "Cancelled: writer thread stopped"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                Error::PagesizeMismatch {
+                    expected: 4096,
+                    found: 1024
+                }
+            ),
+            "Invalid pagesize from meta page 1024. Expected 4096."
+        );
     }
 }
@@ -18,15 +18,18 @@ pub(crate) struct TxFreelist {
     pub(crate) inner: Freelist,
     pub(crate) pages: BTreeMap<u64, (NonNull<u8>, usize)>,
     pub(crate) arena: Bump,
+    // Bytes at the end of each allocation that can't be used, because they hold the page's encryption nonce and tag.
+    reserved: u64,
 }
 
 impl TxFreelist {
-    pub(crate) fn new(meta: Meta, inner: Freelist) -> TxFreelist {
+    pub(crate) fn new(meta: Meta, inner: Freelist, reserved: u64) -> TxFreelist {
         TxFreelist {
             meta,
             inner,
             pages: BTreeMap::new(),
             arena: Bump::new(),
+            reserved,
         }
     }
 
@@ -37,6 +40,12 @@ impl TxFreelist {
         }
     }
 
+    // gives back any free pages at the end of the file
+    pub(crate) fn trim(&mut self) {
+        self.meta.num_pages = self.inner.trim(self.meta.num_pages);
+    }
+
+    #[allow(clippy::manual_is_multiple_of)]
     pub(crate) fn allocate<'a>(&mut self, bytes: u64) -> Result<&'a mut Page> {
         assert!(
             bytes >= (size_of::<Page>() as u64),
@@ -45,10 +54,11 @@ impl TxFreelist {
             size_of::<Page>(),
             bytes < (size_of::<Page>() as u64)
         );
-        let num_pages = if (bytes % self.meta.pagesize) == 0 {
-            bytes / self.meta.pagesize
+        let total = bytes + self.reserved;
+        let num_pages = if (total % self.meta.pagesize) == 0 {
+            total / self.meta.pagesize
         } else {
-            (bytes / self.meta.pagesize) + 1
+            (total / self.meta.pagesize) + 1
         };
         let page_id = match self.inner.allocate(num_pages as usize) {
             Some(page_id) => page_id,
@@ -62,6 +72,9 @@ impl TxFreelist {
         let ptr = self
             .arena
             .alloc_layout(Layout::from_size_align(bytes as usize, 8)?);
+        // The page's checksum covers the padding in the header and in each element,
+        // which is never written, so it has to start out zeroed.
+        unsafe { ptr.as_ptr().write_bytes(0, bytes as usize) };
 
         let page = unsafe { &mut *(ptr.as_ptr() as *mut Page) };
         page.id = page_id;
@@ -159,6 +172,15 @@ impl Freelist {
         None
     }
 
+    // removes the free pages at the end of the file and returns the new number of pages.
+    // Pending pages may still be in use by open read-only transactions, so they are left alone.
+    pub(crate) fn trim(&mut self, mut num_pages: u64) -> u64 {
+        while self.free_pages.remove(&(num_pages - 1)) {
+            num_pages -= 1;
+        }
+        num_pages
+    }
+
     pub(crate) fn pages(&self) -> Vec<PageID> {
         let mut page_ids: Vec<PageID> = self.free_pages.iter().cloned().collect();
         for (_, pages) in self.pending_pages.iter() {
@@ -169,6 +191,17 @@ impl Freelist {
         page_ids
     }
 
+    pub(crate) fn free_count(&self) -> u64 {
+        self.free_pages.len() as u64
+    }
+
+    pub(crate) fn pending_count(&self) -> u64 {
+        self.pending_pages
+            .values()
+            .map(|pages| pages.len() as u64)
+            .sum()
+    }
+
     pub(crate) fn size(&self) -> u64 {
         let count = self.pages().len() as u64;
         HEADER_SIZE + (PAGE_ID_SIZE * count)
@@ -211,7 +244,7 @@ mod tests {
         assert_eq!(freelist.allocate(1), Some(6));
         assert_eq!(
             freelist.free_pages.iter().cloned().collect::<Vec<u64>>(),
-            vec![]
+            Vec::<u64>::new()
         );
         assert_eq!(freelist.allocate(1), None);
     }
@@ -272,6 +305,20 @@ mod tests {
         assert_eq!(freelist.pages(), vec![5, 7, 10]);
     }
 
+    #[test]
+    fn test_trim() {
+        let mut freelist = freelist_from_vec(vec![3, 5, 8, 9]);
+        freelist.free(1, 7);
+        // page 7 is still pending, so we can only remove 8 and 9
+        assert_eq!(freelist.trim(10), 8);
+        assert_eq!(freelist.pages(), vec![3, 5, 7]);
+        // nothing to trim if the last page is in use
+        assert_eq!(freelist.trim(12), 12);
+        freelist.release(2);
+        assert_eq!(freelist.trim(8), 7);
+        assert_eq!(freelist.pages(), vec![3, 5]);
+    }
+
     #[test]
     fn test_size() {
         let freelist = freelist_from_vec(vec![1, 2, 3]);
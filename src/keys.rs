@@ -0,0 +1,419 @@
+//! Order-preserving key encodings.
+//!
+//! Keys in a bucket are sorted by their bytes, so numbers, timestamps and compound keys
+//! only sort correctly if they are encoded carefully. [`OrderedKey`] encodes a value into a [`Key`]
+//! whose bytes sort the same way as the value itself, and [`FromOrderedKey`] decodes it again.
+//! A [`Key`] can be passed straight to [`put`](../struct.Bucket.html#method.put),
+//! [`get`](../struct.Bucket.html#method.get) and [`range`](../struct.Bucket.html#method.range).
+//!
+//! * Unsigned integers are stored big-endian, and signed integers are stored big-endian with the sign bit flipped.
+//! * Floats are sorted by [`f64::total_cmp`], so `-0.0` comes before `0.0` and `NaN` comes after infinity.
+//! * Strings have every `0x00` byte escaped as `0x00 0xFF` and end with `0x00 0x00`, so a string sorts before any
+//!   longer string that starts with it, even inside a tuple.
+//! * Tuples are their fields one after another, so they are sorted by their first field, then their second, and so on.
+//! * [`SystemTime`]s are stored as signed seconds and nanoseconds since the unix epoch, so times before the epoch work too.
+//!
+//! A [`TypedBucket`](../struct.TypedBucket.html) stores its keys with these encodings,
+//! so any type that implements both traits can be its key type.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use jammdb::{keys::{FromOrderedKey, OrderedKey}, DB};
+//! # use jammdb::Error;
+//!
+//! # fn main() -> Result<(), Error> {
+//! let db = DB::open("my.db")?;
+//! let tx = db.tx(true)?;
+//! let bucket = tx.create_bucket("temperatures")?;
+//! bucket.put(("berlin", -5_i64).to_key(), "cold")?;
+//! bucket.put(("berlin", 10_i64).to_key(), "nice")?;
+//! bucket.put(("cairo", 35_i64).to_key(), "hot")?;
+//!
+//! assert!(bucket.get(("berlin", -5_i64).to_key()).is_some());
+//! // every temperature in berlin below 0
+//! for data in bucket.range(("berlin", i64::MIN).to_key()..("berlin", 0_i64).to_key()) {
+//!     let (city, degrees) = <(String, i64)>::from_key(data.key())?;
+//!     println!("{} {}", city, degrees);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    bytes::{Bytes, ToBytes},
+    errors::{Error, Result},
+};
+
+const ESCAPE: u8 = 0x00;
+const ESCAPED_NULL: u8 = 0xFF;
+const END: u8 = 0x00;
+
+/// An encoded key, created by [`OrderedKey::to_key`].
+///
+/// Keys compare the same way as the values they were encoded from.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Key(Vec<u8>);
+
+impl Key {
+    /// Decodes the key.
+    pub fn decode<T: FromOrderedKey>(&self) -> Result<T> {
+        T::from_key(&self.0)
+    }
+
+    /// Returns the encoded bytes.
+    pub fn into_vec(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl AsRef<[u8]> for Key {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl<'a> ToBytes<'a> for Key {
+    fn to_bytes(self) -> Bytes<'a> {
+        self.0.to_bytes()
+    }
+}
+
+impl<'a> ToBytes<'a> for &Key {
+    fn to_bytes(self) -> Bytes<'a> {
+        self.0.clone().to_bytes()
+    }
+}
+
+/// A type that can be encoded into a [`Key`] that sorts the same way as the value.
+pub trait OrderedKey {
+    /// Appends the encoded value to the buffer.
+    fn write_key(&self, buf: &mut Vec<u8>);
+
+    /// Encodes the value into a key.
+    fn to_key(&self) -> Key {
+        let mut buf = Vec::new();
+        self.write_key(&mut buf);
+        Key(buf)
+    }
+}
+
+/// A type that can be decoded from a key written by [`OrderedKey`].
+pub trait FromOrderedKey: Sized {
+    /// Reads a value from the front of the bytes, and moves them past it.
+    fn read_key(bytes: &mut &[u8]) -> Result<Self>;
+
+    /// Decodes a whole key, like the ones returned by [`Data::key`](../enum.Data.html#method.key).
+    ///
+    /// Returns an error if there are bytes left over after the value.
+    fn from_key(mut bytes: &[u8]) -> Result<Self> {
+        let value = Self::read_key(&mut bytes)?;
+        if !bytes.is_empty() {
+            return Err(Error::Codec(String::from("trailing bytes after key")));
+        }
+        Ok(value)
+    }
+}
+
+fn take<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N]> {
+    if bytes.len() < N {
+        return Err(Error::Codec(String::from("unexpected end of key")));
+    }
+    let (head, rest) = bytes.split_at(N);
+    *bytes = rest;
+    let mut array = [0; N];
+    array.copy_from_slice(head);
+    Ok(array)
+}
+
+impl<T: OrderedKey + ?Sized> OrderedKey for &T {
+    fn write_key(&self, buf: &mut Vec<u8>) {
+        (**self).write_key(buf)
+    }
+}
+
+macro_rules! unsigned_key {
+    ($($t:ty),*) => (
+    $(
+        impl OrderedKey for $t {
+            fn write_key(&self, buf: &mut Vec<u8>) {
+                buf.extend_from_slice(&self.to_be_bytes());
+            }
+        }
+
+        impl FromOrderedKey for $t {
+            fn read_key(bytes: &mut &[u8]) -> Result<Self> {
+                Ok(<$t>::from_be_bytes(take(bytes)?))
+            }
+        }
+    )*
+)
+}
+
+unsigned_key!(u8, u16, u32, u64, u128);
+
+macro_rules! signed_key {
+    ($($t:ty => $u:ty),*) => (
+    $(
+        impl OrderedKey for $t {
+            fn write_key(&self, buf: &mut Vec<u8>) {
+                // flipping the sign bit puts negative numbers first
+                (*self as $u ^ (1 << (<$u>::BITS - 1))).write_key(buf)
+            }
+        }
+
+        impl FromOrderedKey for $t {
+            fn read_key(bytes: &mut &[u8]) -> Result<Self> {
+                Ok((<$u>::read_key(bytes)? ^ (1 << (<$u>::BITS - 1))) as $t)
+            }
+        }
+    )*
+)
+}
+
+signed_key!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+macro_rules! float_key {
+    ($($t:ty => $u:ty),*) => (
+    $(
+        impl OrderedKey for $t {
+            fn write_key(&self, buf: &mut Vec<u8>) {
+                let bits = self.to_bits();
+                let sign = 1 << (<$u>::BITS - 1);
+                // negative numbers are flipped so bigger magnitudes come first
+                let mask = if bits & sign != 0 { <$u>::MAX } else { sign };
+                (bits ^ mask).write_key(buf)
+            }
+        }
+
+        impl FromOrderedKey for $t {
+            fn read_key(bytes: &mut &[u8]) -> Result<Self> {
+                let bits = <$u>::read_key(bytes)?;
+                let sign = 1 << (<$u>::BITS - 1);
+                let mask = if bits & sign != 0 { sign } else { <$u>::MAX };
+                Ok(<$t>::from_bits(bits ^ mask))
+            }
+        }
+    )*
+)
+}
+
+float_key!(f32 => u32, f64 => u64);
+
+impl OrderedKey for str {
+    fn write_key(&self, buf: &mut Vec<u8>) {
+        for &b in self.as_bytes() {
+            buf.push(b);
+            if b == ESCAPE {
+                buf.push(ESCAPED_NULL);
+            }
+        }
+        buf.extend_from_slice(&[ESCAPE, END]);
+    }
+}
+
+impl OrderedKey for String {
+    fn write_key(&self, buf: &mut Vec<u8>) {
+        self.as_str().write_key(buf)
+    }
+}
+
+impl FromOrderedKey for String {
+    fn read_key(bytes: &mut &[u8]) -> Result<Self> {
+        let mut s = Vec::new();
+        loop {
+            match take::<1>(bytes)?[0] {
+                ESCAPE => match take::<1>(bytes)?[0] {
+                    END => break,
+                    ESCAPED_NULL => s.push(0),
+                    b => return Err(Error::Codec(format!("invalid escape {:#04x} in key", b))),
+                },
+                b => s.push(b),
+            }
+        }
+        String::from_utf8(s).map_err(|e| Error::Codec(e.to_string()))
+    }
+}
+
+impl OrderedKey for SystemTime {
+    fn write_key(&self, buf: &mut Vec<u8>) {
+        let (secs, nanos) = match self.duration_since(UNIX_EPOCH) {
+            Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+            // before the epoch, count back the whole seconds and then forward the nanoseconds
+            Err(e) => {
+                let d = e.duration();
+                match d.subsec_nanos() {
+                    0 => (-(d.as_secs() as i64), 0),
+                    nanos => (-(d.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+                }
+            }
+        };
+        secs.write_key(buf);
+        nanos.write_key(buf);
+    }
+}
+
+impl FromOrderedKey for SystemTime {
+    fn read_key(bytes: &mut &[u8]) -> Result<Self> {
+        let secs = i64::read_key(bytes)?;
+        let nanos = u32::read_key(bytes)?;
+        if nanos >= 1_000_000_000 {
+            return Err(Error::Codec(format!(
+                "invalid nanoseconds {} in key",
+                nanos
+            )));
+        }
+        let time = if secs >= 0 {
+            UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos))
+        } else {
+            UNIX_EPOCH
+                .checked_sub(Duration::from_secs(secs.unsigned_abs()))
+                .and_then(|t| t.checked_add(Duration::from_nanos(nanos as u64)))
+        };
+        time.ok_or_else(|| Error::Codec(String::from("time in key is out of range")))
+    }
+}
+
+macro_rules! tuple_key {
+    ($(($($name:ident),+)),*) => (
+    $(
+        impl<$($name: OrderedKey),+> OrderedKey for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn write_key(&self, buf: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.write_key(buf);)+
+            }
+        }
+
+        impl<$($name: FromOrderedKey),+> FromOrderedKey for ($($name,)+) {
+            fn read_key(bytes: &mut &[u8]) -> Result<Self> {
+                Ok(($($name::read_key(bytes)?,)+))
+            }
+        }
+    )*
+)
+}
+
+tuple_key!(
+    (A),
+    (A, B),
+    (A, B, C),
+    (A, B, C, D),
+    (A, B, C, D, E),
+    (A, B, C, D, E, F)
+);
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use super::*;
+    use crate::{testutil::RandomFile, OpenOptions};
+
+    fn assert_ordered<T: OrderedKey + FromOrderedKey + PartialEq + Debug>(values: &[T]) {
+        let keys: Vec<Key> = values.iter().map(|v| v.to_key()).collect();
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(&key.decode::<T>().unwrap(), &values[i]);
+            if i > 0 {
+                assert!(
+                    keys[i - 1] < *key,
+                    "{:?} should sort before {:?}",
+                    values[i - 1],
+                    values[i]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_key_order() {
+        assert_ordered(&[i64::MIN, -1000, -1, 0, 1, 1000, i64::MAX]);
+        assert_ordered(&[0_u64, 1, 256, u64::MAX]);
+        assert_ordered(&[0_u128, 1, u64::MAX as u128 + 1, u128::MAX]);
+        assert_ordered(&[i8::MIN, -1, 0, i8::MAX]);
+        assert_ordered(&[
+            f64::NEG_INFINITY,
+            f64::MIN,
+            -1.5,
+            -f64::MIN_POSITIVE,
+            -0.0,
+            0.0,
+            f64::MIN_POSITIVE,
+            2.5,
+            f64::MAX,
+            f64::INFINITY,
+        ]);
+        assert_ordered(&[-2.5_f32, 0.0, 3.0]);
+        let strings = ["", "\0", "\0\0", "a", "a\0", "a\0b", "ab", "b", "é"];
+        assert_ordered(&strings.map(String::from));
+        assert_ordered(&[
+            (String::from("a"), -1_i64),
+            (String::from("a"), 5),
+            (String::from("a\0"), -10),
+            (String::from("ab"), -10),
+        ]);
+        assert_ordered(&[(1_u8, 2_u16, -3_i32), (1, 3, -4), (2, 0, 0)]);
+        assert_ordered(&[
+            UNIX_EPOCH - Duration::new(5, 1),
+            UNIX_EPOCH - Duration::new(5, 0),
+            UNIX_EPOCH - Duration::from_nanos(1),
+            UNIX_EPOCH,
+            UNIX_EPOCH + Duration::from_nanos(1),
+            UNIX_EPOCH + Duration::new(1_700_000_000, 999_999_999),
+        ]);
+        // borrowed strings and tuples encode the same as owned ones
+        assert_eq!(("a", 1_u8).to_key(), (String::from("a"), 1_u8).to_key());
+
+        let nan = f64::NAN.to_key();
+        assert!(nan > f64::INFINITY.to_key());
+        assert!(nan.decode::<f64>().unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        let codec_err = |r: Result<()>| assert!(matches!(r, Err(Error::Codec(_))));
+        codec_err(u64::from_key(&[0; 4]).map(|_| ()));
+        codec_err(u32::from_key(&[0; 5]).map(|_| ()));
+        codec_err(String::from_key(b"abc").map(|_| ()));
+        codec_err(String::from_key(&[0, 7]).map(|_| ()));
+        codec_err(String::from_key(&[0xC0, 0, 0]).map(|_| ()));
+        codec_err(u8::from_key(&[0; 2]).map(|_| ()));
+        let mut bad_nanos = 0_i64.to_key().into_vec();
+        bad_nanos.extend_from_slice(&u32::MAX.to_be_bytes());
+        codec_err(SystemTime::from_key(&bad_nanos).map(|_| ()));
+    }
+
+    #[test]
+    fn test_bucket_keys() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        let tx = db.tx(true)?;
+        let b = tx.create_bucket("abc")?;
+        for i in (-500..500_i64).rev() {
+            b.put(i.to_key(), i.to_string())?;
+            b.put(("x", i).to_key(), i.to_string())?;
+        }
+        assert_eq!(b.get_kv((-3_i64).to_key()).unwrap().value(), b"-3");
+        let key = ("x", 7_i64).to_key();
+        assert_eq!(b.get_kv(&key).unwrap().value(), b"7");
+
+        let keys = b
+            .range((-2_i64).to_key()..=2_i64.to_key())
+            .map(|data| i64::from_key(data.key()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(keys, vec![-2, -1, 0, 1, 2]);
+        let keys = b
+            .range(("x", i64::MIN).to_key()..("x", -497_i64).to_key())
+            .map(|data| <(String, i64)>::from_key(data.key()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            keys,
+            (-500..-497)
+                .map(|i| (String::from("x"), i))
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+}
@@ -21,6 +21,8 @@
 //! * How to create and use multiple [`Tx`]s
 //! * Nested [`Buckets`](struct.Bucket.html)
 //! * [`OpenOptions`](struct.OpenOptions.html) to provide parameters for opening a [`DB`](struct.DB.html)
+//! * The [`keys`] module to store numbers, strings, tuples and timestamps as keys that sort correctly
+//! * A custom [`Comparator`] to change the order of the keys in a bucket
 //!
 //! # Examples
 //!
@@ -109,29 +111,69 @@
 //! }
 //!     Ok(())
 //! }
+//! ```
+//!
+//! # Optional Features
 //!
+//! * `tokio` adds [`AsyncDB`](struct.AsyncDB.html), which runs transactions without blocking the tokio runtime.
+//! * `serde` adds [`TypedBucket`](struct.TypedBucket.html), which stores [`keys`] as keys and serde types as values.
+//!   The `bincode`, `msgpack` and `json` features each add a [`Codec`](trait.Codec.html) for storing values in that format.
+//! * `rayon` adds [`ReadBucket::par_iter`](struct.ReadBucket.html#method.par_iter) to scan a bucket with a rayon parallel iterator.
+//! * `lz4` and `zstd` add those codecs to [`Compression`], to compress the values in a bucket.
+//! * `encryption` adds [`OpenOptions::encryption_key`](struct.OpenOptions.html#method.encryption_key) to encrypt the database file.
 
+#[cfg(feature = "tokio")]
+mod async_db;
+mod batch;
 #[allow(clippy::mutable_key_type)]
 mod bucket;
 mod bytes;
+mod compact;
+mod comparator;
+mod compression;
 mod cursor;
 mod data;
 mod db;
+mod encryption;
 mod errors;
 mod freelist;
+pub mod keys;
 mod lifetimes;
 mod meta;
 mod node;
 mod page;
 mod page_node;
+mod read_tx;
+mod recover;
+mod stats;
 mod tx;
+#[cfg(feature = "serde")]
+mod typed;
+mod verify;
 
-pub use bucket::Bucket;
-pub use cursor::{Buckets, Cursor, KVPairs, ToBuckets, ToKVPairs};
+#[cfg(feature = "tokio")]
+pub use async_db::{AsyncCursor, AsyncDB, AsyncData};
+pub use bucket::{Bucket, BucketOptions};
+pub use compact::CompactReport;
+pub use comparator::Comparator;
+pub use compression::Compression;
+pub use cursor::{Buckets, Cursor, KVPairs, KeyRange, Prefix, Range, ToBuckets, ToKVPairs};
 pub use data::*;
-pub use db::{OpenOptions, DB};
+pub use db::{OpenOptions, SyncMode, DB};
 pub use errors::*;
+pub use read_tx::{ReadBucket, ReadData, ReadIter, ReadTx};
+pub use recover::RecoverReport;
+pub use stats::{BucketStats, Stats};
 pub use tx::Tx;
+#[cfg(feature = "bincode")]
+pub use typed::Bincode;
+#[cfg(feature = "json")]
+pub use typed::Json;
+#[cfg(feature = "msgpack")]
+pub use typed::MsgPack;
+#[cfg(feature = "serde")]
+pub use typed::{Codec, TypedBucket, TypedRange};
+pub use verify::{VerifyIssue, VerifyOptions, VerifyReport};
 
 pub use crate::bytes::ToBytes;
 
@@ -11,14 +11,55 @@ pub(crate) struct Meta {
     pub(crate) magic: u32,
     pub(crate) version: u32,
     pub(crate) pagesize: u64,
-    pub(crate) root: BucketMeta,
+    pub(crate) root: RootMeta,
     pub(crate) num_pages: PageID,
     pub(crate) freelist_page: PageID,
     pub(crate) tx_id: u64,
     pub(crate) hash: u64,
+    // Not covered by the hash, so versions of jammdb that don't know about these
+    // still accept the meta page, and clear them when they write a new one.
+    pub(crate) flags: u64,
 }
 
+// The root bucket's metadata, which always uses the default comparator.
+// It's embedded in the meta pages, so its layout can never change.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct RootMeta {
+    pub(crate) root_page: PageID,
+    pub(crate) next_int: u64,
+}
+
+impl From<RootMeta> for BucketMeta {
+    fn from(root: RootMeta) -> Self {
+        BucketMeta {
+            root_page: root.root_page,
+            next_int: root.next_int,
+            comparator: 0,
+            compression: 0,
+        }
+    }
+}
+
+impl From<BucketMeta> for RootMeta {
+    fn from(meta: BucketMeta) -> Self {
+        debug_assert_eq!((meta.comparator, meta.compression), (0, 0));
+        RootMeta {
+            root_page: meta.root_page,
+            next_int: meta.next_int,
+        }
+    }
+}
+
+// Set in files that have a checksum in every page header. Older versions of jammdb don't update
+// the checksums, but they also clear this flag whenever they commit, so we stop checking them.
+pub(crate) const FLAG_CHECKSUMS: u64 = 1;
+
 impl Meta {
+    pub(crate) fn has_checksums(&self) -> bool {
+        self.flags & FLAG_CHECKSUMS != 0
+    }
+
     pub(crate) fn valid(&self) -> bool {
         self.hash == self.hash_self()
     }
@@ -55,7 +96,7 @@ pub(crate) struct OldMeta {
     pub(crate) magic: u32,
     pub(crate) version: u32,
     pub(crate) pagesize: u64,
-    pub(crate) root: BucketMeta,
+    pub(crate) root: RootMeta,
     pub(crate) num_pages: PageID,
     pub(crate) freelist_page: PageID,
     pub(crate) tx_id: u64,
@@ -106,6 +147,7 @@ impl From<&OldMeta> for Meta {
             freelist_page: val.freelist_page,
             tx_id: val.tx_id,
             hash: 0,
+            flags: 0,
         };
 
         m.hash = m.hash_self();
@@ -124,7 +166,7 @@ mod tests {
             magic: 1_234_567_890,
             version: 987_654_321,
             pagesize: 4096,
-            root: BucketMeta {
+            root: RootMeta {
                 root_page: 2,
                 next_int: 2020,
             },
@@ -132,6 +174,7 @@ mod tests {
             freelist_page: 3,
             tx_id: 8,
             hash: 64,
+            flags: 0,
         };
 
         assert!(!meta.valid());
@@ -143,6 +186,10 @@ mod tests {
 
         meta.hash = meta.hash_self();
         assert_eq!(meta.hash, meta.hash_self());
+
+        meta.flags = FLAG_CHECKSUMS;
+        assert!(meta.valid());
+        assert!(meta.has_checksums());
     }
 
     #[test]
@@ -152,7 +199,7 @@ mod tests {
             magic: 1_234_567_890,
             version: 987_654_321,
             pagesize: 4096,
-            root: BucketMeta {
+            root: RootMeta {
                 root_page: 2,
                 next_int: 2020,
             },
@@ -1,8 +1,10 @@
 use std::{cell::RefCell, mem::size_of, rc::Rc};
 
 use crate::{
-    bucket::{BucketMeta, InnerBucket, META_SIZE},
+    bucket::{BucketMeta, InnerBucket},
     bytes::Bytes,
+    comparator::Comparator,
+    compression::Compression,
     errors::Result,
     freelist::TxFreelist,
     page::{BranchElement, LeafElement, Page, PageID, PageType},
@@ -26,13 +28,14 @@ pub(crate) struct Node<'n> {
     pub(crate) original_key: Option<Bytes<'n>>,
     pub(crate) parent: Option<u64>,
     pagesize: u64,
+    comparator: Comparator,
     spilled: bool,
 }
 
 impl<'n> Node<'n> {
     // This is only used when creating a root node for a new bucket
     // So the parent is always going to be None
-    pub(crate) fn new(id: NodeID, t: PageType, pagesize: u64) -> Node<'n> {
+    pub(crate) fn new(id: NodeID, t: PageType, pagesize: u64, comparator: Comparator) -> Node<'n> {
         let data: NodeData = match t {
             Page::TYPE_BRANCH => NodeData::Branches(Vec::new()),
             Page::TYPE_LEAF => NodeData::Leaves(Vec::new()),
@@ -47,6 +50,7 @@ impl<'n> Node<'n> {
             deleted: false,
             original_key: None,
             pagesize,
+            comparator,
             spilled: false,
             parent: None,
         }
@@ -54,7 +58,12 @@ impl<'n> Node<'n> {
 
     // This is used to initialize nodes for pages that are being modified.
     // The parent value needs to be set afterwards!
-    pub(crate) fn from_page(id: NodeID, p: &Page, pagesize: u64) -> Node<'n> {
+    pub(crate) fn from_page(
+        id: NodeID,
+        p: &Page,
+        pagesize: u64,
+        comparator: Comparator,
+    ) -> Node<'n> {
         let data: NodeData = match p.page_type {
             Page::TYPE_BRANCH => {
                 let mut data = Vec::with_capacity(p.count as usize);
@@ -89,6 +98,7 @@ impl<'n> Node<'n> {
             deleted: false,
             original_key,
             pagesize,
+            comparator,
             spilled: false,
             parent: None,
         }
@@ -97,7 +107,12 @@ impl<'n> Node<'n> {
     // This is used to create new nodes created by splitting existing nodes.
     // They don't need to have their parent set since we no longer care about parent/child
     // relationships once we're splitting.
-    pub(crate) fn with_data(id: NodeID, data: NodeData<'n>, pagesize: u64) -> Node<'n> {
+    pub(crate) fn with_data(
+        id: NodeID,
+        data: NodeData<'n>,
+        pagesize: u64,
+        comparator: Comparator,
+    ) -> Node<'n> {
         let original_key = Some(data.first_key());
         Node {
             id,
@@ -108,6 +123,7 @@ impl<'n> Node<'n> {
             deleted: false,
             original_key,
             pagesize,
+            comparator,
             spilled: false,
             parent: None,
         }
@@ -118,7 +134,7 @@ impl<'n> Node<'n> {
             NodeData::Branches(branches) => {
                 debug_assert!(!self.children.contains(&id));
                 debug_assert!(branches
-                    .binary_search_by_key(&key.as_ref(), |b| b.key())
+                    .binary_search_by(|b| self.comparator.compare(b.key(), key.as_ref()))
                     .is_ok());
                 self.children.push(id);
             }
@@ -130,7 +146,7 @@ impl<'n> Node<'n> {
         match &mut self.data {
             NodeData::Branches(_) => panic!("CANNOT INSERT DATA INTO A BRANCH NODE"),
             NodeData::Leaves(leaves) => {
-                match leaves.binary_search_by_key(&leaf.key(), |l| l.key()) {
+                match leaves.binary_search_by(|l| self.comparator.compare(l.key(), leaf.key())) {
                     Ok(i) => leaves[i] = leaf,
                     Err(i) => leaves.insert(i, leaf),
                 };
@@ -150,7 +166,7 @@ impl<'n> Node<'n> {
         match &mut self.data {
             NodeData::Leaves(_) => panic!("CANNOT INSERT BRANCH INTO A LEAF NODE"),
             NodeData::Branches(branches) => {
-                match branches.binary_search_by_key(&search_key, |b| b.key()) {
+                match branches.binary_search_by(|b| self.comparator.compare(b.key(), search_key)) {
                     Ok(i) => {
                         assert!(original_key.is_some());
                         branches[i] = branch
@@ -197,8 +213,14 @@ impl<'n> Node<'n> {
             return Ok(root_page_id);
         }
         // Sort the children so we iterate over them in order
-        self.children
-            .sort_by_cached_key(|id| bucket.nodes[*id as usize].borrow().data.first_key());
+        let comparator = self.comparator;
+        let mut children: Vec<(Bytes, NodeID)> = self
+            .children
+            .iter()
+            .map(|id| (bucket.nodes[*id as usize].borrow().data.first_key(), *id))
+            .collect();
+        children.sort_by(|(a, _), (b, _)| comparator.compare(a.as_ref(), b.as_ref()));
+        self.children = children.into_iter().map(|(_, id)| id).collect();
 
         // spill all of the children nodes
         let mut i = 0_usize;
@@ -416,18 +438,18 @@ impl<'a> NodeData<'a> {
         }
     }
 
-    pub(crate) fn merge(&mut self, other_data: &mut Self) {
+    pub(crate) fn merge(&mut self, other_data: &mut Self, comparator: Comparator) {
         match (self, other_data) {
             (NodeData::Branches(b1), NodeData::Branches(b2)) => {
                 b1.append(b2);
-                b1.sort_unstable_by_key(|b| b.key.clone());
+                b1.sort_unstable_by(|a, b| comparator.compare(a.key(), b.key()));
             }
             (NodeData::Leaves(l1), NodeData::Leaves(l2)) => {
                 l1.append(l2);
-                l1.sort_unstable_by_key(|l| l.key_bytes());
+                l1.sort_unstable_by(|a, b| comparator.compare(a.key(), b.key()));
                 let mut last = l1[0].key();
                 for l in l1[1..].iter() {
-                    if last >= l.key() {
+                    if comparator.compare(last, l.key()).is_ge() {
                         println!("HA. GOT 'EM!");
                     }
                     last = l.key();
@@ -470,14 +492,18 @@ impl<'a> Branch<'a> {
 #[derive(Clone)]
 pub(crate) enum Leaf<'a> {
     Bucket(Bytes<'a>, BucketMeta),
-    Kv(Bytes<'a>, Bytes<'a>),
+    // The value is stored with the compression it was compressed with
+    Kv(Bytes<'a>, Bytes<'a>, Compression),
 }
 
 impl<'a> Leaf<'a> {
     pub(crate) fn from_leaf<'b>(l: &'b LeafElement) -> Leaf<'a> {
-        match l.node_type {
-            Node::TYPE_DATA => Leaf::Kv(Bytes::Slice(l.key()), Bytes::Slice(l.value())),
-            Node::TYPE_BUCKET => Leaf::Bucket(Bytes::Slice(l.key()), l.value().into()),
+        let compression = Compression::from_flag(l.node_type >> Node::COMPRESSION_SHIFT);
+        match (l.node_type & Node::TYPE_MASK, compression) {
+            (Node::TYPE_DATA, Some(c)) => {
+                Leaf::Kv(Bytes::Slice(l.key()), Bytes::Slice(l.value()), c)
+            }
+            (Node::TYPE_BUCKET, _) => Leaf::Bucket(Bytes::Slice(l.key()), l.value().into()),
             _ => panic!("INVALID NODE TYPE"),
         }
     }
@@ -485,42 +511,42 @@ impl<'a> Leaf<'a> {
     pub(crate) fn node_type(&self) -> NodeType {
         match self {
             Self::Bucket(_, _) => Node::TYPE_BUCKET,
-            Self::Kv(_, _) => Node::TYPE_DATA,
+            Self::Kv(_, _, c) => Node::TYPE_DATA | (c.flag() << Node::COMPRESSION_SHIFT),
         }
     }
 
     pub(crate) fn key_bytes<'b>(&'b self) -> Bytes<'a> {
         match self {
             Self::Bucket(name, _) => name.clone(),
-            Self::Kv(k, _) => k.clone(),
+            Self::Kv(k, _, _) => k.clone(),
         }
     }
 
     pub(crate) fn key(&self) -> &[u8] {
         match self {
             Self::Bucket(b, _) => b.as_ref(),
-            Self::Kv(k, _) => k.as_ref(),
+            Self::Kv(k, _, _) => k.as_ref(),
         }
     }
 
     pub(crate) fn value(&self) -> &[u8] {
         match self {
             Self::Bucket(_, meta) => meta.as_ref(),
-            Self::Kv(_, v) => v.as_ref(),
+            Self::Kv(_, v, _) => v.as_ref(),
         }
     }
 
     pub(crate) fn size(&self) -> usize {
         match self {
-            Self::Bucket(b, _) => b.size() + META_SIZE,
-            Self::Kv(k, v) => k.size() + v.size(),
+            Self::Bucket(b, meta) => b.size() + meta.as_ref().len(),
+            Self::Kv(k, v, _) => k.size() + v.size(),
         }
     }
 
     pub(crate) fn is_kv(&self) -> bool {
         match self {
             Self::Bucket(_, _) => false,
-            Self::Kv(_, _) => true,
+            Self::Kv(_, _, _) => true,
         }
     }
 }
@@ -531,6 +557,10 @@ pub(crate) type NodeType = u8;
 impl Node<'_> {
     pub(crate) const TYPE_DATA: NodeType = 0x00;
     pub(crate) const TYPE_BUCKET: NodeType = 0x01;
+    // The upper bits of a key / value leaf's type hold the compression used for its value,
+    // which is always zero in files written before compression was supported.
+    pub(crate) const TYPE_MASK: NodeType = 0x0F;
+    pub(crate) const COMPRESSION_SHIFT: NodeType = 4;
 }
 
 #[cfg(test)]
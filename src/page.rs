@@ -1,14 +1,21 @@
 use std::{
+    collections::HashMap,
+    hash::Hasher,
     io::Write,
     mem::size_of,
     slice::{from_raw_parts, from_raw_parts_mut},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
 };
 
+use fnv::FnvHasher;
 use memmap2::Mmap;
 
 use crate::{
-    errors::Result,
+    encryption::{Cipher, RESERVED_SIZE},
+    errors::{Error, Result},
     meta::{Meta, OldMeta},
     node::{Node, NodeData, NodeType},
 };
@@ -17,24 +24,129 @@ pub(crate) type PageID = u64;
 
 pub(crate) type PageType = u8;
 
+// Everything before Page::ptr
+pub(crate) const HEADER_SIZE: usize = size_of::<Page>() - size_of::<u64>();
+// Page::checksum comes after the page's id and type
+const CHECKSUM_OFFSET: usize = 12;
+
 #[derive(Clone)]
 pub(crate) struct Pages {
     pub(crate) data: Arc<Mmap>,
     pub(crate) pagesize: u64,
+    // Pages that have to be checked before they are used go through this cache,
+    // so they are only decrypted or verified the first time they are read.
+    cache: Option<Arc<PageCache>>,
+}
+
+struct PageCache {
+    cipher: Option<Arc<Cipher>>,
+    checksums: bool,
+    // Decrypted copies of encrypted pages, or None for pages that are read straight from the mmap.
+    pages: Mutex<HashMap<PageID, Option<Box<[u64]>>>>,
+    // Set once a page has been decrypted, after which we know the key is right
+    // and a page that can't be decrypted has been corrupted instead.
+    key_verified: AtomicBool,
 }
 
 impl Pages {
-    pub fn new(data: Arc<Mmap>, pagesize: u64) -> Pages {
-        Pages { data, pagesize }
+    pub fn new(
+        data: Arc<Mmap>,
+        pagesize: u64,
+        cipher: Option<Arc<Cipher>>,
+        checksums: bool,
+    ) -> Pages {
+        let cache = match (cipher, checksums) {
+            (None, false) => None,
+            (cipher, checksums) => Some(Arc::new(PageCache {
+                cipher,
+                checksums,
+                pages: Mutex::new(HashMap::new()),
+                key_verified: AtomicBool::new(false),
+            })),
+        };
+        Pages {
+            data,
+            pagesize,
+            cache,
+        }
     }
 
+    // Returns an error if the page is encrypted and we can't decrypt it,
+    // if it isn't encrypted but should be, or if it doesn't match its checksum.
+    // Once the key has decrypted another page, that's a Corruption error instead of an InvalidKey one.
+    pub(crate) fn page<'a>(&self, id: PageID) -> Result<&'a Page> {
+        let page = self.raw_page(id);
+        let encrypted = page.page_type & Page::FLAG_ENCRYPTED != 0;
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None if encrypted => return Err(Error::InvalidKey),
+            None => return Ok(page),
+        };
+        let mut pages = cache.pages.lock()?;
+        let buf = match pages.get(&id) {
+            Some(buf) => buf,
+            None => {
+                let start = (id * self.pagesize) as usize;
+                let end = start + ((page.overflow + 1) * self.pagesize) as usize;
+                let block = self.data.get(start..end).ok_or_else(|| {
+                    Error::InvalidDB(format!("Page {} is past the end of the file", id))
+                })?;
+                let invalid = || {
+                    if cache.key_verified.load(Ordering::Acquire) {
+                        Error::Corruption { page_id: id }
+                    } else {
+                        Error::InvalidKey
+                    }
+                };
+                let buf = match &cache.cipher {
+                    Some(cipher) if encrypted => {
+                        let buf = cipher.decrypt(block).ok_or_else(invalid)?;
+                        cache.key_verified.store(true, Ordering::Release);
+                        // the header is authenticated too, but a whole page could have been copied from somewhere else
+                        if Page::from_decrypted(&buf).id != id {
+                            return Err(Error::Corruption { page_id: id });
+                        }
+                        Some(buf)
+                    }
+                    Some(_) => return Err(invalid()),
+                    None if !encrypted => None,
+                    None => return Err(Error::InvalidKey),
+                };
+                if cache.checksums {
+                    let checked = match &buf {
+                        Some(buf) => Page::from_decrypted(buf),
+                        None => page,
+                    };
+                    if !checked.checksum_valid(block.len()) {
+                        return Err(Error::Corruption { page_id: id });
+                    }
+                }
+                pages.entry(id).or_insert(buf)
+            }
+        };
+        match buf {
+            // The buffers are never removed from the cache, or moved, until every copy of this Pages is dropped.
+            Some(buf) => Ok(Page::from_decrypted(buf)),
+            None => Ok(page),
+        }
+    }
+
+    // Reads the page straight from the mmap, without decrypting or checking it
     #[inline]
-    pub fn page<'a>(&self, id: PageID) -> &'a Page {
+    pub(crate) fn raw_page<'a>(&self, id: PageID) -> &'a Page {
         #[allow(clippy::cast_ptr_alignment)]
         unsafe {
             &*(&self.data[(id * self.pagesize) as usize] as *const u8 as *const Page)
         }
     }
+
+    // The number of bytes a node can use on a page, leaving room for the nonce and tag when pages are encrypted.
+    pub(crate) fn node_pagesize(&self) -> u64 {
+        match self.cache.as_ref().and_then(|cache| cache.cipher.as_ref()) {
+            None => self.pagesize,
+            Some(_) => self.pagesize - RESERVED_SIZE,
+        }
+    }
 }
 
 #[repr(C)]
@@ -43,6 +155,9 @@ pub(crate) struct Page {
     // id * pagesize is the offset from the beginning of the file
     pub(crate) id: PageID,
     pub(crate) page_type: PageType,
+    // Hash of the rest of the page. This used to be padding, so it's only checked in files created
+    // and only written to by versions of jammdb that always write it (see Meta::has_checksums).
+    pub(crate) checksum: u32,
     // Number of elements on this page, the type of element depends on the pageType
     pub(crate) count: u64,
     // Number of additional pages after this one that are part of this block
@@ -56,6 +171,12 @@ impl Page {
     pub(crate) const TYPE_LEAF: PageType = 0x02;
     pub(crate) const TYPE_META: PageType = 0x03;
     pub(crate) const TYPE_FREELIST: PageType = 0x04;
+    // Set on the page type of pages that are encrypted on disk, and cleared once they are decrypted.
+    pub(crate) const FLAG_ENCRYPTED: PageType = 0x80;
+
+    fn from_decrypted<'a>(buf: &[u64]) -> &'a Page {
+        unsafe { &*(buf.as_ptr() as *const Page) }
+    }
 
     #[inline]
     pub(crate) fn from_buf(buf: &[u8], id: PageID, pagesize: u64) -> &Page {
@@ -180,6 +301,108 @@ impl Page {
         }
     }
 
+    // Number of bytes at the start of the page that are in use, based on the elements it holds.
+    // Returns None if that would be more than max bytes, which means the page is corrupted.
+    fn used_size(&self, max: usize) -> Option<usize> {
+        let count = self.count as usize;
+        let elem_size = match self.page_type {
+            Page::TYPE_BRANCH => size_of::<BranchElement>(),
+            Page::TYPE_LEAF => size_of::<LeafElement>(),
+            Page::TYPE_FREELIST => size_of::<PageID>(),
+            _ => 0,
+        };
+        let mut end = count.checked_mul(elem_size)?.checked_add(HEADER_SIZE)?;
+        if end > max {
+            return None;
+        }
+        // The keys and values are stored in the same order as the elements, so the last element's data is at the end of the page
+        if count > 0 && matches!(self.page_type, Page::TYPE_BRANCH | Page::TYPE_LEAF) {
+            let last =
+                unsafe { (&self.ptr as *const u64 as *const u8).add((count - 1) * elem_size) };
+            let data_end = match self.page_type {
+                Page::TYPE_BRANCH => {
+                    let b = unsafe { &*(last as *const BranchElement) };
+                    b.pos.checked_add(b.key_size)?
+                }
+                _ => {
+                    let l = unsafe { &*(last as *const LeafElement) };
+                    l.pos.checked_add(l.key_size)?.checked_add(l.value_size)?
+                }
+            };
+            end = usize::try_from(data_end)
+                .ok()?
+                .checked_add(HEADER_SIZE + (count - 1) * elem_size)?;
+        }
+        if end > max {
+            return None;
+        }
+        Some(end)
+    }
+
+    fn compute_checksum(&self, size: usize) -> u32 {
+        let bytes = unsafe { from_raw_parts(self as *const Page as *const u8, size) };
+        let mut hasher = FnvHasher::default();
+        hasher.write(&bytes[..CHECKSUM_OFFSET]);
+        hasher.write(&bytes[CHECKSUM_OFFSET + size_of::<u32>()..]);
+        let hash = hasher.finish();
+        (hash ^ (hash >> 32)) as u32
+    }
+
+    // Updates the checksum once everything else on the page has been written.
+    pub(crate) fn update_checksum(&mut self) {
+        let size = self
+            .used_size(usize::MAX)
+            .expect("page should fit in memory");
+        self.checksum = self.compute_checksum(size);
+    }
+
+    // Checks the page against its checksum. max is the size of the page plus its overflow pages.
+    pub(crate) fn checksum_valid(&self, max: usize) -> bool {
+        match self.used_size(max) {
+            Some(size) => self.checksum == self.compute_checksum(size),
+            None => false,
+        }
+    }
+
+    // Checks that every element's key and value are within the first max bytes of the page.
+    // used_size only looks at the last element, which is enough for pages we wrote, but not for pages we are trying to recover.
+    pub(crate) fn elements_in_bounds(&self, max: usize) -> bool {
+        let count = self.count as usize;
+        let elem_size = match self.page_type {
+            Page::TYPE_BRANCH => size_of::<BranchElement>(),
+            Page::TYPE_LEAF => size_of::<LeafElement>(),
+            _ => return false,
+        };
+        match count
+            .checked_mul(elem_size)
+            .and_then(|size| size.checked_add(HEADER_SIZE))
+        {
+            Some(size) if size <= max => (),
+            _ => return false,
+        }
+        let data_end = |i: usize, end: Option<u64>| {
+            let end = end
+                .and_then(|end| usize::try_from(end).ok())
+                .and_then(|end| end.checked_add(HEADER_SIZE + i * elem_size));
+            matches!(end, Some(end) if end <= max)
+        };
+        match self.page_type {
+            Page::TYPE_BRANCH => self
+                .branch_elements()
+                .iter()
+                .enumerate()
+                .all(|(i, b)| data_end(i, b.pos.checked_add(b.key_size))),
+            _ => self.leaf_elements().iter().enumerate().all(|(i, l)| {
+                data_end(
+                    i,
+                    l.pos
+                        .checked_add(l.key_size)
+                        .and_then(|end| end.checked_add(l.value_size)),
+                )
+            }),
+        }
+    }
+
     pub(crate) fn write_node(&mut self, n: &Node, num_pages: u64) -> Result<()> {
         debug_assert!(self.id == n.page_id);
         debug_assert!(self.overflow == num_pages - 1);
@@ -233,6 +456,7 @@ impl Page {
         for b in data.iter() {
             buf.write_all(b)?;
         }
+        self.update_checksum();
         Ok(())
     }
 }
@@ -1,6 +1,7 @@
 use std::{cell::RefCell, rc::Rc};
 
 use crate::{
+    comparator::Comparator,
     node::{Leaf, Node, NodeData, NodeID},
     page::{Page, PageID},
 };
@@ -61,16 +62,20 @@ impl<'a> PageNode<'a> {
         }
     }
 
-    pub fn index(&self, key: &[u8]) -> (usize, bool) {
+    pub fn index(&self, key: &[u8], comparator: Comparator) -> (usize, bool) {
         let result = match self {
             PageNode::Page(p) => match p.page_type {
-                Page::TYPE_LEAF => p.leaf_elements().binary_search_by_key(&key, |e| e.key()),
-                Page::TYPE_BRANCH => p.branch_elements().binary_search_by_key(&key, |e| e.key()),
+                Page::TYPE_LEAF => p
+                    .leaf_elements()
+                    .binary_search_by(|e| comparator.compare(e.key(), key)),
+                Page::TYPE_BRANCH => p
+                    .branch_elements()
+                    .binary_search_by(|e| comparator.compare(e.key(), key)),
                 _ => panic!("INVALID PAGE TYPE FOR INDEX: {:?}", p.page_type),
             },
             PageNode::Node(n) => match &n.borrow().data {
-                NodeData::Branches(b) => b.binary_search_by_key(&key, |b| b.key()),
-                NodeData::Leaves(l) => l.binary_search_by_key(&key, |l| l.key()),
+                NodeData::Branches(b) => b.binary_search_by(|b| comparator.compare(b.key(), key)),
+                NodeData::Leaves(l) => l.binary_search_by(|l| comparator.compare(l.key(), key)),
             },
         };
         match result {
@@ -0,0 +1,688 @@
+use std::borrow::Cow;
+
+use crate::{
+    bucket::{BucketMeta, BucketOptions},
+    comparator::Comparator,
+    compression::Compression,
+    db::DB,
+    errors::{Error, Result},
+    meta::Meta,
+    node::Node,
+    page::{LeafElement, Page, PageID, Pages},
+};
+
+/// A read-only snapshot of the database that can be shared between threads.
+///
+/// A [`Tx`](struct.Tx.html) and the [`Bucket`](struct.Bucket.html)s it creates keep track of changes using
+/// types that can't be sent to other threads, even when the transaction is read-only.
+/// A `ReadTx` only reads the pages that have already been committed, so it is both `Send` and `Sync`.
+/// You can hold it across an `.await` in a multi-threaded async runtime, or share a single snapshot
+/// between several threads that each scan part of the database.
+///
+/// Like a read-only [`Tx`](struct.Tx.html), a `ReadTx` sees the database exactly as it was when it was created,
+/// and prevents the pages it can see from being reused until it is dropped.
+/// Writers that need to shrink the database file have to wait until all `ReadTx`s are dropped,
+/// so don't call [`DB::shrink`](struct.DB.html#method.shrink), or commit with
+/// [`auto_shrink`](struct.OpenOptions.html#method.auto_shrink) enabled, on a thread while that thread is holding a `ReadTx`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use jammdb::{DB, ReadData};
+/// # use jammdb::Error;
+///
+/// # fn main() -> Result<(), Error> {
+/// let db = DB::open("my.db")?;
+/// let tx = db.read_tx()?;
+/// let tx = &tx;
+/// std::thread::scope(|s| {
+///     for name in ["users", "posts"] {
+///         s.spawn(move || {
+///             let b = tx.get_bucket(name).unwrap();
+///             let mut values = 0;
+///             for data in b.iter() {
+///                 if data.unwrap().is_kv() {
+///                     values += 1;
+///                 }
+///             }
+///             println!("{} has {} values", name, values);
+///         });
+///     }
+/// });
+/// # Ok(())
+/// # }
+/// ```
+pub struct ReadTx {
+    db: DB,
+    meta: Meta,
+    pages: Pages,
+}
+
+impl DB {
+    /// Creates a [`ReadTx`], a read-only snapshot of the database that can be sent and shared between threads.
+    ///
+    /// If the database is [encrypted](struct.OpenOptions.html#method.encryption_key), every page the `ReadTx` reads
+    /// is decrypted into a cache that is only freed once it is dropped, so a long-lived `ReadTx` that reads
+    /// most of a large database will hold a decrypted copy of it in memory.
+    pub fn read_tx(&self) -> Result<ReadTx> {
+        // hold the mmap lock so the file isn't resized while we grab the current mmap
+        let _lock = self.inner.mmap_lock.read()?;
+        let meta = self.inner.meta()?;
+        let data = self.inner.data.lock()?.clone();
+        self.inner.open_snapshot(meta.tx_id)?;
+        Ok(ReadTx {
+            db: self.clone(),
+            meta,
+            pages: self.inner.pages(data),
+        })
+    }
+}
+
+impl ReadTx {
+    /// Returns the root level bucket with the given name.
+    ///
+    /// # Errors
+    ///
+    /// Will return a [`BucketMissing`](enum.Error.html#variant.BucketMissing) error if the bucket does not exist,
+    /// or an [`IncompatibleValue`](enum.Error.html#variant.IncompatibleValue) error if the key exists but is not a bucket.
+    pub fn get_bucket<T: AsRef<[u8]>>(&self, name: T) -> Result<ReadBucket<'_>> {
+        self.root().get_bucket(name)
+    }
+
+    /// Returns the root level bucket with the given name, using the given options.
+    ///
+    /// See [`Tx::get_bucket_with`](struct.Tx.html#method.get_bucket_with) for details.
+    pub fn get_bucket_with<T: AsRef<[u8]>>(
+        &self,
+        name: T,
+        options: BucketOptions,
+    ) -> Result<ReadBucket<'_>> {
+        self.root().get_bucket_with(name, options)
+    }
+
+    /// Iterator over the root level buckets.
+    ///
+    /// Like [`ReadBucket::iter`], it returns an error and stops if a page can't be read.
+    pub fn buckets(&self) -> impl Iterator<Item = Result<(&[u8], ReadBucket<'_>)>> {
+        let root = self.root();
+        root.iter().filter_map(move |data| match data {
+            Ok(ReadData::Bucket(name)) => Some(root.open_bucket(name, None).map(|b| (name, b))),
+            Ok(ReadData::KeyValue(..)) => None,
+            Err(e) => Some(Err(e)),
+        })
+    }
+
+    fn root(&self) -> ReadBucket<'_> {
+        ReadBucket {
+            pages: &self.pages,
+            meta: self.meta.root.into(),
+            comparator: Comparator::BYTEWISE,
+        }
+    }
+}
+
+impl Drop for ReadTx {
+    fn drop(&mut self) {
+        self.db.inner.close_snapshot(self.meta.tx_id);
+    }
+}
+
+/// A bucket in a [`ReadTx`].
+///
+/// Unlike a [`Bucket`](struct.Bucket.html), it can be sent and shared between threads.
+#[derive(Clone, Copy)]
+pub struct ReadBucket<'a> {
+    pages: &'a Pages,
+    meta: BucketMeta,
+    comparator: Comparator,
+}
+
+impl<'a> ReadBucket<'a> {
+    /// Gets data from the bucket.
+    ///
+    /// Returns `None` if the key does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the value can't be [decompressed](enum.Compression.html).
+    pub fn get<T: AsRef<[u8]>>(&self, key: T) -> Result<Option<ReadData<'a>>> {
+        self.find(key.as_ref())?
+            .map(ReadData::from_leaf)
+            .transpose()
+    }
+
+    /// Gets the value of a key / value pair.
+    ///
+    /// Returns `None` if the key does not exist, or if it is a nested bucket.
+    /// The value is only copied if it was [compressed](enum.Compression.html).
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the value can't be decompressed.
+    pub fn get_kv<T: AsRef<[u8]>>(&self, key: T) -> Result<Option<Cow<'a, [u8]>>> {
+        match self.get(key)? {
+            Some(ReadData::KeyValue(_, value)) => Ok(Some(value)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns the nested bucket with the given name.
+    ///
+    /// # Errors
+    ///
+    /// Will return a [`BucketMissing`](enum.Error.html#variant.BucketMissing) error if the bucket does not exist,
+    /// or an [`IncompatibleValue`](enum.Error.html#variant.IncompatibleValue) error if the key exists but is not a bucket.
+    pub fn get_bucket<T: AsRef<[u8]>>(&self, name: T) -> Result<ReadBucket<'a>> {
+        self.open_bucket(name.as_ref(), Some(Comparator::BYTEWISE))
+    }
+
+    /// Returns the nested bucket with the given name, using the given options.
+    ///
+    /// See [`Bucket::get_bucket_with`](struct.Bucket.html#method.get_bucket_with) for details.
+    pub fn get_bucket_with<T: AsRef<[u8]>>(
+        &self,
+        name: T,
+        options: BucketOptions,
+    ) -> Result<ReadBucket<'a>> {
+        self.open_bucket(name.as_ref(), Some(options.comparator))
+    }
+
+    // Without a comparator, the bucket is opened with whatever comparator it was created with.
+    fn open_bucket(&self, name: &[u8], comparator: Option<Comparator>) -> Result<ReadBucket<'a>> {
+        match self.find(name)? {
+            Some(leaf) if leaf.node_type == Node::TYPE_BUCKET => {
+                let meta: BucketMeta = leaf.value().into();
+                let comparator = match comparator {
+                    Some(c) if c.id() != meta.comparator => {
+                        return Err(Error::ComparatorMismatch(c.name()))
+                    }
+                    Some(c) => c,
+                    None => Comparator::for_id(meta.comparator),
+                };
+                Ok(ReadBucket {
+                    pages: self.pages,
+                    meta,
+                    comparator,
+                })
+            }
+            Some(_) => Err(Error::IncompatibleValue),
+            None => Err(Error::BucketMissing),
+        }
+    }
+
+    /// Iterator over all of the data in the bucket, in order.
+    ///
+    /// Values that can't be [decompressed](enum.Compression.html) are returned as errors.
+    /// If a page can't be read, the error is returned and the iterator stops, since everything below that page is missing.
+    pub fn iter(&self) -> ReadIter<'a> {
+        ReadIter::new(self.pages, self.meta.root_page)
+    }
+
+    /// Splits the bucket into at most `n` iterators over consecutive ranges of keys.
+    ///
+    /// The bucket is split using the elements of its root page (or the pages below it, if the root doesn't have
+    /// enough elements), so each iterator scans whole sub-trees and they can all be run at the same time on different threads. The iterators are returned in key order,
+    /// so chaining them together gives the same results as [`iter`](#method.iter).
+    /// Small buckets may be split into fewer than `n` iterators.
+    /// If the pages needed to split the bucket can't be read, a single iterator that returns the error is returned.
+    ///
+    /// With the `rayon` feature enabled, [`par_iter`](#method.par_iter) uses this to give you a parallel iterator.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::{DB, ReadData};
+    /// # use jammdb::Error;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let db = DB::open("my.db")?;
+    /// let tx = db.read_tx()?;
+    /// let b = tx.get_bucket("my-bucket")?;
+    /// let total = std::thread::scope(|s| {
+    ///     let handles: Vec<_> = b
+    ///         .par_chunks(8)
+    ///         .into_iter()
+    ///         .map(|chunk| {
+    ///             s.spawn(move || {
+    ///                 chunk
+    ///                     .map(|data| Ok(usize::from(data?.is_kv())))
+    ///                     .sum::<Result<usize, Error>>()
+    ///             })
+    ///         })
+    ///         .collect();
+    ///     handles.into_iter().map(|h| h.join().unwrap()).sum::<Result<usize, Error>>()
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn par_chunks(&self, n: usize) -> Vec<ReadIter<'a>> {
+        assert!(n > 0, "Must split into at least one chunk");
+        // Start with the root page's elements, and go down a level at a time until there are enough sub-trees
+        let mut level = match self.pages.page(self.meta.root_page) {
+            Ok(page) => vec![page],
+            Err(e) => return vec![ReadIter::failed(self.pages, e)],
+        };
+        let mut count: usize = level[0].count as usize;
+        while count < n && level[0].page_type == Page::TYPE_BRANCH {
+            let next_level: Result<Vec<&Page>> = level
+                .iter()
+                .flat_map(|page| page.branch_elements())
+                .map(|b| self.pages.page(b.page))
+                .collect();
+            level = match next_level {
+                Ok(level) => level,
+                Err(e) => return vec![ReadIter::failed(self.pages, e)],
+            };
+            count = level.iter().map(|page| page.count as usize).sum();
+        }
+        let n = n.min(count).max(1);
+        // spread the remainder over the first chunks so they're all within one element of each other
+        let (size, remainder) = (count / n, count % n);
+        let mut pages = level.into_iter();
+        let mut page = pages.next();
+        let mut index = 0;
+        (0..n)
+            .map(|i| {
+                let mut left = size + usize::from(i < remainder);
+                let mut ranges = Vec::new();
+                while let Some(p) = page {
+                    let end = (index + left).min(p.count as usize);
+                    if end > index {
+                        ranges.push((p, index, end));
+                        left -= end - index;
+                    }
+                    index = end;
+                    if index < p.count as usize {
+                        break;
+                    }
+                    page = pages.next();
+                    index = 0;
+                }
+                ReadIter::with_ranges(self.pages, ranges)
+            })
+            .collect()
+    }
+
+    /// Returns a rayon [`ParallelIterator`](rayon::iter::ParallelIterator) over all of the data in the bucket.
+    ///
+    /// The bucket is split up using [`par_chunks`](#method.par_chunks), so the data is not returned in order
+    /// unless you collect it into an ordered collection.
+    #[cfg(feature = "rayon")]
+    pub fn par_iter(&self) -> impl rayon::iter::ParallelIterator<Item = Result<ReadData<'a>>> + 'a {
+        use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+        self.par_chunks(rayon::current_num_threads() * 4)
+            .into_par_iter()
+            .flat_map_iter(|chunk| chunk)
+    }
+
+    /// Returns the bucket's current auto-incrementing integer.
+    ///
+    /// See [`Bucket::next_int`](struct.Bucket.html#method.next_int) for details.
+    pub fn next_int(&self) -> u64 {
+        self.meta.next_int
+    }
+
+    fn find(&self, key: &[u8]) -> Result<Option<&'a LeafElement>> {
+        if !self.comparator.is_known() {
+            return Ok(None);
+        }
+        let mut page = self.pages.page(self.meta.root_page)?;
+        loop {
+            match page.page_type {
+                Page::TYPE_BRANCH => {
+                    let branches = page.branch_elements();
+                    let index = match branches
+                        .binary_search_by(|b| self.comparator.compare(b.key(), key))
+                    {
+                        Ok(i) => i,
+                        // the key can only be in the branch "before" where it would be
+                        Err(0) => return Ok(None),
+                        Err(i) => i - 1,
+                    };
+                    page = self.pages.page(branches[index].page)?;
+                }
+                Page::TYPE_LEAF => {
+                    let leaves = page.leaf_elements();
+                    let index = leaves.binary_search_by(|l| self.comparator.compare(l.key(), key));
+                    return Ok(index.ok().map(|index| &leaves[index]));
+                }
+                _ => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Data from a [`ReadBucket`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadData<'a> {
+    /// The name of a nested bucket.
+    Bucket(&'a [u8]),
+    /// A key / value pair.
+    ///
+    /// The value is borrowed from the database file, unless it was [compressed](enum.Compression.html).
+    KeyValue(&'a [u8], Cow<'a, [u8]>),
+}
+
+impl<'a> ReadData<'a> {
+    /// Returns the key, or the name of the bucket.
+    pub fn key(&self) -> &'a [u8] {
+        match self {
+            ReadData::Bucket(name) => name,
+            ReadData::KeyValue(key, _) => key,
+        }
+    }
+
+    /// Checks if the data is a key / value pair.
+    pub fn is_kv(&self) -> bool {
+        matches!(self, ReadData::KeyValue(..))
+    }
+}
+
+impl<'a> ReadData<'a> {
+    fn from_leaf(leaf: &'a LeafElement) -> Result<Self> {
+        if leaf.node_type == Node::TYPE_BUCKET {
+            return Ok(ReadData::Bucket(leaf.key()));
+        }
+        let flag = leaf.node_type >> Node::COMPRESSION_SHIFT;
+        match Compression::from_flag(flag) {
+            Some(c) => Ok(ReadData::KeyValue(
+                leaf.key(),
+                c.decompress_slice(leaf.value())?,
+            )),
+            None => Err(Error::InvalidDB(format!(
+                "Invalid compression flag {}",
+                flag
+            ))),
+        }
+    }
+}
+
+/// Iterator over the data in a [`ReadBucket`].
+pub struct ReadIter<'a> {
+    pages: &'a Pages,
+    // the pages we're in the middle of, the index of the next element in each one, and the index to stop at
+    stack: Vec<(&'a Page, usize, usize)>,
+    // returned before anything else, if the first page couldn't be read
+    error: Option<Error>,
+}
+
+impl<'a> ReadIter<'a> {
+    pub(crate) fn new(pages: &'a Pages, page_id: PageID) -> ReadIter<'a> {
+        match pages.page(page_id) {
+            Ok(page) => ReadIter::with_ranges(pages, vec![(page, 0, page.count as usize)]),
+            Err(e) => ReadIter::failed(pages, e),
+        }
+    }
+
+    fn failed(pages: &'a Pages, e: Error) -> ReadIter<'a> {
+        ReadIter {
+            pages,
+            stack: Vec::new(),
+            error: Some(e),
+        }
+    }
+
+    // iterates over the sub-trees of the given ranges of elements, in order
+    fn with_ranges(pages: &'a Pages, mut ranges: Vec<(&'a Page, usize, usize)>) -> ReadIter<'a> {
+        // the last range on the stack is the first one to be iterated over
+        ranges.reverse();
+        ReadIter {
+            pages,
+            stack: ranges,
+            error: None,
+        }
+    }
+}
+
+impl<'a> Iterator for ReadIter<'a> {
+    type Item = Result<ReadData<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+        loop {
+            let (page, index, end) = self.stack.last_mut()?;
+            let page: &'a Page = page;
+            let i = *index;
+            *index += 1;
+            match page.page_type {
+                Page::TYPE_BRANCH if i < *end => {
+                    match self.pages.page(page.branch_elements()[i].page) {
+                        Ok(child) => self.stack.push((child, 0, child.count as usize)),
+                        Err(e) => {
+                            self.stack.clear();
+                            return Some(Err(e));
+                        }
+                    }
+                }
+                Page::TYPE_LEAF if i < *end => {
+                    return Some(ReadData::from_leaf(&page.leaf_elements()[i]));
+                }
+                _ => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testutil::RandomFile, OpenOptions};
+
+    fn is_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_read_tx() -> Result<()> {
+        is_send_sync::<ReadTx>();
+        is_send_sync::<ReadBucket>();
+        is_send_sync::<ReadIter>();
+
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket("abc")?;
+            for i in 0..1000_u32 {
+                b.put(i.to_be_bytes(), i.to_string())?;
+            }
+            b.create_bucket("nested")?.put("key", "value")?;
+            tx.create_bucket("def")?;
+            tx.commit()?;
+        }
+        let read_tx = db.read_tx()?;
+        {
+            // changes after the snapshot was created aren't visible
+            let tx = db.tx(true)?;
+            tx.delete_bucket("def")?;
+            tx.get_bucket("abc")?.put("new", "value")?;
+            tx.commit()?;
+        }
+        // but the snapshot's pages are kept
+        {
+            let tx = db.tx(true)?;
+            tx.create_bucket("ghi")?.put("a", vec![0; 4000])?;
+            tx.commit()?;
+        }
+        assert_eq!(
+            read_tx.buckets().map(|b| b.unwrap().0).collect::<Vec<_>>(),
+            vec![b"abc", b"def"]
+        );
+        assert_eq!(read_tx.get_bucket("ghi").err(), Some(Error::BucketMissing));
+
+        let b = read_tx.get_bucket("abc")?;
+        assert_eq!(b.next_int(), 1001);
+        assert_eq!(b.get_kv("new")?, None);
+        assert_eq!(b.get_kv(7_u32.to_be_bytes())?.as_deref(), Some(&b"7"[..]));
+        assert_eq!(b.get("nested")?, Some(ReadData::Bucket(b"nested")));
+        assert_eq!(b.get_kv("nested")?, None);
+        assert_eq!(
+            b.get_bucket(7_u32.to_be_bytes()).err(),
+            Some(Error::IncompatibleValue)
+        );
+        assert_eq!(
+            b.get_bucket("nested")?.get_kv("key")?.as_deref(),
+            Some(&b"value"[..])
+        );
+
+        // share the snapshot between threads
+        let counts: Vec<usize> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..4_u32)
+                .map(|t| {
+                    let read_tx = &read_tx;
+                    s.spawn(move || {
+                        let b = read_tx.get_bucket("abc").unwrap();
+                        b.iter()
+                            .filter(|data| match data.as_ref().unwrap() {
+                                ReadData::KeyValue(k, v) => {
+                                    let i = u32::from_be_bytes((*k).try_into().unwrap());
+                                    assert_eq!(*v, i.to_string().as_bytes());
+                                    i % 4 == t
+                                }
+                                ReadData::Bucket(_) => false,
+                            })
+                            .count()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(counts, vec![250; 4]);
+
+        // the iterator returns everything in order
+        let keys: Vec<&[u8]> = b.iter().map(|data| data.unwrap().key()).collect();
+        let tx = db.tx(false)?;
+        let expected: Vec<Vec<u8>> = tx
+            .get_bucket("abc")?
+            .cursor()
+            .map(|data| data.key().to_vec())
+            .filter(|key| key != b"new")
+            .collect();
+        assert_eq!(keys, expected);
+        drop(tx);
+
+        // the snapshot can be sent to another thread
+        std::thread::spawn(move || {
+            assert_eq!(read_tx.get_bucket("abc").unwrap().iter().count(), 1001);
+        })
+        .join()
+        .unwrap();
+        db.check()
+    }
+
+    #[test]
+    fn test_read_tx_resize() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        db.update(|tx| tx.create_bucket("abc").map(|_| ()))?;
+        let read_tx = db.read_tx()?;
+        let size = random_file.path.metadata()?.len();
+        // the writer can grow the file while the snapshot is open
+        db.update(|tx| {
+            let b = tx.get_bucket("abc")?;
+            for i in 0..1000_u32 {
+                b.put(i.to_be_bytes(), vec![0; 1024]).map(|_| ())?;
+            }
+            Ok::<_, Error>(())
+        })?;
+        let grown = random_file.path.metadata()?.len();
+        assert!(grown > size);
+        assert_eq!(read_tx.get_bucket("abc")?.iter().count(), 0);
+        assert_eq!(db.read_tx()?.get_bucket("abc")?.iter().count(), 1000);
+
+        db.update(|tx| tx.delete_bucket("abc"))?;
+        let handle = {
+            let db = db.clone();
+            std::thread::spawn(move || db.shrink())
+        };
+        // but it can't truncate the file until the snapshot is dropped
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!handle.is_finished());
+        assert_eq!(read_tx.get_bucket("abc")?.iter().count(), 0);
+        drop(read_tx);
+        handle.join().unwrap()?;
+        assert!(random_file.path.metadata()?.len() < grown);
+        Ok(())
+    }
+
+    #[test]
+    fn test_par_chunks() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        db.update(|tx| {
+            tx.create_bucket("empty")?;
+            tx.create_bucket("small")?.put("a", "b")?;
+            let b = tx.create_bucket("abc")?;
+            for i in 0..10_000_u32 {
+                b.put(i.to_be_bytes(), i.to_string())?;
+            }
+            Ok::<_, Error>(())
+        })?;
+        let tx = db.read_tx()?;
+        assert_eq!(tx.get_bucket("empty")?.par_chunks(4).len(), 1);
+        assert_eq!(tx.get_bucket("empty")?.par_chunks(4).remove(0).count(), 0);
+        assert_eq!(tx.get_bucket("small")?.par_chunks(4).len(), 1);
+
+        let b = tx.get_bucket("abc")?;
+        let stats = db.tx(false)?.get_bucket("abc")?.stats()?;
+        assert!(stats.depth >= 3);
+        let expected: Vec<ReadData> = b.iter().collect::<Result<_>>()?;
+        assert_eq!(expected.len(), 10_000);
+        for n in [1, 2, 3, 8, 50, 100, 1000, 20_000] {
+            let chunks = b.par_chunks(n);
+            // there aren't enough leaf elements to make more than 10,000 chunks
+            assert_eq!(chunks.len(), n.min(10_000), "{}", n);
+            let chained: Vec<ReadData> = chunks.into_iter().flatten().collect::<Result<_>>()?;
+            assert_eq!(chained, expected, "{}", n);
+        }
+
+        let counts: Vec<usize> = std::thread::scope(|s| {
+            let handles: Vec<_> = b
+                .par_chunks(4)
+                .into_iter()
+                .map(|chunk| s.spawn(move || chunk.count()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(counts.len(), 4);
+        assert_eq!(counts.iter().sum::<usize>(), 10_000);
+        assert!(counts.iter().all(|count| *count > 1000));
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_iter() -> Result<()> {
+        use rayon::iter::ParallelIterator;
+
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        db.update(|tx| {
+            let b = tx.create_bucket("abc")?;
+            for i in 0..10_000_u32 {
+                b.put(i.to_be_bytes(), i.to_string())?;
+            }
+            Ok::<_, Error>(())
+        })?;
+        let tx = db.read_tx()?;
+        let b = tx.get_bucket("abc")?;
+        let sum: u64 = b
+            .par_iter()
+            .map(|data| match data.unwrap() {
+                ReadData::KeyValue(_, v) => {
+                    std::str::from_utf8(&v).unwrap().parse::<u64>().unwrap()
+                }
+                ReadData::Bucket(_) => 0,
+            })
+            .sum();
+        assert_eq!(sum, (0..10_000).sum::<u64>());
+        Ok(())
+    }
+}
@@ -0,0 +1,625 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::{Error as IOError, ErrorKind},
+    path::Path,
+    sync::Arc,
+};
+
+use memmap2::Mmap;
+use page_size::get as get_page_size;
+
+use crate::{
+    bucket::{BucketMeta, BucketOptions, META_SIZE, SHORT_META_SIZE},
+    compact::Compactor,
+    compression::Compression,
+    db::{lock_file, mmap, read_meta, OpenOptions, DB},
+    encryption::Cipher,
+    errors::{Error, Result},
+    meta::RootMeta,
+    node::{Node, NodeType},
+    page::{Page, PageID, Pages},
+};
+
+/// The result of recovering a database.
+///
+/// Returned by [`DB::recover`](struct.DB.html#method.recover).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoverReport {
+    /// The transaction that was recovered, or `None` if neither of the meta pages were valid
+    /// and the data was found by scanning the file.
+    pub tx_id: Option<u64>,
+    /// Number of buckets copied into the new file, including nested buckets.
+    pub buckets: u64,
+    /// Number of key / value pairs copied into the new file.
+    pub kv_pairs: u64,
+    /// Pages that couldn't be read. Everything on them, or below them in the tree, is missing from the new file.
+    pub lost_pages: Vec<u64>,
+    /// Values that couldn't be decompressed, either because they are corrupted or because the
+    /// codec's feature isn't enabled. Each one is the path of the bucket it was in, followed by its key.
+    pub lost_values: Vec<Vec<Vec<u8>>>,
+    /// Paths of the buckets that were created with a custom [`Comparator`](struct.Comparator.html).
+    /// They are recovered using the default bytewise comparator, so their keys may be in a different order.
+    pub custom_comparators: Vec<Vec<Vec<u8>>>,
+}
+
+impl DB {
+    /// Salvages whatever it can from a damaged database file into a new one.
+    ///
+    /// Use this when opening the database returns a [`NoValidMeta`](enum.Error.html#variant.NoValidMeta)
+    /// or [`Corruption`](enum.Error.html#variant.Corruption) error, or [`verify`](#method.verify) finds issues.
+    /// The file at `path` is only read, and the data is copied into a new file at `new_path`,
+    /// which is created using the given [`OpenOptions`] and must not already exist.
+    /// The options' encryption key is also used to read the damaged file,
+    /// along with its pagesize if the pagesize can't be read from the file.
+    ///
+    /// The two meta pages at the start of the file point to the database's root from the last two transactions.
+    /// If every page from the newest one can be read, that is what gets recovered.
+    /// Otherwise, whichever has the most key / value pairs is recovered, skipping any pages that are corrupted.
+    /// If neither meta page is valid, the file is scanned for pages that look like the root of the database instead,
+    /// which may find an older version of your data.
+    ///
+    /// Buckets that were created with a custom [`Comparator`](struct.Comparator.html) are recovered using the default bytewise comparator,
+    /// and listed in the report's [`custom_comparators`](struct.RecoverReport.html#structfield.custom_comparators).
+    /// Compressed values that can't be decompressed are skipped and listed in [`lost_values`](struct.RecoverReport.html#structfield.lost_values),
+    /// and buckets using a codec whose feature isn't enabled are recovered without compression.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::{DB, OpenOptions};
+    /// # use jammdb::Error;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let report = DB::recover("my.db", "my-recovered.db", OpenOptions::new())?;
+    /// if !report.lost_pages.is_empty() {
+    ///     println!("{} pages were lost", report.lost_pages.len());
+    /// }
+    /// let db = DB::open("my-recovered.db")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the new file already exists or can't be created, or if there are issues opening or locking the damaged file.
+    /// An [`InvalidKey`](enum.Error.html#variant.InvalidKey) error is returned if nothing could be decrypted with the encryption key,
+    /// and an [`InvalidDB`](enum.Error.html#variant.InvalidDB) error if there is nothing to recover at all.
+    pub fn recover<P: AsRef<Path>, Q: AsRef<Path>>(
+        path: P,
+        new_path: Q,
+        options: OpenOptions,
+    ) -> Result<RecoverReport> {
+        let new_path = new_path.as_ref();
+        if new_path.exists() {
+            return Err(IOError::new(
+                ErrorKind::AlreadyExists,
+                format!("cannot recover into existing file {:?}", new_path),
+            )
+            .into());
+        }
+        let file = File::open(path)?;
+        lock_file(&file, true, options.flags.lock_timeout)?;
+        let data = Arc::new(mmap(&file, false)?);
+        let cipher = options.flags.cipher.clone();
+
+        let mut invalid_key = false;
+        let mut candidates = find_metas(&data, options.pagesize);
+        if candidates.is_empty() {
+            candidates = scan_roots(&data, options.pagesize, &cipher, &mut invalid_key);
+        }
+        let mut best: Option<(Candidate, RecoverReport)> = None;
+        for (i, candidate) in candidates.into_iter().enumerate() {
+            let mut s = Salvager::new(&data, &candidate, &cipher);
+            s.walk(candidate.root.root_page, &mut Vec::new(), &mut |_| Ok(()))?;
+            invalid_key |= s.invalid_key;
+            let complete = s.report.lost_pages.is_empty();
+            let better = match &best {
+                Some((_, best)) => {
+                    (s.report.kv_pairs, s.report.buckets) > (best.kv_pairs, best.buckets)
+                }
+                None => true,
+            };
+            if better {
+                best = Some((candidate, s.report));
+            }
+            // the newest transaction is always recovered if nothing is missing from it
+            if i == 0 && complete && best.as_ref().unwrap().0.tx_id.is_some() {
+                break;
+            }
+        }
+        let candidate = match best {
+            Some((_, report)) if invalid_key && report.buckets == 0 => {
+                return Err(Error::InvalidKey)
+            }
+            Some((candidate, _)) => candidate,
+            None if invalid_key => return Err(Error::InvalidKey),
+            None => {
+                return Err(Error::InvalidDB(String::from(
+                    "Could not find any data to recover",
+                )))
+            }
+        };
+
+        let dst = options.open(new_path)?;
+        let mut c = Compactor::new(&dst);
+        // putting data increments next_int, so overwrite it once everything has been copied
+        let mut next_ints = Vec::new();
+        let mut s = Salvager::new(&data, &candidate, &cipher);
+        s.walk(
+            candidate.root.root_page,
+            &mut Vec::new(),
+            &mut |item| match item {
+                Salvaged::Bucket(path, meta) => {
+                    next_ints.push((path.to_vec(), meta.next_int));
+                    let compression = Compression::from_flag(meta.compression as u8)
+                        .filter(|compression| compression.check_enabled().is_ok())
+                        .unwrap_or_default();
+                    c.bucket(path, Some(BucketOptions::new().compression(compression)))?;
+                    Ok(())
+                }
+                Salvaged::KeyValue(path, key, value) => c.put(path, key, value),
+            },
+        )?;
+        for (path, next_int) in next_ints {
+            c.set_next_int(&path, next_int)?;
+        }
+        if candidate.tx_id.is_some() {
+            c.set_next_int(&[], candidate.root.next_int)?;
+        }
+        c.commit()?;
+        Ok(s.report)
+    }
+}
+
+// A root the database can be recovered from
+struct Candidate {
+    tx_id: Option<u64>,
+    root: RootMeta,
+    pagesize: u64,
+    // pages at or past this one don't belong to the database
+    num_pages: u64,
+    checksums: bool,
+}
+
+// Finds the valid meta pages, newest first.
+fn find_metas(data: &[u8], pagesize: u64) -> Vec<Candidate> {
+    let mut metas = Vec::new();
+    match read_meta(data, 0, pagesize) {
+        // the first meta page tells us the pagesize
+        Some(meta) if meta.pagesize >= 1024 => {
+            let pagesize = meta.pagesize;
+            metas.extend(read_meta(data, 1, pagesize).filter(|m| m.pagesize == pagesize));
+            metas.push(meta);
+        }
+        // otherwise try the likely pagesizes until we find the second one
+        _ => metas.extend(
+            [pagesize, get_page_size() as u64]
+                .into_iter()
+                .chain((10..=16).map(|shift| 1 << shift))
+                .find_map(|pagesize| {
+                    read_meta(data, 1, pagesize).filter(|m| m.pagesize == pagesize)
+                }),
+        ),
+    }
+    metas.sort_by_key(|meta| std::cmp::Reverse(meta.tx_id));
+    metas
+        .into_iter()
+        .map(|meta| Candidate {
+            tx_id: Some(meta.tx_id),
+            root: meta.root,
+            pagesize: meta.pagesize,
+            num_pages: meta.num_pages.min(data.len() as u64 / meta.pagesize),
+            checksums: meta.has_checksums(),
+        })
+        .collect()
+}
+
+// Without any meta pages, look for branch and leaf pages that no other page points to.
+// One of them should be the root of the database, but others will be left over from older transactions.
+fn scan_roots(
+    data: &Arc<Mmap>,
+    pagesize: u64,
+    cipher: &Option<Arc<Cipher>>,
+    invalid_key: &mut bool,
+) -> Vec<Candidate> {
+    let num_pages = data.len() as u64 / pagesize;
+    let pages = Pages::new(data.clone(), pagesize, cipher.clone(), false);
+    let checked = Pages::new(data.clone(), pagesize, cipher.clone(), true);
+    let mut roots = Vec::new();
+    let mut children = HashSet::new();
+    for page_id in 2..num_pages {
+        let page = match read_page(&pages, page_id, num_pages) {
+            Ok(page) => page,
+            Err(e) => {
+                *invalid_key |= e == Error::InvalidKey;
+                continue;
+            }
+        };
+        match page.page_type {
+            Page::TYPE_BRANCH => children.extend(page.branch_elements().iter().map(|b| b.page)),
+            _ => children.extend(
+                page.leaf_elements()
+                    .iter()
+                    .filter_map(|l| bucket_meta(l.node_type, l.value()))
+                    .map(|meta| meta.root_page),
+            ),
+        }
+        roots.push(page_id);
+    }
+    roots
+        .into_iter()
+        .filter(|page_id| !children.contains(page_id))
+        .map(|page_id| Candidate {
+            tx_id: None,
+            root: RootMeta {
+                root_page: page_id,
+                next_int: 0,
+            },
+            pagesize,
+            num_pages,
+            // pages written by versions with checksums will all match them
+            checksums: checked.page(page_id).is_ok(),
+        })
+        .collect()
+}
+
+// Reads a branch or leaf page, making sure it fits in the database, can be decrypted,
+// matches its checksum if the database has them, and that every element on it is within the page.
+fn read_page<'a>(pages: &Pages, page_id: PageID, num_pages: u64) -> Result<&'a Page> {
+    if page_id < 2 || page_id >= num_pages {
+        return Err(Error::InvalidDB(format!(
+            "Page {} is past the end of the file",
+            page_id
+        )));
+    }
+    let overflow = pages.raw_page(page_id).overflow;
+    if overflow >= num_pages - page_id {
+        return Err(Error::InvalidDB(format!(
+            "Page {} is past the end of the file",
+            page_id
+        )));
+    }
+    let page = pages.page(page_id)?;
+    if !page.elements_in_bounds(((overflow + 1) * pages.pagesize) as usize) {
+        return Err(Error::Corruption { page_id });
+    }
+    Ok(page)
+}
+
+// Returns the bucket's metadata if the leaf element holds a bucket that can be recovered
+fn bucket_meta(node_type: NodeType, value: &[u8]) -> Option<BucketMeta> {
+    if node_type & Node::TYPE_MASK != Node::TYPE_BUCKET
+        || (value.len() != SHORT_META_SIZE && value.len() != META_SIZE)
+    {
+        return None;
+    }
+    let meta = BucketMeta::from(value);
+    match u8::try_from(meta.compression) {
+        Ok(flag) if Compression::valid_flag(flag) => Some(meta),
+        _ => None,
+    }
+}
+
+enum Salvaged<'a> {
+    Bucket(&'a [Vec<u8>], BucketMeta),
+    // the value has already been decompressed
+    KeyValue(&'a [Vec<u8>], &'a [u8], &'a [u8]),
+}
+
+// Walks the tree from a candidate root, skipping any pages that can't be read
+struct Salvager {
+    pages: Pages,
+    num_pages: u64,
+    seen: HashSet<PageID>,
+    invalid_key: bool,
+    report: RecoverReport,
+}
+
+impl Salvager {
+    fn new(data: &Arc<Mmap>, candidate: &Candidate, cipher: &Option<Arc<Cipher>>) -> Salvager {
+        Salvager {
+            pages: Pages::new(
+                data.clone(),
+                candidate.pagesize,
+                cipher.clone(),
+                candidate.checksums,
+            ),
+            num_pages: candidate.num_pages,
+            seen: HashSet::new(),
+            invalid_key: false,
+            report: RecoverReport {
+                tx_id: candidate.tx_id,
+                ..RecoverReport::default()
+            },
+        }
+    }
+
+    fn walk<F: FnMut(Salvaged) -> Result<()>>(
+        &mut self,
+        page_id: PageID,
+        path: &mut Vec<Vec<u8>>,
+        f: &mut F,
+    ) -> Result<()> {
+        // a page that has already been copied means the tree points back into itself
+        if !self.seen.insert(page_id) {
+            return Ok(());
+        }
+        let page = match read_page(&self.pages, page_id, self.num_pages) {
+            Ok(page) => page,
+            Err(e) => {
+                self.invalid_key |= e == Error::InvalidKey;
+                self.report.lost_pages.push(page_id);
+                return Ok(());
+            }
+        };
+        if page.page_type == Page::TYPE_BRANCH {
+            for b in page.branch_elements() {
+                self.walk(b.page, path, f)?;
+            }
+            return Ok(());
+        }
+        for l in page.leaf_elements() {
+            if let Some(meta) = bucket_meta(l.node_type, l.value()) {
+                path.push(l.key().to_vec());
+                f(Salvaged::Bucket(path, meta))?;
+                self.report.buckets += 1;
+                if meta.comparator != 0 {
+                    self.report.custom_comparators.push(path.clone());
+                }
+                self.walk(meta.root_page, path, f)?;
+                path.pop();
+            } else if l.node_type & Node::TYPE_MASK == Node::TYPE_DATA
+                && Compression::valid_flag(l.node_type >> Node::COMPRESSION_SHIFT)
+                // the root bucket can only hold other buckets
+                && !path.is_empty()
+            {
+                let compression = Compression::from_flag(l.node_type >> Node::COMPRESSION_SHIFT);
+                match compression.map(|compression| compression.decompress_slice(l.value())) {
+                    Some(Ok(value)) => {
+                        f(Salvaged::KeyValue(path, l.key(), &value))?;
+                        self.report.kv_pairs += 1;
+                    }
+                    _ => {
+                        let mut key = path.clone();
+                        key.push(l.key().to_vec());
+                        self.report.lost_values.push(key);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::OpenOptions as FileOpenOptions,
+        io::{Seek, SeekFrom, Write},
+    };
+
+    use super::*;
+    use crate::{page::HEADER_SIZE, testutil::RandomFile};
+
+    fn corrupt(random_file: &RandomFile, offset: u64, data: &[u8]) -> Result<()> {
+        let mut file = FileOpenOptions::new().write(true).open(random_file)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    fn options() -> OpenOptions {
+        OpenOptions::new().pagesize(1024)
+    }
+
+    fn fill(db: &DB, name: &str, n: u32) -> Result<()> {
+        let tx = db.tx(true)?;
+        let b = tx.get_or_create_bucket(name)?;
+        let nested = b.get_or_create_bucket("nested")?;
+        for i in 0..n {
+            b.put(i.to_be_bytes(), vec![1; 64])?;
+            nested.put(i.to_be_bytes(), vec![2; 16])?;
+        }
+        tx.commit()
+    }
+
+    #[test]
+    fn test_recover_without_meta() -> Result<()> {
+        let random_file = RandomFile::new();
+        let recovered = RandomFile::new();
+        let next_int = {
+            let db = options().open(&random_file)?;
+            fill(&db, "abc", 500)?;
+            let tx = db.tx(true)?;
+            let b = tx.get_bucket("abc")?;
+            b.put("next", "int")?;
+            let next_int = b.next_int();
+            tx.commit()?;
+            next_int
+        };
+        corrupt(&random_file, 0, &[0; 2048])?;
+        assert_eq!(options().open(&random_file).err(), Some(Error::NoValidMeta));
+
+        let report = DB::recover(&random_file, &recovered, options())?;
+        assert_eq!(
+            report,
+            RecoverReport {
+                tx_id: None,
+                buckets: 2,
+                kv_pairs: 1001,
+                lost_pages: vec![],
+                lost_values: vec![],
+                custom_comparators: vec![],
+            }
+        );
+        let db = options().open(&recovered)?;
+        db.check()?;
+        let tx = db.tx(false)?;
+        let b = tx.get_bucket("abc")?;
+        assert_eq!(b.kv_pairs().count(), 501);
+        assert_eq!(b.next_int(), next_int);
+        assert_eq!(b.get_bucket("nested")?.kv_pairs().count(), 500);
+        assert_eq!(b.get_kv("next").unwrap().value(), b"int");
+
+        // recovering never overwrites a file
+        let result = DB::recover(&random_file, &recovered, options());
+        assert!(matches!(result, Err(Error::Io(e)) if e.kind() == ErrorKind::AlreadyExists));
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_older_tx() -> Result<()> {
+        let random_file = RandomFile::new();
+        let recovered = RandomFile::new();
+        let (old_tx_id, root_page) = {
+            let db = options().open(&random_file)?;
+            fill(&db, "abc", 100)?;
+            let old_tx_id = db.tx(false)?.inner.borrow().meta.tx_id;
+            fill(&db, "def", 100)?;
+            let root_page = db.tx(false)?.inner.borrow().meta.root.root_page;
+            (old_tx_id, root_page)
+        };
+        // the newest transaction's root can't be read, but the previous transaction's still can
+        corrupt(&random_file, root_page * 1024 + 40, &[0xFF; 16])?;
+
+        let report = DB::recover(&random_file, &recovered, options())?;
+        assert_eq!(
+            report,
+            RecoverReport {
+                tx_id: Some(old_tx_id),
+                buckets: 2,
+                kv_pairs: 200,
+                lost_pages: vec![],
+                lost_values: vec![],
+                custom_comparators: vec![],
+            }
+        );
+        let db = options().open(&recovered)?;
+        let tx = db.tx(false)?;
+        assert_eq!(tx.get_bucket("abc")?.kv_pairs().count(), 100);
+        assert_eq!(tx.get_bucket("def").err(), Some(Error::BucketMissing));
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_lost_pages() -> Result<()> {
+        let random_file = RandomFile::new();
+        let recovered = RandomFile::new();
+        let (tx_id, leaf) = {
+            let db = options().open(&random_file)?;
+            fill(&db, "abc", 1000)?;
+            let tx = db.tx(false)?;
+            let b = tx.get_bucket("abc")?;
+            let root_page = b.inner.borrow().meta.root_page;
+            let tx = tx.inner.borrow();
+            let root = tx.pages.page(root_page)?;
+            assert_eq!(root.page_type, Page::TYPE_BRANCH);
+            (tx.meta.tx_id, root.branch_elements()[1].page)
+        };
+        corrupt(&random_file, leaf * 1024 + 40, &[0xFF; 16])?;
+
+        let report = DB::recover(&random_file, &recovered, options())?;
+        assert_eq!(report.tx_id, Some(tx_id));
+        assert_eq!(report.buckets, 2);
+        assert_eq!(report.lost_pages, vec![leaf]);
+        assert!(report.kv_pairs > 1500 && report.kv_pairs < 2000);
+
+        let db = options().open(&recovered)?;
+        db.check()?;
+        let tx = db.tx(false)?;
+        let b = tx.get_bucket("abc")?;
+        let nested = b.get_bucket("nested")?;
+        assert_eq!(nested.kv_pairs().count(), 1000);
+        assert_eq!(b.kv_pairs().count() as u64 + 1000, report.kv_pairs);
+        assert!(b.kv_pairs().all(|kv| kv.value() == [1; 64]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_lost_values() -> Result<()> {
+        let random_file = RandomFile::new();
+        let recovered = RandomFile::new();
+        let reverse = crate::Comparator::new("reverse", |a, b| b.cmp(a));
+        let def = {
+            let db = options().open(&random_file)?;
+            let tx = db.tx(true)?;
+            let abc = tx.create_bucket_with("abc", BucketOptions::new().comparator(reverse))?;
+            abc.put("a", "1")?;
+            let def = tx.create_bucket("def")?;
+            def.put("a", "1")?;
+            def.put("b", "2")?;
+            tx.commit()?;
+            let tx = db.tx(false)?;
+            let root_page = tx.get_bucket("def")?.inner.borrow().meta.root_page;
+            root_page
+        };
+        // mark the value of "a" as compressed, which it isn't,
+        // turning off checksums like an older version would so the page can still be read
+        let compressed = Compression::Lz4.flag() << Node::COMPRESSION_SHIFT;
+        corrupt(&random_file, def * 1024 + HEADER_SIZE as u64, &[compressed])?;
+        let flags = HEADER_SIZE + std::mem::size_of::<crate::meta::Meta>() - 8;
+        for meta_page in 0..2 {
+            corrupt(&random_file, meta_page * 1024 + flags as u64, &[0; 8])?;
+        }
+
+        let report = DB::recover(&random_file, &recovered, options())?;
+        assert_eq!(report.buckets, 2);
+        assert_eq!(report.kv_pairs, 2);
+        assert_eq!(
+            report.lost_values,
+            vec![vec![b"def".to_vec(), b"a".to_vec()]]
+        );
+        assert_eq!(report.custom_comparators, vec![vec![b"abc".to_vec()]]);
+        let db = options().open(&recovered)?;
+        db.check()?;
+        let tx = db.tx(false)?;
+        let def = tx.get_bucket("def")?;
+        assert!(def.get_kv("a").is_none());
+        assert_eq!(def.get_kv("b").unwrap().value(), b"2");
+        assert_eq!(tx.get_bucket("abc")?.get_kv("a").unwrap().value(), b"1");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_recover_encrypted() -> Result<()> {
+        let random_file = RandomFile::new();
+        let recovered = RandomFile::new();
+        let key = [5; 32];
+        {
+            let db = options().encryption_key(key).open(&random_file)?;
+            fill(&db, "abc", 300)?;
+        }
+        corrupt(&random_file, 0, &[0; 2048])?;
+        let result = DB::recover(&random_file, &recovered, options().encryption_key([6; 32]));
+        assert_eq!(result.err(), Some(Error::InvalidKey));
+        assert!(!recovered.path.exists());
+
+        let report = DB::recover(&random_file, &recovered, options().encryption_key(key))?;
+        assert_eq!(report.kv_pairs, 600);
+        assert!(report.lost_pages.is_empty());
+        let db = options().encryption_key(key).open(&recovered)?;
+        db.check()?;
+        assert_eq!(
+            db.tx(false)?
+                .get_bucket("abc")?
+                .get_bucket("nested")?
+                .kv_pairs()
+                .count(),
+            300
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_nothing() -> Result<()> {
+        let random_file = RandomFile::new();
+        let recovered = RandomFile::new();
+        std::fs::write(&random_file, vec![0; 8192])?;
+        let result = DB::recover(&random_file, &recovered, options());
+        assert!(matches!(result, Err(Error::InvalidDB(_))));
+        Ok(())
+    }
+}
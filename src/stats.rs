@@ -0,0 +1,322 @@
+use std::mem::{size_of, size_of_val};
+
+use crate::{
+    bucket::{Bucket, BucketMeta},
+    db::DB,
+    errors::Result,
+    node::Node,
+    page::{Page, PageID, Pages},
+};
+
+// Size of a page header, not counting the elements that start at `Page::ptr`
+const PAGE_HEADER_SIZE: u64 = (size_of::<Page>() - size_of::<u64>()) as u64;
+
+/// Statistics about a bucket and all of its nested buckets.
+///
+/// These are modeled on BoltDB's `BucketStats`, and are calculated from the pages stored on disk,
+/// so changes that haven't been committed yet are not included.
+/// You can get them using [`Bucket::stats`](struct.Bucket.html#method.stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BucketStats {
+    /// Number of logical branch pages.
+    pub branch_pages: u64,
+    /// Number of physical branch overflow pages.
+    pub branch_overflow: u64,
+    /// Number of logical leaf pages.
+    pub leaf_pages: u64,
+    /// Number of physical leaf overflow pages.
+    pub leaf_overflow: u64,
+
+    /// Number of keys, including the names of nested buckets.
+    pub key_count: u64,
+    /// Number of levels in the deepest B+tree.
+    pub depth: u64,
+
+    /// Bytes allocated for physical branch pages.
+    pub branch_alloc: u64,
+    /// Bytes actually used for branch data.
+    pub branch_inuse: u64,
+    /// Bytes allocated for physical leaf pages.
+    pub leaf_alloc: u64,
+    /// Bytes actually used for leaf data.
+    pub leaf_inuse: u64,
+
+    /// Total number of buckets, including this one.
+    pub bucket_count: u64,
+    /// Number of buckets stored inline in their parent's leaf page.
+    ///
+    /// Unlike BoltDB, jammdb always gives a bucket its own pages, so this is always zero.
+    pub inline_bucket_count: u64,
+    /// Bytes used by inline buckets. Always zero, see `inline_bucket_count`.
+    pub inline_bucket_inuse: u64,
+}
+
+impl BucketStats {
+    fn add(&mut self, other: &BucketStats) {
+        self.branch_pages += other.branch_pages;
+        self.branch_overflow += other.branch_overflow;
+        self.leaf_pages += other.leaf_pages;
+        self.leaf_overflow += other.leaf_overflow;
+        self.key_count += other.key_count;
+        self.depth = self.depth.max(other.depth);
+        self.branch_alloc += other.branch_alloc;
+        self.branch_inuse += other.branch_inuse;
+        self.leaf_alloc += other.leaf_alloc;
+        self.leaf_inuse += other.leaf_inuse;
+        self.bucket_count += other.bucket_count;
+        self.inline_bucket_count += other.inline_bucket_count;
+        self.inline_bucket_inuse += other.inline_bucket_inuse;
+    }
+
+    // walks every page in the bucket starting at the given root page
+    pub(crate) fn from_pages(pages: &Pages, root_page: PageID) -> Result<BucketStats> {
+        let mut stats = BucketStats {
+            bucket_count: 1,
+            ..BucketStats::default()
+        };
+        // buckets that have never been committed don't have any pages yet
+        if root_page == 0 {
+            return Ok(stats);
+        }
+        let mut page_stack = vec![(root_page, 1)];
+        while let Some((page_id, depth)) = page_stack.pop() {
+            let page = pages.page(page_id)?;
+            stats.depth = stats.depth.max(depth);
+            let alloc = (page.overflow + 1) * pages.pagesize;
+            match page.page_type {
+                Page::TYPE_BRANCH => {
+                    let elements = page.branch_elements();
+                    stats.branch_pages += 1;
+                    stats.branch_overflow += page.overflow;
+                    stats.branch_alloc += alloc;
+                    stats.branch_inuse += PAGE_HEADER_SIZE
+                        + size_of_val(elements) as u64
+                        + elements.iter().map(|b| b.key().len() as u64).sum::<u64>();
+                    for b in elements.iter() {
+                        page_stack.push((b.page, depth + 1));
+                    }
+                }
+                Page::TYPE_LEAF => {
+                    let elements = page.leaf_elements();
+                    stats.leaf_pages += 1;
+                    stats.leaf_overflow += page.overflow;
+                    stats.leaf_alloc += alloc;
+                    stats.key_count += elements.len() as u64;
+                    stats.leaf_inuse += PAGE_HEADER_SIZE + size_of_val(elements) as u64;
+                    for leaf in elements.iter() {
+                        stats.leaf_inuse += (leaf.key().len() + leaf.value().len()) as u64;
+                        if leaf.node_type == Node::TYPE_BUCKET {
+                            let meta: BucketMeta = leaf.value().into();
+                            stats.add(&BucketStats::from_pages(pages, meta.root_page)?);
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(stats)
+    }
+}
+
+/// Statistics about the whole database.
+///
+/// You can get them using [`DB::stats`](struct.DB.html#method.stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The database's pagesize.
+    pub pagesize: u64,
+    /// Number of pages in use by the database, including free pages.
+    pub num_pages: u64,
+    /// Size of the database file in bytes.
+    /// The file grows in large chunks, so this is usually bigger than `num_pages * pagesize`.
+    pub file_size: u64,
+    /// Number of free pages that are ready to be reused.
+    pub free_pages: u64,
+    /// Number of pages that have been freed, but may still be used by open read-only transactions.
+    pub pending_pages: u64,
+    /// Bytes allocated for the freelist.
+    pub freelist_alloc: u64,
+    /// Bytes actually used by the freelist.
+    pub freelist_inuse: u64,
+    /// Combined statistics for every bucket in the database.
+    ///
+    /// This includes the root bucket that holds all of the top-level buckets,
+    /// so its `key_count` includes the names of the top-level buckets.
+    pub buckets: BucketStats,
+}
+
+impl DB {
+    /// Returns statistics about the pages and buckets in the database.
+    ///
+    /// This walks every page in the database from a read-only transaction, so it can take a while on a large database.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::DB;
+    /// # use jammdb::Error;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let db = DB::open("my.db")?;
+    /// let stats = db.stats()?;
+    /// println!(
+    ///     "{} of {} pages are free, {} keys in {} buckets",
+    ///     stats.free_pages,
+    ///     stats.num_pages,
+    ///     stats.buckets.key_count,
+    ///     stats.buckets.bucket_count,
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn stats(&self) -> Result<Stats> {
+        let tx = self.tx(false)?;
+        let tx = tx.inner.borrow();
+        let freelist = tx.freelist.borrow();
+        let freelist_page = tx.pages.page(tx.meta.freelist_page)?;
+
+        Ok(Stats {
+            pagesize: tx.meta.pagesize,
+            num_pages: tx.meta.num_pages,
+            file_size: tx.pages.data.len() as u64,
+            free_pages: freelist.inner.free_count(),
+            pending_pages: freelist.inner.pending_count(),
+            freelist_alloc: (freelist_page.overflow + 1) * tx.meta.pagesize,
+            freelist_inuse: freelist.inner.size(),
+            buckets: BucketStats::from_pages(&tx.pages, tx.meta.root.root_page)?,
+        })
+    }
+}
+
+impl Bucket<'_, '_> {
+    /// Returns statistics about this bucket and all of its nested buckets.
+    ///
+    /// The statistics are calculated from the pages on disk,
+    /// so any changes made in this transaction that haven't been committed yet are not included.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Corruption`](enum.Error.html#variant.Corruption) if one of the bucket's pages can't be read.
+    pub fn stats(&self) -> Result<BucketStats> {
+        let b = self.inner.borrow();
+        if b.deleted {
+            panic!("Cannot get stats from a deleted bucket.");
+        }
+        BucketStats::from_pages(&b.pages, b.meta.root_page)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testutil::RandomFile, OpenOptions};
+
+    #[test]
+    fn test_bucket_stats() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket("abc")?;
+            b.put("a", "1")?;
+            b.put("bb", "22")?;
+            b.create_bucket("n")?;
+            // uncommitted buckets don't have any pages yet
+            let stats = tx.create_bucket("def")?.stats()?;
+            assert_eq!(
+                stats,
+                BucketStats {
+                    bucket_count: 1,
+                    ..BucketStats::default()
+                }
+            );
+            tx.commit()?;
+        }
+        {
+            let tx = db.tx(false)?;
+            let stats = tx.get_bucket("abc")?.stats()?;
+            assert_eq!(
+                stats,
+                BucketStats {
+                    leaf_pages: 2,
+                    key_count: 3,
+                    depth: 1,
+                    leaf_alloc: 2048,
+                    // two page headers, three elements and their keys / values
+                    leaf_inuse: (2 * 32) + (3 * 32) + (1 + 1) + (2 + 2) + (1 + 16),
+                    bucket_count: 2,
+                    ..BucketStats::default()
+                }
+            );
+        }
+        {
+            let tx = db.tx(true)?;
+            let b = tx.get_bucket("abc")?;
+            for i in 0..1000_u32 {
+                b.put(i.to_be_bytes(), vec![0; 64])?;
+            }
+            b.put("big", vec![0; 4000])?;
+            tx.commit()?;
+        }
+        let tx = db.tx(false)?;
+        let stats = tx.get_bucket("abc")?.stats()?;
+        assert_eq!(stats.key_count, 1004);
+        assert_eq!(stats.bucket_count, 2);
+        assert_eq!(stats.depth, 3);
+        assert!(stats.branch_pages >= 1);
+        assert!(stats.leaf_pages > 50);
+        assert!(stats.leaf_overflow >= 3);
+        assert_eq!(
+            stats.leaf_alloc,
+            (stats.leaf_pages + stats.leaf_overflow) * 1024
+        );
+        assert!(stats.leaf_inuse < stats.leaf_alloc);
+        assert!(stats.branch_inuse < stats.branch_alloc);
+        Ok(())
+    }
+
+    #[test]
+    fn test_db_stats() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        let stats = db.stats()?;
+        assert_eq!(stats.pagesize, 1024);
+        assert_eq!(stats.num_pages, 4);
+        assert_eq!(stats.free_pages, 0);
+        assert_eq!(stats.pending_pages, 0);
+        assert_eq!(stats.freelist_alloc, 1024);
+        assert_eq!(stats.freelist_inuse, 40);
+        assert_eq!(stats.buckets.bucket_count, 1);
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket("abc")?;
+            for i in 0..1000_u32 {
+                b.put(i.to_be_bytes(), vec![0; 64])?;
+            }
+            tx.create_bucket("def")?;
+            tx.commit()?;
+        }
+        let stats = db.stats()?;
+        assert_eq!(stats.file_size, random_file.path.metadata()?.len());
+        assert_eq!(stats.buckets.bucket_count, 3);
+        assert_eq!(stats.buckets.key_count, 1002);
+        {
+            let tx = db.tx(true)?;
+            tx.delete_bucket("abc")?;
+            tx.commit()?;
+        }
+        // the deleted pages are pending until the next writable transaction
+        let stats = db.stats()?;
+        assert_eq!(stats.buckets.bucket_count, 2);
+        assert!(stats.pending_pages > 50);
+        let pending_pages = stats.pending_pages;
+        db.tx(true)?.commit()?;
+        let stats = db.stats()?;
+        assert!(stats.free_pages >= pending_pages);
+        assert_eq!(
+            stats.freelist_inuse,
+            40 + 8 * (stats.free_pages + stats.pending_pages)
+        );
+        Ok(())
+    }
+}
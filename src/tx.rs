@@ -1,41 +1,56 @@
 use std::{
     cell::RefCell,
-    collections::HashSet,
     fs::File,
     io::{Seek, SeekFrom, Write},
     marker::PhantomData,
+    path::Path,
     rc::Rc,
-    sync::{MutexGuard, RwLockReadGuard},
+    sync::{MutexGuard, RwLockReadGuard, TryLockError},
 };
 
 use crate::{
-    bucket::{Bucket, BucketMeta, InnerBucket},
+    bucket::{Bucket, BucketOptions, InnerBucket},
     bytes::ToBytes,
+    comparator::Comparator,
     cursor::ToBuckets,
-    db::{DB, MIN_ALLOC_SIZE},
+    db::{SyncMode, DB, MIN_ALLOC_SIZE},
+    encryption::RESERVED_SIZE,
     errors::{Error, Result},
     freelist::TxFreelist,
     meta::Meta,
-    node::Node,
-    page::{Page, PageID, Pages},
+    page::{Page, Pages},
+    verify::{verify, VerifyIssue, VerifyOptions},
     BucketName,
 };
 
+// The writer is the only one who can hold a lock for long, so any contention means it is busy
+fn try_lock<T>(result: std::result::Result<T, TryLockError<T>>) -> Result<T> {
+    match result {
+        Ok(guard) => Ok(guard),
+        Err(TryLockError::WouldBlock) => Err(Error::WriterBusy),
+        Err(TryLockError::Poisoned(e)) => Err(e.into()),
+    }
+}
+
 pub(crate) enum TxLock<'tx> {
     Rw(MutexGuard<'tx, File>),
     #[allow(dead_code)] // "field `0` is never read" but need it for RAII
     Ro(RwLockReadGuard<'tx, ()>),
+    // The transaction is finished and has given up its lock
+    Released,
 }
 
 impl TxLock<'_> {
     fn writable(&self) -> bool {
         match self {
             Self::Rw(_) => true,
-            Self::Ro(_) => false,
+            Self::Ro(_) | Self::Released => false,
         }
     }
 }
 
+type Callback<'tx> = Box<dyn FnOnce() + 'tx>;
+
 /// An isolated view of the database
 ///
 /// Transactions are how you can interact with the database.
@@ -105,16 +120,39 @@ pub(crate) struct TxInner<'tx> {
     pub(crate) root: Rc<RefCell<InnerBucket<'tx>>>,
     pub(crate) meta: Meta,
     pub(crate) freelist: Rc<RefCell<TxFreelist>>,
-    pages: Pages,
+    pub(crate) pages: Pages,
     num_freelist_pages: u64,
+    pub(crate) shrink: bool,
+    on_commit: Vec<Callback<'tx>>,
+    on_rollback: Vec<Callback<'tx>>,
 }
 
 impl<'tx> Tx<'tx> {
     pub(crate) fn new(db: &'tx DB, writable: bool) -> Result<Tx<'tx>> {
+        if writable && db.inner.flags.read_only {
+            return Err(Error::ReadOnlyDB);
+        }
         let lock = match writable {
             true => TxLock::Rw(db.inner.file.lock()?),
             false => TxLock::Ro(db.inner.mmap_lock.read()?),
         };
+        Tx::from_lock(db, lock)
+    }
+
+    pub(crate) fn try_new(db: &'tx DB, writable: bool) -> Result<Tx<'tx>> {
+        if writable && db.inner.flags.read_only {
+            return Err(Error::ReadOnlyDB);
+        }
+        let lock = match writable {
+            true => TxLock::Rw(try_lock(db.inner.file.try_lock())?),
+            false => TxLock::Ro(try_lock(db.inner.mmap_lock.try_read())?),
+        };
+        Tx::from_lock(db, lock)
+    }
+
+    #[allow(clippy::len_zero)]
+    fn from_lock(db: &'tx DB, lock: TxLock<'tx>) -> Result<Tx<'tx>> {
+        let writable = lock.writable();
         let mut freelist = db.inner.freelist.lock()?.clone();
         let mut meta = db.inner.meta()?;
         debug_assert!(meta.valid());
@@ -132,12 +170,20 @@ impl<'tx> Tx<'tx> {
                 open_ro_txs.sort_unstable();
             }
         }
-        let freelist = Rc::new(RefCell::new(TxFreelist::new(meta.clone(), freelist)));
+        let reserved = match db.inner.flags.cipher {
+            Some(_) => RESERVED_SIZE,
+            None => 0,
+        };
+        let freelist = Rc::new(RefCell::new(TxFreelist::new(
+            meta.clone(),
+            freelist,
+            reserved,
+        )));
 
         let data = db.inner.data.lock()?.clone();
-        let pages = Pages::new(data, db.inner.pagesize);
-        let num_freelist_pages = pages.page(meta.freelist_page).overflow + 1;
-        let root = InnerBucket::from_meta(meta.root, pages.clone());
+        let pages = db.inner.pages(data);
+        let num_freelist_pages = pages.page(meta.freelist_page)?.overflow + 1;
+        let root = InnerBucket::from_meta(meta.root.into(), Comparator::BYTEWISE, pages.clone());
         let root = Rc::new(RefCell::new(root));
         let inner = TxInner {
             db,
@@ -147,6 +193,9 @@ impl<'tx> Tx<'tx> {
             freelist,
             num_freelist_pages,
             pages,
+            shrink: false,
+            on_commit: Vec::new(),
+            on_rollback: Vec::new(),
         };
         Ok(Tx {
             inner: RefCell::new(inner),
@@ -168,7 +217,7 @@ impl<'tx> Tx<'tx> {
     pub fn get_bucket<'b, T: ToBytes<'tx>>(&'b self, name: T) -> Result<Bucket<'b, 'tx>> {
         let tx = self.inner.borrow();
         let mut root = tx.root.borrow_mut();
-        let inner = root.get_bucket(name)?;
+        let inner = root.get_bucket(name, Some(Comparator::BYTEWISE))?;
         Ok(Bucket {
             inner,
             freelist: tx.freelist.clone(),
@@ -190,7 +239,56 @@ impl<'tx> Tx<'tx> {
             return Err(Error::ReadOnlyTx);
         }
         let mut root = tx.root.borrow_mut();
-        let inner = root.create_bucket(name)?;
+        let inner = root.create_bucket(name, BucketOptions::default())?;
+        Ok(Bucket {
+            inner,
+            freelist: tx.freelist.clone(),
+            writable: true,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Returns a reference to the root level bucket with the given name, using the given options.
+    ///
+    /// # Errors
+    ///
+    /// Will return a [`BucketMissing`](enum.Error.html#variant.BucketMissing) error if the bucket does not exist,
+    /// an [`IncompatibleValue`](enum.Error.html#variant.IncompatibleValue) error if the key exists but is not a bucket,
+    /// or a [`ComparatorMismatch`](enum.Error.html#variant.ComparatorMismatch) error if the bucket was created with a different [`Comparator`](struct.Comparator.html).
+    pub fn get_bucket_with<'b, T: ToBytes<'tx>>(
+        &'b self,
+        name: T,
+        options: BucketOptions,
+    ) -> Result<Bucket<'b, 'tx>> {
+        let tx = self.inner.borrow();
+        let mut root = tx.root.borrow_mut();
+        let inner = root.get_bucket(name, Some(options.comparator))?;
+        Ok(Bucket {
+            inner,
+            freelist: tx.freelist.clone(),
+            writable: tx.lock.writable(),
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Creates a new bucket with the given name and options, and returns a reference it.
+    ///
+    /// # Errors
+    ///
+    /// Will return a [`BucketExists`](enum.Error.html#variant.BucketExists) error if the bucket already exists,
+    /// an [`IncompatibleValue`](enum.Error.html#variant.IncompatibleValue) error if the key exists but is not a bucket,
+    /// or a [`ReadOnlyTx`](enum.Error.html#variant.ReadOnlyTx) error if this is called on a read-only transaction.
+    pub fn create_bucket_with<'b, T: ToBytes<'tx>>(
+        &'b self,
+        name: T,
+        options: BucketOptions,
+    ) -> Result<Bucket<'b, 'tx>> {
+        let tx = self.inner.borrow();
+        if !tx.lock.writable() {
+            return Err(Error::ReadOnlyTx);
+        }
+        let mut root = tx.root.borrow_mut();
+        let inner = root.create_bucket(name, options)?;
         Ok(Bucket {
             inner,
             freelist: tx.freelist.clone(),
@@ -212,7 +310,7 @@ impl<'tx> Tx<'tx> {
             return Err(Error::ReadOnlyTx);
         }
         let mut root = tx.root.borrow_mut();
-        let inner = root.get_or_create_bucket(name)?;
+        let inner = root.get_or_create_bucket(name, BucketOptions::default())?;
         Ok(Bucket {
             inner,
             freelist: tx.freelist.clone(),
@@ -240,37 +338,138 @@ impl<'tx> Tx<'tx> {
     }
 
     /// Iterator over the root level buckets
-    pub fn buckets<'b>(&'b self) -> impl Iterator<Item = (BucketName<'b, 'tx>, Bucket<'b, 'tx>)> {
+    pub fn buckets<'b>(
+        &'b self,
+    ) -> impl DoubleEndedIterator<Item = (BucketName<'b, 'tx>, Bucket<'b, 'tx>)> {
+        self.root().cursor().to_buckets()
+    }
+
+    // The bucket that holds all of the root level buckets.
+    pub(crate) fn root<'b>(&'b self) -> Bucket<'b, 'tx> {
         let tx = self.inner.borrow();
-        let bucket = Bucket {
+        Bucket {
             inner: tx.root.clone(),
             freelist: tx.freelist.clone(),
             writable: tx.lock.writable(),
             _phantom: PhantomData,
-        };
-        bucket.cursor().to_buckets()
+        }
+    }
+
+    /// Writes a consistent copy of the database, as seen by this transaction, to the given writer.
+    ///
+    /// Both meta pages are rewritten to point to this transaction's snapshot,
+    /// so the output is a complete database file that can be opened with [`DB::open`](struct.DB.html#method.open).
+    /// This makes it easy to take a backup of a live database, since a read-only transaction doesn't block the writer.
+    /// Changes that haven't been committed in a writable transaction are not included.
+    ///
+    /// Returns the number of bytes written.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::DB;
+    /// # use jammdb::Error;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let db = DB::open("my.db")?;
+    /// let tx = db.tx(false)?;
+    ///
+    /// let mut backup = Vec::new();
+    /// tx.write_to(&mut backup)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_to<W: Write>(&self, mut w: W) -> Result<u64> {
+        self.inner.borrow().write_to(&mut w)
+    }
+
+    /// Copies the database, as seen by this transaction, to a new file at the given path.
+    ///
+    /// If the file already exists it will be overwritten.
+    /// See [`write_to`](#method.write_to) for more details.
+    pub fn copy_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut file = File::create(path)?;
+        self.write_to(&mut file)?;
+        file.sync_all()?;
+        Ok(())
     }
 
     /// Writes the changes made in the writeable transaction to the underlying file.
     ///
+    /// Once the changes are safely on disk, the transaction's lock is released
+    /// and any callbacks registered with [`on_commit`](#method.on_commit) are called.
+    ///
     /// # Errors
     ///
     /// Will return an [`IOError`](enum.Error.html#variant.IOError) error if there are any io errors while writing to disk,
     /// or a [`ReadOnlyTx`](enum.Error.html#variant.ReadOnlyTx) error if this is called on a read-only transaction.
+    /// If the commit fails, the transaction is rolled back.
     pub fn commit(self) -> Result<()> {
         if !self.writable() {
             return Err(Error::ReadOnlyTx);
         }
-        let mut tx = self.inner.borrow_mut();
-        let freelist = tx.freelist.clone();
-        let mut freelist = freelist.borrow_mut();
-        let meta = {
-            let mut root = tx.root.borrow_mut();
-            root.rebalance(&mut freelist)?;
-            root.spill(&mut freelist)?
+        let on_commit = {
+            let mut tx = self.inner.borrow_mut();
+            let freelist = tx.freelist.clone();
+            let mut freelist = freelist.borrow_mut();
+            let meta = {
+                let mut root = tx.root.borrow_mut();
+                root.rebalance(&mut freelist)?;
+                root.spill(&mut freelist)?
+            };
+            tx.meta.root = meta.into();
+            tx.write_data(&mut freelist)?;
+            tx.on_rollback.clear();
+            std::mem::take(&mut tx.on_commit)
         };
-        tx.meta.root = meta;
-        tx.write_data(&mut freelist)
+        drop(self);
+        for f in on_commit {
+            f();
+        }
+        Ok(())
+    }
+
+    /// Discards all of the changes made in the transaction.
+    ///
+    /// This is the same as dropping the transaction, and any callbacks registered with
+    /// [`on_rollback`](#method.on_rollback) are called once the transaction's lock is released.
+    pub fn rollback(self) {
+        drop(self);
+    }
+
+    /// Registers a callback to run after the transaction is successfully committed.
+    ///
+    /// Callbacks run in the order they were registered, after the changes have been synced to disk
+    /// and the transaction's lock has been released, so it is safe to start another transaction from a callback.
+    /// If the transaction is rolled back, or is read-only, the callback is never called.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::DB;
+    /// # use jammdb::Error;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let db = DB::open("my.db")?;
+    /// let tx = db.tx(true)?;
+    /// tx.create_bucket("my-bucket")?;
+    /// tx.on_commit(|| println!("my-bucket was created"));
+    /// tx.on_rollback(|| println!("my-bucket was not created"));
+    /// tx.commit()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn on_commit<F: FnOnce() + 'tx>(&self, f: F) {
+        self.inner.borrow_mut().on_commit.push(Box::new(f));
+    }
+
+    /// Registers a callback to run when the transaction is rolled back.
+    ///
+    /// A transaction is rolled back when [`rollback`](#method.rollback) is called, when it is dropped without being committed,
+    /// or when [`commit`](#method.commit) fails. Read-only transactions are always rolled back.
+    /// Callbacks run in the order they were registered, after the transaction's lock has been released.
+    pub fn on_rollback<F: FnOnce() + 'tx>(&self, f: F) {
+        self.inner.borrow_mut().on_rollback.push(Box::new(f));
     }
 
     pub(crate) fn check(&self) -> Result<()> {
@@ -284,6 +483,9 @@ impl TxInner<'_> {
             // Write the freelist to a new page
             {
                 freelist.free(self.meta.freelist_page, self.num_freelist_pages);
+                if self.shrink || self.db.inner.flags.auto_shrink {
+                    freelist.trim();
+                }
                 let freelist_size = freelist.inner.size();
                 let page = freelist.allocate(freelist_size)?;
                 self.meta.freelist_page = page.id;
@@ -292,6 +494,7 @@ impl TxInner<'_> {
                 page.count = free_page_ids.len() as u64;
                 page.freelist_mut()
                     .copy_from_slice(free_page_ids.as_slice());
+                page.update_checksum();
             }
 
             // Update our num_pages from the freelist now that we've allocated everything
@@ -304,7 +507,7 @@ impl TxInner<'_> {
                 let size_diff = required_size - current_size;
                 let alloc_size = ((size_diff / MIN_ALLOC_SIZE) + 1) * MIN_ALLOC_SIZE;
                 let data = self.db.inner.resize(file, current_size + alloc_size)?;
-                self.pages = Pages::new(data, self.db.inner.pagesize);
+                self.pages = self.db.inner.pages(data);
             }
 
             // write the data to the file
@@ -314,179 +517,139 @@ impl TxInner<'_> {
                 for (page_id, (ptr, size)) in freelist.pages.iter() {
                     let buf = unsafe { std::slice::from_raw_parts(ptr.as_ptr(), *size) };
                     file.seek(SeekFrom::Start(self.db.inner.pagesize * page_id))?;
-                    file.write_all(buf)?;
+                    match &self.db.inner.flags.cipher {
+                        Some(cipher) => {
+                            // encrypt the whole block, since the nonce and tag go at the very end
+                            let page = unsafe { &*(ptr.as_ptr() as *const Page) };
+                            let mut block =
+                                vec![0; ((page.overflow + 1) * self.db.inner.pagesize) as usize];
+                            block[..*size].copy_from_slice(buf);
+                            cipher.encrypt(&mut block)?;
+                            file.write_all(&block)?;
+                        }
+                        None => file.write_all(buf)?,
+                    }
                 }
             }
         }
         if self.db.inner.flags.strict_mode {
             self.check()?;
         }
+        let meta_page_id = u64::from(self.meta.meta_page == 0);
+        let buf = self.meta_page(meta_page_id);
         if let TxLock::Rw(file) = &mut self.lock {
             // write meta page to file
             {
-                let mut buf = vec![0; self.db.inner.pagesize as usize];
-
-                // Safety: buffer is big enough to contain a page (see `assert!`)
-                let page = unsafe {
-                    assert!(buf.len() >= std::mem::size_of::<Page>());
-                    &mut *(buf.as_mut_ptr() as *mut Page)
-                };
-                let meta_page_id = u64::from(self.meta.meta_page == 0);
-                page.id = meta_page_id;
-                page.page_type = Page::TYPE_META;
-                let m = page.meta_mut();
-                m.meta_page = meta_page_id as u32;
-                m.magic = self.meta.magic;
-                m.version = self.meta.version;
-                m.pagesize = self.meta.pagesize;
-                m.root = self.meta.root;
-                m.num_pages = self.meta.num_pages;
-                m.freelist_page = self.meta.freelist_page;
-                m.tx_id = self.meta.tx_id;
-                m.hash = m.hash_self();
-
                 file.seek(SeekFrom::Start(self.db.inner.pagesize * meta_page_id))?;
                 file.write_all(buf.as_slice())?;
             }
 
             file.flush()?;
-            file.sync_all()?;
+            match self.db.inner.flags.sync_mode {
+                SyncMode::Full => file.sync_all()?,
+                SyncMode::MetaOnly => file.sync_data()?,
+                SyncMode::None | SyncMode::Periodic(_) => (),
+            }
 
-            let mut lock = self.db.inner.freelist.lock()?;
-            *lock = freelist.inner.clone();
+            // The commit has happened, so nothing after this point should make it look like it failed.
+            {
+                let mut lock = self.db.inner.freelist.lock()?;
+                *lock = freelist.inner.clone();
+            }
+
+            // Now that the meta page no longer points past the end of the file we can truncate it.
+            // The file grows in chunks, so when shrinking automatically leave some room to avoid
+            // truncating and growing it over and over again.
+            // Truncating only reclaims space, so if it fails the file just stays bigger than it needs to be.
+            let required_size = self.meta.num_pages * self.db.inner.pagesize;
+            if let Ok(metadata) = file.metadata() {
+                let current_size = metadata.len();
+                let new_size = if self.shrink {
+                    required_size
+                } else if self.db.inner.flags.auto_shrink
+                    && current_size > required_size + MIN_ALLOC_SIZE
+                {
+                    required_size + MIN_ALLOC_SIZE
+                } else {
+                    current_size
+                };
+                // An older meta page may still point at the pages we're about to drop,
+                // so the new one has to be on disk first, even if commits don't usually wait for it.
+                let meta_synced = || match self.db.inner.flags.sync_mode {
+                    SyncMode::Full | SyncMode::MetaOnly => true,
+                    SyncMode::None | SyncMode::Periodic(_) => file.sync_data().is_ok(),
+                };
+                if new_size < current_size && meta_synced() {
+                    if let Ok(data) = self.db.inner.resize(file, new_size) {
+                        self.pages = self.db.inner.pages(data);
+                    }
+                }
+            }
             Ok(())
         } else {
             unreachable!()
         }
     }
 
-    fn check(&self) -> Result<()> {
-        let mut unused_pages: HashSet<PageID> = (2..self.meta.num_pages).collect();
-        let mut page_stack = Vec::new();
-        page_stack.push(self.meta.root.root_page);
-        page_stack.push(self.meta.freelist_page);
-        while let Some(page_id) = page_stack.pop() {
-            // Make sure this page hasn't already been used
-            if !unused_pages.remove(&page_id) {
-                return Err(Error::InvalidDB(format!(
-                    "Page {} missing from unused_pages",
-                    page_id,
-                )));
-            }
-            let page = self.pages.page(page_id);
-            // Make sure none of the overflow pages have been used
-            for i in 0..page.overflow {
-                let page_id = page_id + i + 1;
-                if !unused_pages.remove(&page_id) {
-                    return Err(Error::InvalidDB(format!(
-                        "Overflow Page {} from missing from unused_pages",
-                        page_id,
-                    )));
-                }
-            }
-            // Check the page type and explore all possible pages
-            match page.page_type {
-                Page::TYPE_BRANCH => {
-                    let mut last: Option<&[u8]> = None;
-                    for b in page.branch_elements().iter() {
-                        // Make sure we visit every branch page
-                        page_stack.push(b.page);
-                        // and that the keys are in order
-                        if let Some(last) = last {
-                            if last >= b.key() {
-                                return Err(Error::InvalidDB(format!(
-                                    "Branch page {} contains unsorted elements",
-                                    page_id
-                                )));
-                            }
-                        }
-                        last = Some(b.key());
-                    }
-                }
-                Page::TYPE_LEAF => {
-                    let mut last: Option<&[u8]> = None;
-                    for (i, leaf) in page.leaf_elements().iter().enumerate() {
-                        match leaf.node_type {
-                            Node::TYPE_BUCKET => {
-                                let meta: BucketMeta = leaf.value().into();
-                                // Push all nested bucket pages onto the queue for exploration
-                                page_stack.push(meta.root_page);
-                            }
-                            // Ignore data nodes since they don't point to more pages
-                            Node::TYPE_DATA => (),
-                            // If somehow it isn't a bucket or data, that's really bad...
-                            _ => {
-                                return Err(Error::InvalidDB(format!(
-                                    "Page {} index {} has an invalid leaf node type {}",
-                                    page_id, i, leaf.node_type,
-                                )))
-                            }
-                        }
-                        // Make sure all leaf elements are in order
-                        if let Some(last) = last {
-                            if last >= leaf.key() {
-                                // let keys: Vec<&[u8]> =
-                                //     page.leaf_elements().iter().map(|l| l.key()).collect();
-                                // let key = leaf.key();
-                                return Err(Error::InvalidDB(format!(
-                                    "Leaf page {} contains unsorted elements",
-                                    page_id
-                                )));
-                            }
-                        }
-                        last = Some(leaf.key());
-                    }
-                }
-                Page::TYPE_FREELIST => {
-                    // Make sure our metadata is pointing at the correct freelist page
-                    // and we didn't somehow find our way to another one.
-                    if page_id != self.meta.freelist_page {
-                        return Err(Error::InvalidDB(format!(
-                            "Found Invalid Freelist Page {}",
-                            page_id
-                        )));
-                    }
-                    // "visit" all freelist pages (we don't actually care what data is in these pages)
-                    for page_id in page.freelist() {
-                        if !unused_pages.remove(page_id) {
-                            return Err(Error::InvalidDB(format!(
-                                "Page {} from freelist missing from unused_pages",
-                                page_id,
-                            )));
-                        }
-                    }
-                }
-                // There are no other valid page types, so getting here is really bad 😅
-                _ => {
-                    return Err(Error::InvalidDB(format!(
-                        "Invalid page type {} for page {}",
-                        page.page_type, page_id,
-                    )))
-                }
-            }
+    // builds a meta page for this transaction's meta data, stored at the given page id
+    fn meta_page(&self, meta_page_id: u64) -> Vec<u8> {
+        let mut buf = vec![0; self.db.inner.pagesize as usize];
+
+        // Safety: buffer is big enough to contain a page (see `assert!`)
+        let page = unsafe {
+            assert!(buf.len() >= std::mem::size_of::<Page>());
+            &mut *(buf.as_mut_ptr() as *mut Page)
+        };
+        page.id = meta_page_id;
+        page.page_type = Page::TYPE_META;
+        let m = page.meta_mut();
+        m.meta_page = meta_page_id as u32;
+        m.magic = self.meta.magic;
+        m.version = self.meta.version;
+        m.pagesize = self.meta.pagesize;
+        m.root = self.meta.root;
+        m.num_pages = self.meta.num_pages;
+        m.freelist_page = self.meta.freelist_page;
+        m.tx_id = self.meta.tx_id;
+        m.flags = self.meta.flags;
+        m.hash = m.hash_self();
+        buf
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> Result<u64> {
+        let pagesize = self.db.inner.pagesize;
+        // write both meta pages so the copy doesn't depend on which one was written last
+        for meta_page_id in 0..2 {
+            w.write_all(self.meta_page(meta_page_id).as_slice())?;
         }
+        let data =
+            &self.pages.data[(pagesize * 2) as usize..(pagesize * self.meta.num_pages) as usize];
+        w.write_all(data)?;
+        w.flush()?;
+        Ok(pagesize * self.meta.num_pages)
+    }
 
-        // Once we've explored all of the pages we can reach from the root bucket and freelist,
-        // If there are any pages left then we have an invalid database.
-        if !unused_pages.is_empty() {
-            return Err(Error::InvalidDB(format!(
-                "Unreachable pages {:?}",
-                unused_pages,
-            )));
+    fn check(&self) -> Result<()> {
+        let report = verify(&self.meta, &self.pages, &VerifyOptions::new().max_issues(1));
+        match report.issues.first() {
+            Some(VerifyIssue::ChecksumMismatch { page_id, .. }) => {
+                Err(Error::Corruption { page_id: *page_id })
+            }
+            Some(issue) => Err(Error::InvalidDB(issue.to_string())),
+            None => Ok(()),
         }
-        Ok(())
     }
 }
 
 impl Drop for TxInner<'_> {
     fn drop(&mut self) {
         if !self.lock.writable() {
-            let mut open_txs = self.db.inner.open_ro_txs.lock().unwrap();
-            let index = match open_txs.binary_search(&self.meta.tx_id) {
-                Ok(i) => i,
-                _ => return, // this shouldn't happen, but isn't the end of the world if it does
-            };
-            open_txs.remove(index);
+            self.db.inner.close_ro_tx(self.meta.tx_id);
+        }
+        // release the lock before calling the callbacks so they can start new transactions
+        self.lock = TxLock::Released;
+        for f in std::mem::take(&mut self.on_rollback) {
+            f();
         }
     }
 }
@@ -523,6 +686,80 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_try_tx() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        {
+            let tx = db.try_tx(true)?;
+            assert!(tx.writable());
+            assert_eq!(db.try_tx(true).err(), Some(Error::WriterBusy));
+            // read-only transactions don't have to wait for the writer
+            assert!(!db.try_tx(false)?.writable());
+            let handle = {
+                let db = db.clone();
+                std::thread::spawn(move || db.try_tx(true).err())
+            };
+            assert_eq!(handle.join().unwrap(), Some(Error::WriterBusy));
+            tx.commit()?;
+        }
+        assert!(db.try_tx(true)?.writable());
+        Ok(())
+    }
+
+    #[test]
+    fn test_callbacks() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        let calls = RefCell::new(Vec::new());
+        let callback = |name: &'static str| {
+            let calls = &calls;
+            let db = &db;
+            // the writer lock has already been released
+            move || calls.borrow_mut().push((name, db.try_tx(true).is_ok()))
+        };
+        {
+            let tx = db.tx(true)?;
+            tx.create_bucket("abc")?;
+            tx.on_commit(callback("commit 1"));
+            tx.on_rollback(callback("rollback"));
+            tx.on_commit(callback("commit 2"));
+            assert!(calls.borrow().is_empty());
+            tx.commit()?;
+        }
+        assert_eq!(
+            calls.replace(Vec::new()),
+            vec![("commit 1", true), ("commit 2", true)]
+        );
+        {
+            let tx = db.tx(true)?;
+            tx.create_bucket("def")?;
+            tx.on_commit(callback("commit"));
+            tx.on_rollback(callback("rollback"));
+            tx.rollback();
+        }
+        assert_eq!(calls.replace(Vec::new()), vec![("rollback", true)]);
+        {
+            let tx = db.tx(true)?;
+            tx.create_bucket("def")?;
+            tx.on_rollback(callback("dropped"));
+        }
+        assert_eq!(calls.replace(Vec::new()), vec![("dropped", true)]);
+        {
+            // read-only transactions can't be committed, so they are always rolled back
+            let tx = db.tx(false)?;
+            tx.on_commit(callback("commit"));
+            tx.on_rollback(callback("failed commit"));
+            assert_eq!(tx.commit(), Err(Error::ReadOnlyTx));
+        }
+        assert_eq!(calls.replace(Vec::new()), vec![("failed commit", true)]);
+
+        let tx = db.tx(false)?;
+        assert!(tx.get_bucket("abc").is_ok());
+        assert_eq!(tx.get_bucket("def").err(), Some(Error::BucketMissing));
+        Ok(())
+    }
+
     #[test]
     fn test_concurrent_txs() -> Result<()> {
         let random_file = RandomFile::new();
@@ -552,7 +789,7 @@ mod tests {
                         let inner = tx.inner.borrow_mut();
                         assert_eq!(inner.meta.tx_id, 1);
                         let freelist = inner.freelist.borrow();
-                        assert_eq!(freelist.inner.pages(), vec![]);
+                        assert_eq!(freelist.inner.pages(), Vec::<u64>::new());
                     }
                     let b = tx.create_bucket("abc")?;
                     b.put("123", "456")?;
@@ -612,8 +849,63 @@ mod tests {
             assert!(page.id == 10);
             assert!(page.overflow == 0);
             assert_eq!(freelist.meta.num_pages, 11);
-            assert_eq!(freelist.inner.pages(), vec![]);
+            assert_eq!(freelist.inner.pages(), Vec::<u64>::new());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_file() -> Result<()> {
+        let random_file = RandomFile::new();
+        let backup_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket("abc")?;
+            for i in 0..500_u32 {
+                b.put(i.to_be_bytes(), i.to_string())?;
+            }
+            b.create_bucket("nested")?.put("key", "value")?;
+            tx.commit()?;
+        }
+        let ro_tx = db.tx(false)?;
+        // keep writing while we have the read-only snapshot open,
+        // so both meta pages move past it
+        for _ in 0..2 {
+            let tx = db.tx(true)?;
+            let b = tx.get_bucket("abc")?;
+            for i in 0..500_u32 {
+                b.put(i.to_be_bytes(), "changed")?;
+            }
+            tx.delete_bucket("abc")?;
+            tx.create_bucket("def")?;
+            tx.commit()?;
+            let tx = db.tx(true)?;
+            tx.delete_bucket("def")?;
+            tx.create_bucket("abc")?;
+            tx.commit()?;
+        }
+
+        let mut buf = Vec::new();
+        let written = ro_tx.write_to(&mut buf)?;
+        assert_eq!(written, buf.len() as u64);
+        assert_eq!(written, ro_tx.inner.borrow().meta.num_pages * 1024);
+        ro_tx.copy_file(&backup_file)?;
+        drop(ro_tx);
+        assert_eq!(std::fs::read(&backup_file)?, buf);
+
+        let backup = OpenOptions::new().pagesize(1024).open(&backup_file)?;
+        backup.check()?;
+        let tx = backup.tx(false)?;
+        let b = tx.get_bucket("abc")?;
+        assert_eq!(b.kv_pairs().count(), 500);
+        for i in 0..500_u32 {
+            let kv = b.get_kv(i.to_be_bytes()).unwrap();
+            assert_eq!(kv.value(), i.to_string().as_bytes());
         }
+        let nested = b.get_bucket("nested")?;
+        assert_eq!(nested.get_kv("key").unwrap().value(), b"value");
+        assert!(tx.get_bucket("def").is_err());
         Ok(())
     }
 }
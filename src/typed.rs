@@ -0,0 +1,379 @@
+use std::{
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    bucket::Bucket,
+    cursor::Range,
+    data::Data,
+    errors::{Error, Result},
+    keys::{FromOrderedKey, Key, OrderedKey},
+};
+
+/// Encodes and decodes the values stored in a [`TypedBucket`].
+///
+/// jammdb comes with codecs for a few common formats, each behind its own feature:
+/// [`Bincode`] (`bincode`), [`MsgPack`] (`msgpack`) and [`Json`] (`json`).
+/// You can implement this trait to use any other format.
+pub trait Codec {
+    /// Encodes a value into bytes.
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>>;
+    /// Decodes a value from bytes that were returned by [`encode`](#tymethod.encode).
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T>;
+}
+
+/// Stores values using [bincode](https://docs.rs/bincode).
+///
+/// Only available with the `bincode` feature.
+#[cfg(feature = "bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        bincode::serialize(value).map_err(|e| Error::Codec(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        bincode::deserialize(bytes).map_err(|e| Error::Codec(e.to_string()))
+    }
+}
+
+/// Stores values using [MessagePack](https://msgpack.org), with struct fields stored by name.
+///
+/// Only available with the `msgpack` feature.
+#[cfg(feature = "msgpack")]
+pub struct MsgPack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MsgPack {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        rmp_serde::to_vec_named(value).map_err(|e| Error::Codec(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        rmp_serde::from_slice(bytes).map_err(|e| Error::Codec(e.to_string()))
+    }
+}
+
+/// Stores values as JSON.
+///
+/// Only available with the `json` feature.
+#[cfg(feature = "json")]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(|e| Error::Codec(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+        serde_json::from_slice(bytes).map_err(|e| Error::Codec(e.to_string()))
+    }
+}
+
+// doesn't own any K, V or C, so it is Send and Sync no matter what they are
+type Types<K, V, C> = PhantomData<fn() -> (K, V, C)>;
+
+/// A [`Bucket`] that stores keys of type `K` and values of type `V`.
+///
+/// Keys are encoded with [`OrderedKey`](keys/trait.OrderedKey.html), so they are sorted the same way as the values they came from
+/// (integers and floats numerically, strings lexicographically, and tuples field by field),
+/// and a plain [`Bucket`] can read them with the [`keys`](keys/index.html) module.
+/// That means [`range`](#method.range) works on the keys themselves instead of their bytes.
+/// Values are encoded with the [`Codec`] `C`.
+///
+/// Nested buckets are skipped when iterating, and trying to [`get`](#method.get) one
+/// returns an [`IncompatibleValue`](enum.Error.html#variant.IncompatibleValue) error.
+/// Only available with the `serde` feature.
+///
+/// # Examples
+///
+/// ```no_run
+/// use jammdb::{DB, MsgPack};
+/// use serde::{Deserialize, Serialize};
+/// # use jammdb::Error;
+///
+/// #[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// struct User {
+///     name: String,
+///     age: u8,
+/// }
+///
+/// # fn main() -> Result<(), Error> {
+/// let db = DB::open("my.db")?;
+/// let tx = db.tx(true)?;
+/// let users = tx.get_or_create_bucket("users")?.typed::<u64, User, MsgPack>();
+/// users.put(&1, &User { name: String::from("pj"), age: 30 })?;
+/// assert_eq!(users.get(&1)?.unwrap().name, "pj");
+/// for user in users.range(1..10) {
+///     let (id, user) = user?;
+///     println!("{}: {:?}", id, user);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct TypedBucket<'b, 'tx, K, V, C> {
+    bucket: Bucket<'b, 'tx>,
+    _phantom: Types<K, V, C>,
+}
+
+impl<'b, 'tx> Bucket<'b, 'tx> {
+    /// Wraps the bucket in a [`TypedBucket`] with keys of type `K`, and values of type `V` stored with the codec `C`.
+    ///
+    /// Only available with the `serde` feature.
+    pub fn typed<K, V, C>(self) -> TypedBucket<'b, 'tx, K, V, C>
+    where
+        K: OrderedKey + FromOrderedKey,
+        V: Serialize + DeserializeOwned,
+        C: Codec,
+    {
+        TypedBucket::new(self)
+    }
+}
+
+impl<'b, 'tx, K, V, C> TypedBucket<'b, 'tx, K, V, C>
+where
+    K: OrderedKey + FromOrderedKey,
+    V: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    /// Wraps a bucket.
+    pub fn new(bucket: Bucket<'b, 'tx>) -> Self {
+        TypedBucket {
+            bucket,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the wrapped bucket.
+    pub fn bucket(&self) -> &Bucket<'b, 'tx> {
+        &self.bucket
+    }
+
+    /// Unwraps the bucket.
+    pub fn into_inner(self) -> Bucket<'b, 'tx> {
+        self.bucket
+    }
+
+    /// Adds to or replaces the value for the key.
+    ///
+    /// Returns an error if the key currently exists but is a bucket instead of a value,
+    /// or if the value can't be encoded.
+    pub fn put(&self, key: &K, value: &V) -> Result<()> {
+        self.bucket.put(key.to_key(), C::encode(value)?)?;
+        Ok(())
+    }
+
+    /// Gets the value for the key.
+    ///
+    /// Returns an error if the key is a bucket instead of a value, or if the value can't be decoded.
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        match self.bucket.try_get(key.to_key())? {
+            Some(Data::KeyValue(kv)) => C::decode(kv.value()).map(Some),
+            Some(Data::Bucket(_)) => Err(Error::IncompatibleValue),
+            None => Ok(None),
+        }
+    }
+
+    /// Deletes the key, and returns the value it had.
+    ///
+    /// Returns a [`KeyValueMissing`](enum.Error.html#variant.KeyValueMissing) error if the key doesn't exist.
+    pub fn delete(&self, key: &K) -> Result<V> {
+        let kv = self.bucket.delete(key.to_key())?;
+        C::decode(kv.value())
+    }
+
+    /// Iterator over every key / value pair in the bucket, in key order.
+    pub fn iter(&self) -> TypedRange<'b, 'tx, K, V, C> {
+        TypedRange {
+            range: self.bucket.range((Bound::Unbounded, Bound::Unbounded)),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Iterator over the key / value pairs whose keys fall within the given range, in key order.
+    pub fn range<R: RangeBounds<K>>(&self, r: R) -> TypedRange<'b, 'tx, K, V, C> {
+        TypedRange {
+            range: self
+                .bucket
+                .range((encode_bound(r.start_bound()), encode_bound(r.end_bound()))),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+fn encode_bound<K: OrderedKey>(bound: Bound<&K>) -> Bound<Key> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.to_key()),
+        Bound::Excluded(key) => Bound::Excluded(key.to_key()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+type KeyBounds = (Bound<Key>, Bound<Key>);
+
+/// An iterator over the key / value pairs in a [`TypedBucket`].
+///
+/// Each item is decoded as it is returned, so each one is a [`Result`](std::result::Result).
+pub struct TypedRange<'b, 'tx, K, V, C> {
+    range: Range<'b, 'b, 'tx, KeyBounds>,
+    _phantom: Types<K, V, C>,
+}
+
+impl<'b, 'tx, K, V, C> TypedRange<'b, 'tx, K, V, C>
+where
+    K: FromOrderedKey,
+    V: DeserializeOwned,
+    C: Codec,
+{
+    fn decode(data: Data) -> Option<Result<(K, V)>> {
+        match data {
+            Data::KeyValue(kv) => Some(
+                K::from_key(kv.key())
+                    .and_then(|key| C::decode(kv.value()).map(|value| (key, value))),
+            ),
+            Data::Bucket(_) => None,
+        }
+    }
+}
+
+impl<'b, 'tx, K, V, C> Iterator for TypedRange<'b, 'tx, K, V, C>
+where
+    K: FromOrderedKey,
+    V: DeserializeOwned,
+    C: Codec,
+{
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = Self::decode(self.range.next()?) {
+                return Some(item);
+            }
+        }
+    }
+}
+
+impl<'b, 'tx, K, V, C> DoubleEndedIterator for TypedRange<'b, 'tx, K, V, C>
+where
+    K: FromOrderedKey,
+    V: DeserializeOwned,
+    C: Codec,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = Self::decode(self.range.next_back()?) {
+                return Some(item);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, any(feature = "bincode", feature = "msgpack", feature = "json")))]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{testutil::RandomFile, OpenOptions};
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        tags: Vec<String>,
+        score: Option<f64>,
+    }
+
+    fn user(i: i64) -> User {
+        User {
+            name: format!("user {}", i),
+            tags: vec![String::from("a"); i.unsigned_abs() as usize % 3],
+            score: if i % 2 == 0 {
+                Some(i as f64 / 2.0)
+            } else {
+                None
+            },
+        }
+    }
+
+    fn check_codec<C: Codec>() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket("users")?.typed::<i64, User, C>();
+            // insert out of order, with negative keys
+            for i in (-100..100).rev() {
+                b.put(&i, &user(i))?;
+            }
+            b.bucket().create_bucket(1000_i64.to_key())?;
+            tx.commit()?;
+        }
+        let tx = db.tx(true)?;
+        let b: TypedBucket<i64, User, C> = tx.get_bucket("users")?.typed();
+        assert_eq!(b.get(&-5)?, Some(user(-5)));
+        assert_eq!(b.get(&500)?, None);
+        // nested buckets are skipped, and can't be read as values
+        assert_eq!(b.get(&1000), Err(Error::IncompatibleValue));
+
+        let keys: Vec<i64> = b.iter().map(|kv| kv.unwrap().0).collect();
+        assert_eq!(keys, (-100..100).collect::<Vec<_>>());
+        let items = b.range(-3..=2).collect::<Result<Vec<_>>>()?;
+        assert_eq!(items, (-3..=2).map(|i| (i, user(i))).collect::<Vec<_>>());
+        let keys: Vec<i64> = b.range(..-98).rev().map(|kv| kv.unwrap().0).collect();
+        assert_eq!(keys, vec![-99, -100]);
+
+        assert_eq!(b.delete(&0)?, user(0));
+        assert_eq!(b.delete(&0), Err(Error::KeyValueMissing));
+        assert_eq!(b.range(-1..2).count(), 2);
+
+        let names = tx.create_bucket("names")?.typed::<String, u64, C>();
+        names.put(&String::from("b"), &2)?;
+        names.put(&String::from("a"), &1)?;
+        names.put(&String::from("a\0"), &3)?;
+        let keys: Vec<String> = names.iter().map(|kv| kv.unwrap().0).collect();
+        assert_eq!(keys, vec!["a", "a\0", "b"]);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "bincode")]
+    fn test_bincode() -> Result<()> {
+        check_codec::<Bincode>()
+    }
+
+    #[test]
+    #[cfg(feature = "msgpack")]
+    fn test_msgpack() -> Result<()> {
+        check_codec::<MsgPack>()
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn test_json() -> Result<()> {
+        check_codec::<Json>()
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn test_decode_errors() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        let tx = db.tx(true)?;
+        let b = tx.create_bucket("abc")?;
+        b.put(1_u32.to_key(), "not json")?;
+        b.put("bad key", "1")?;
+        let typed = b.typed::<u32, u32, Json>();
+        assert!(matches!(typed.get(&1), Err(Error::Codec(_))));
+        let items: Vec<_> = typed.iter().collect();
+        assert_eq!(items.len(), 2);
+        assert!(items
+            .iter()
+            .all(|item| matches!(item, Err(Error::Codec(_)))));
+        Ok(())
+    }
+}
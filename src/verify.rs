@@ -0,0 +1,830 @@
+use std::{fmt, mem::size_of};
+
+use crate::{
+    bucket::BucketMeta,
+    compression::Compression,
+    db::DB,
+    errors::{Error, Result},
+    meta::Meta,
+    node::Node,
+    page::{Page, PageID, Pages, HEADER_SIZE},
+};
+
+/// Options to configure [`DB::verify`](struct.DB.html#method.verify).
+///
+/// # Examples
+///
+/// ```no_run
+/// use jammdb::{DB, VerifyOptions};
+/// # use jammdb::Error;
+///
+/// # fn main() -> Result<(), Error> {
+/// let db = DB::open("my.db")?;
+/// let report = db.verify(VerifyOptions::new().max_issues(100))?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct VerifyOptions {
+    max_issues: Option<usize>,
+}
+
+impl VerifyOptions {
+    /// Returns a new VerifyOptions, with the default values.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops checking the database once this many issues have been found.
+    ///
+    /// By default every issue is collected.
+    pub fn max_issues(mut self, max_issues: usize) -> Self {
+        self.max_issues = Some(max_issues);
+        self
+    }
+}
+
+/// A problem found while verifying a database.
+///
+/// `bucket` is the path of bucket names from the root of the database
+/// to the bucket that the page belongs to. An empty path is the root bucket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VerifyIssue {
+    /// The page can't be reached from the root bucket or the freelist.
+    UnreachablePage {
+        /// The unreachable page.
+        page_id: u64,
+    },
+    /// The page is referenced from more than one place in the database.
+    DoubleReferencedPage {
+        /// The page that was already referenced.
+        page_id: u64,
+        /// The bucket that referenced it a second time.
+        bucket: Vec<Vec<u8>>,
+    },
+    /// The page points past the end of the database.
+    PageOutOfBounds {
+        /// The page that doesn't exist.
+        page_id: u64,
+        /// The bucket that references it.
+        bucket: Vec<Vec<u8>>,
+    },
+    /// The page's overflow pages run past the end of the database.
+    InvalidOverflow {
+        /// The first page of the block.
+        page_id: u64,
+        /// The number of overflow pages stored in the page header.
+        overflow: u64,
+        /// The bucket the page belongs to.
+        bucket: Vec<Vec<u8>>,
+    },
+    /// The keys in the page are not in sorted order.
+    ///
+    /// Only checked for buckets that use the default [`Comparator`](struct.Comparator.html).
+    UnsortedKeys {
+        /// The page with unsorted keys.
+        page_id: u64,
+        /// The bucket the page belongs to.
+        bucket: Vec<Vec<u8>>,
+    },
+    /// The page's type is not valid for where it was found.
+    InvalidPageType {
+        /// The page with the invalid type.
+        page_id: u64,
+        /// The type stored in the page header.
+        page_type: u8,
+        /// The bucket the page belongs to.
+        bucket: Vec<Vec<u8>>,
+    },
+    /// A leaf element is neither a key / value pair nor a nested bucket.
+    InvalidLeafType {
+        /// The leaf page.
+        page_id: u64,
+        /// The index of the element in the page.
+        index: usize,
+        /// The type stored in the element.
+        node_type: u8,
+        /// The bucket the page belongs to.
+        bucket: Vec<Vec<u8>>,
+    },
+    /// The page's elements point past the end of the page.
+    ElementsOutOfBounds {
+        /// The page with the invalid elements.
+        page_id: u64,
+        /// The bucket the page belongs to.
+        bucket: Vec<Vec<u8>>,
+    },
+    /// The freelist contains a page that is still in use, or lists the same page twice.
+    FreelistCollision {
+        /// The page in the freelist.
+        page_id: u64,
+    },
+    /// The page doesn't match its checksum (or can't be decrypted), but is otherwise valid.
+    ///
+    /// Only checked in databases created by versions of jammdb that write page checksums.
+    ChecksumMismatch {
+        /// The corrupted page.
+        page_id: u64,
+        /// The bucket the page belongs to.
+        bucket: Vec<Vec<u8>>,
+    },
+}
+
+impl fmt::Display for VerifyIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyIssue::UnreachablePage { page_id } => write!(f, "Page {} is unreachable", page_id),
+            VerifyIssue::DoubleReferencedPage { page_id, bucket } => write!(
+                f,
+                "Page {} is referenced more than once, last from bucket {}",
+                page_id,
+                BucketPath(bucket)
+            ),
+            VerifyIssue::PageOutOfBounds { page_id, bucket } => write!(
+                f,
+                "Page {} from bucket {} is out of bounds",
+                page_id,
+                BucketPath(bucket)
+            ),
+            VerifyIssue::InvalidOverflow {
+                page_id,
+                overflow,
+                bucket,
+            } => write!(
+                f,
+                "Page {} from bucket {} has {} overflow pages that run past the end of the database",
+                page_id,
+                BucketPath(bucket),
+                overflow
+            ),
+            VerifyIssue::UnsortedKeys { page_id, bucket } => write!(
+                f,
+                "Page {} from bucket {} contains unsorted elements",
+                page_id,
+                BucketPath(bucket)
+            ),
+            VerifyIssue::InvalidPageType {
+                page_id,
+                page_type,
+                bucket,
+            } => write!(
+                f,
+                "Invalid page type {} for page {} from bucket {}",
+                page_type,
+                page_id,
+                BucketPath(bucket)
+            ),
+            VerifyIssue::InvalidLeafType {
+                page_id,
+                index,
+                node_type,
+                bucket,
+            } => write!(
+                f,
+                "Page {} index {} from bucket {} has an invalid leaf node type {}",
+                page_id,
+                index,
+                BucketPath(bucket),
+                node_type
+            ),
+            VerifyIssue::ElementsOutOfBounds { page_id, bucket } => write!(
+                f,
+                "Page {} from bucket {} has elements that run past the end of the page",
+                page_id,
+                BucketPath(bucket)
+            ),
+            VerifyIssue::FreelistCollision { page_id } => {
+                write!(f, "Page {} from the freelist is already in use", page_id)
+            }
+            VerifyIssue::ChecksumMismatch { page_id, bucket } => write!(
+                f,
+                "Page {} from bucket {} doesn't match its checksum",
+                page_id,
+                BucketPath(bucket)
+            ),
+        }
+    }
+}
+
+// Formats a bucket path as "/name/nested-name"
+struct BucketPath<'a>(&'a [Vec<u8>]);
+
+impl fmt::Display for BucketPath<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "/");
+        }
+        for name in self.0 {
+            write!(f, "/{}", String::from_utf8_lossy(name))?;
+        }
+        Ok(())
+    }
+}
+
+/// The result of verifying a database.
+///
+/// Returned by [`DB::verify`](struct.DB.html#method.verify).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Every problem that was found, in the order they were found.
+    pub issues: Vec<VerifyIssue>,
+    /// Number of pages that were checked, including overflow pages.
+    pub pages_checked: u64,
+}
+
+impl VerifyReport {
+    /// Returns true if no problems were found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+impl DB {
+    /// Checks the integrity of the database.
+    ///
+    /// Every page reachable from the root bucket and the freelist is visited from a read-only transaction,
+    /// and every problem that is found is collected in the returned [`VerifyReport`]
+    /// instead of stopping at the first one.
+    ///
+    /// # Errors
+    ///
+    /// Problems with the database are returned in the report. This only returns an error if the transaction can't be created.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use jammdb::{DB, VerifyOptions};
+    /// # use jammdb::Error;
+    ///
+    /// # fn main() -> Result<(), Error> {
+    /// let db = DB::open("my.db")?;
+    /// let report = db.verify(VerifyOptions::new())?;
+    /// for issue in report.issues.iter() {
+    ///     println!("{}", issue);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn verify(&self, options: VerifyOptions) -> Result<VerifyReport> {
+        let tx = self.tx(false)?;
+        let tx = tx.inner.borrow();
+        Ok(verify(&tx.meta, &tx.pages, &options))
+    }
+}
+
+pub(crate) fn verify(meta: &Meta, pages: &Pages, options: &VerifyOptions) -> VerifyReport {
+    let mut v = Verifier {
+        pages,
+        checksums: meta.has_checksums(),
+        num_pages: meta.num_pages,
+        seen: vec![false; meta.num_pages as usize],
+        report: VerifyReport::default(),
+        max_issues: options.max_issues.unwrap_or(usize::MAX),
+    };
+    // the meta pages are always in use
+    for page_id in 0..2.min(meta.num_pages) {
+        v.seen[page_id as usize] = true;
+    }
+    v.walk(meta.root.root_page, meta.freelist_page);
+    if !v.done() {
+        for page_id in 0..meta.num_pages {
+            if !v.seen[page_id as usize] && !v.issue(VerifyIssue::UnreachablePage { page_id }) {
+                break;
+            }
+        }
+    }
+    v.report
+}
+
+struct Verifier<'a> {
+    pages: &'a Pages,
+    checksums: bool,
+    num_pages: u64,
+    seen: Vec<bool>,
+    report: VerifyReport,
+    max_issues: usize,
+}
+
+impl<'a> Verifier<'a> {
+    // records an issue, and returns false once we've found enough of them
+    fn issue(&mut self, issue: VerifyIssue) -> bool {
+        self.report.issues.push(issue);
+        !self.done()
+    }
+
+    fn done(&self) -> bool {
+        self.report.issues.len() >= self.max_issues
+    }
+
+    // marks the page (and its overflow pages) as used, and returns the page if it is safe to read
+    fn visit(&mut self, page_id: PageID, bucket: &[Vec<u8>]) -> Option<&'a Page> {
+        if page_id >= self.num_pages {
+            self.issue(VerifyIssue::PageOutOfBounds {
+                page_id,
+                bucket: bucket.to_vec(),
+            });
+            return None;
+        }
+        if self.seen[page_id as usize] {
+            self.issue(VerifyIssue::DoubleReferencedPage {
+                page_id,
+                bucket: bucket.to_vec(),
+            });
+            return None;
+        }
+        let pages = self.pages;
+        // the header can always be read, even if the rest of the page is encrypted
+        let overflow = pages.raw_page(page_id).overflow;
+        if page_id + overflow >= self.num_pages {
+            self.seen[page_id as usize] = true;
+            self.report.pages_checked += 1;
+            self.issue(VerifyIssue::InvalidOverflow {
+                page_id,
+                overflow,
+                bucket: bucket.to_vec(),
+            });
+            return None;
+        }
+        for id in page_id..=(page_id + overflow) {
+            self.report.pages_checked += 1;
+            if self.seen[id as usize] {
+                self.issue(VerifyIssue::DoubleReferencedPage {
+                    page_id: id,
+                    bucket: bucket.to_vec(),
+                });
+            }
+            self.seen[id as usize] = true;
+        }
+        match pages.page(page_id) {
+            Ok(page) => Some(page),
+            // check_checksum will report it, unless there's something more specific wrong with the page
+            Err(Error::Corruption { .. }) => Some(pages.raw_page(page_id)),
+            // encrypted pages that fail to decrypt can't be read at all
+            Err(_) => {
+                self.issue(VerifyIssue::ChecksumMismatch {
+                    page_id,
+                    bucket: bucket.to_vec(),
+                });
+                None
+            }
+        }
+    }
+
+    // Checks the page's checksum once everything else about it has been checked,
+    // so a page is only reported once, for the most specific problem we can find.
+    fn check_checksum(&mut self, page: &Page, issues_before: usize, bucket: &[Vec<u8>]) -> bool {
+        let size = ((page.overflow + 1) * self.pages.pagesize) as usize;
+        if !self.checksums || self.report.issues.len() > issues_before || page.checksum_valid(size)
+        {
+            return true;
+        }
+        self.issue(VerifyIssue::ChecksumMismatch {
+            page_id: page.id,
+            bucket: bucket.to_vec(),
+        })
+    }
+
+    fn walk(&mut self, root_page: PageID, freelist_page: PageID) {
+        // Every bucket we find is stored here, so the stack only needs to keep track of an index.
+        let mut buckets: Vec<Vec<Vec<u8>>> = vec![Vec::new()];
+        // Buckets with a custom comparator can't have their order checked, since we don't know the comparator.
+        let mut bytewise = vec![true];
+        let mut page_stack = vec![(root_page, 0)];
+        while let Some((page_id, bucket_index)) = page_stack.pop() {
+            if self.done() {
+                return;
+            }
+            let bucket = buckets[bucket_index].clone();
+            let page = match self.visit(page_id, &bucket) {
+                Some(page) => page,
+                None => continue,
+            };
+            // the element headers are read before anything else, so make sure they're safe to follow
+            let size = ((page.overflow + 1) * self.pages.pagesize) as usize;
+            if matches!(page.page_type, Page::TYPE_BRANCH | Page::TYPE_LEAF)
+                && !page.elements_in_bounds(size)
+            {
+                self.issue(VerifyIssue::ElementsOutOfBounds { page_id, bucket });
+                continue;
+            }
+            let issues_before = self.report.issues.len();
+            match page.page_type {
+                Page::TYPE_BRANCH => {
+                    let mut last: Option<&[u8]> = None;
+                    let mut sorted = true;
+                    for b in page.branch_elements().iter() {
+                        page_stack.push((b.page, bucket_index));
+                        if let Some(last) = last {
+                            sorted &= last < b.key();
+                        }
+                        last = Some(b.key());
+                    }
+                    if !sorted && bytewise[bucket_index] {
+                        self.issue(VerifyIssue::UnsortedKeys { page_id, bucket });
+                    }
+                }
+                Page::TYPE_LEAF => {
+                    let mut last: Option<&[u8]> = None;
+                    let mut sorted = true;
+                    for (index, leaf) in page.leaf_elements().iter().enumerate() {
+                        match leaf.node_type {
+                            Node::TYPE_BUCKET => {
+                                let meta: BucketMeta = leaf.value().into();
+                                let mut nested = bucket.clone();
+                                nested.push(leaf.key().to_vec());
+                                buckets.push(nested);
+                                bytewise.push(meta.comparator == 0);
+                                page_stack.push((meta.root_page, buckets.len() - 1));
+                            }
+                            node_type
+                                if node_type & Node::TYPE_MASK == Node::TYPE_DATA
+                                    && Compression::valid_flag(
+                                        node_type >> Node::COMPRESSION_SHIFT,
+                                    ) => {}
+                            node_type => {
+                                self.issue(VerifyIssue::InvalidLeafType {
+                                    page_id,
+                                    index,
+                                    node_type,
+                                    bucket: bucket.clone(),
+                                });
+                            }
+                        }
+                        if let Some(last) = last {
+                            sorted &= last < leaf.key();
+                        }
+                        last = Some(leaf.key());
+                    }
+                    if !sorted && bytewise[bucket_index] {
+                        self.issue(VerifyIssue::UnsortedKeys { page_id, bucket });
+                    }
+                }
+                page_type => {
+                    self.issue(VerifyIssue::InvalidPageType {
+                        page_id,
+                        page_type,
+                        bucket,
+                    });
+                }
+            }
+            self.check_checksum(page, issues_before, &buckets[bucket_index]);
+        }
+        if self.done() {
+            return;
+        }
+
+        // Now that we know every page that is in use, make sure none of them are in the freelist
+        let page = match self.visit(freelist_page, &[]) {
+            Some(page) => page,
+            None => return,
+        };
+        let issues_before = self.report.issues.len();
+        if page.page_type != Page::TYPE_FREELIST {
+            self.issue(VerifyIssue::InvalidPageType {
+                page_id: freelist_page,
+                page_type: page.page_type,
+                bucket: Vec::new(),
+            });
+            return;
+        }
+        let size = (page.overflow + 1) * self.pages.pagesize;
+        let freelist_size = page
+            .count
+            .checked_mul(size_of::<PageID>() as u64)
+            .and_then(|freelist_size| freelist_size.checked_add(HEADER_SIZE as u64));
+        if !matches!(freelist_size, Some(freelist_size) if freelist_size <= size) {
+            self.issue(VerifyIssue::ElementsOutOfBounds {
+                page_id: freelist_page,
+                bucket: Vec::new(),
+            });
+            return;
+        }
+        for &page_id in page.freelist() {
+            let issue = if page_id >= self.num_pages {
+                VerifyIssue::PageOutOfBounds {
+                    page_id,
+                    bucket: Vec::new(),
+                }
+            } else if self.seen[page_id as usize] {
+                VerifyIssue::FreelistCollision { page_id }
+            } else {
+                self.seen[page_id as usize] = true;
+                self.report.pages_checked += 1;
+                continue;
+            };
+            if !self.issue(issue) {
+                return;
+            }
+        }
+        self.check_checksum(page, issues_before, &[]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::OpenOptions as FileOpenOptions,
+        io::{Seek, SeekFrom, Write},
+    };
+
+    use super::*;
+    use crate::{testutil::RandomFile, OpenOptions};
+
+    // writes directly to the database file, behind the database's back
+    fn corrupt(random_file: &RandomFile, offset: u64, data: &[u8]) -> Result<()> {
+        let mut file = FileOpenOptions::new().write(true).open(random_file)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    // returns the offset of the given pointer into the database file
+    fn offset(db: &DB, ptr: *const u8) -> u64 {
+        let data = db.inner.data.lock().unwrap();
+        (ptr as usize - data.as_ptr() as usize) as u64
+    }
+
+    fn bucket_root(db: &DB, path: &[&str]) -> Result<PageID> {
+        let tx = db.tx(false)?;
+        let mut b = tx.get_bucket(path[0])?;
+        for name in &path[1..] {
+            b = b.get_bucket(*name)?;
+        }
+        let root_page = b.inner.borrow().meta.root_page;
+        Ok(root_page)
+    }
+
+    #[test]
+    fn test_verify() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket("abc")?;
+            b.put("a", "1")?;
+            b.put("b", "2")?;
+            b.create_bucket("def")?.put("c", "3")?;
+            tx.create_bucket("ghi")?.put("d", "4")?;
+            tx.commit()?;
+        }
+        {
+            // free up some pages so the freelist isn't empty
+            let tx = db.tx(true)?;
+            tx.get_bucket("ghi")?.put("e", "5")?;
+            tx.commit()?;
+        }
+        let report = db.verify(VerifyOptions::new())?;
+        assert!(report.is_ok(), "{:?}", report);
+        let num_pages = db.tx(false)?.inner.borrow().meta.num_pages;
+        assert_eq!(report.pages_checked, num_pages - 2);
+
+        let abc = bucket_root(&db, &["abc"])?;
+        let def = bucket_root(&db, &["abc", "def"])?;
+        let ghi = bucket_root(&db, &["ghi"])?;
+        // make "abc"'s keys unsorted by changing "a" to "z"
+        let key = {
+            let tx = db.tx(false)?;
+            let tx = tx.inner.borrow();
+            let key = tx.pages.page(abc)?.leaf_elements()[0].key();
+            assert_eq!(key, b"a");
+            key.as_ptr()
+        };
+        corrupt(&random_file, offset(&db, key), b"z")?;
+        // give "def"'s root page an invalid page type
+        corrupt(&random_file, def * 1024 + 8, &[9])?;
+        // make "ghi"'s root page overflow past the end of the database
+        corrupt(&random_file, ghi * 1024 + 24, &1000_u64.to_ne_bytes())?;
+
+        let report = db.verify(VerifyOptions::new())?;
+        assert!(!report.is_ok());
+        assert_eq!(report.issues.len(), 3, "{:?}", report.issues);
+        assert!(report.issues.contains(&VerifyIssue::UnsortedKeys {
+            page_id: abc,
+            bucket: vec![b"abc".to_vec()],
+        }));
+        assert!(report.issues.contains(&VerifyIssue::InvalidPageType {
+            page_id: def,
+            page_type: 9,
+            bucket: vec![b"abc".to_vec(), b"def".to_vec()],
+        }));
+        assert!(report.issues.contains(&VerifyIssue::InvalidOverflow {
+            page_id: ghi,
+            overflow: 1000,
+            bucket: vec![b"ghi".to_vec()],
+        }));
+        assert_eq!(
+            VerifyIssue::InvalidPageType {
+                page_id: def,
+                page_type: 9,
+                bucket: vec![b"abc".to_vec(), b"def".to_vec()],
+            }
+            .to_string(),
+            format!("Invalid page type 9 for page {} from bucket /abc/def", def)
+        );
+
+        let report = db.verify(VerifyOptions::new().max_issues(1))?;
+        assert_eq!(report.issues.len(), 1);
+        match db.check() {
+            Err(Error::InvalidDB(message)) => assert_eq!(message, report.issues[0].to_string()),
+            result => panic!("Expected an invalid database, got {:?}", result),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_freelist() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        for i in 0..3_u32 {
+            let tx = db.tx(true)?;
+            tx.get_or_create_bucket("abc")?
+                .put(i.to_be_bytes(), "value")?;
+            tx.commit()?;
+        }
+        let abc = bucket_root(&db, &["abc"])?;
+        let (freelist_page, free_page) = {
+            let tx = db.tx(false)?;
+            let tx = tx.inner.borrow();
+            let page = tx.pages.page(tx.meta.freelist_page)?;
+            let free_pages = page.freelist();
+            assert!(!free_pages.is_empty());
+            (tx.meta.freelist_page, free_pages[0])
+        };
+        // replace a free page with a page that is in use
+        corrupt(&random_file, freelist_page * 1024 + 32, &abc.to_ne_bytes())?;
+
+        let report = db.verify(VerifyOptions::new())?;
+        assert_eq!(
+            report.issues,
+            vec![
+                VerifyIssue::FreelistCollision { page_id: abc },
+                VerifyIssue::UnreachablePage { page_id: free_page },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_elements_out_of_bounds() -> Result<()> {
+        let random_file = RandomFile::new();
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        {
+            let tx = db.tx(true)?;
+            let b = tx.create_bucket("abc")?;
+            b.put("a", "1")?;
+            b.put("b", "2")?;
+            tx.commit()?;
+        }
+        let abc = bucket_root(&db, &["abc"])?;
+        // claim there are far more elements than can fit on the page
+        corrupt(&random_file, abc * 1024 + 16, &1000_u64.to_ne_bytes())?;
+
+        let report = db.verify(VerifyOptions::new())?;
+        let issue = VerifyIssue::ElementsOutOfBounds {
+            page_id: abc,
+            bucket: vec![b"abc".to_vec()],
+        };
+        assert_eq!(report.issues, vec![issue.clone()]);
+        assert_eq!(
+            issue.to_string(),
+            format!(
+                "Page {} from bucket /abc has elements that run past the end of the page",
+                abc
+            )
+        );
+        Ok(())
+    }
+
+    fn checksum_db(random_file: &RandomFile) -> Result<(PageID, PageID)> {
+        let db = OpenOptions::new().pagesize(1024).open(random_file)?;
+        let tx = db.tx(true)?;
+        let b = tx.create_bucket("abc")?;
+        b.put("a", "1")?;
+        b.put("b", "2")?;
+        tx.commit()?;
+        assert!(db.verify(VerifyOptions::new())?.is_ok());
+
+        let abc = bucket_root(&db, &["abc"])?;
+        let freelist_page = db.tx(false)?.inner.borrow().meta.freelist_page;
+        // change the value of "a", which is otherwise perfectly valid
+        let value = {
+            let tx = db.tx(false)?;
+            let tx = tx.inner.borrow();
+            let value = tx.pages.page(abc)?.leaf_elements()[0].value();
+            assert_eq!(value, b"1");
+            value.as_ptr()
+        };
+        corrupt(random_file, offset(&db, value), b"9")?;
+        Ok((abc, freelist_page))
+    }
+
+    #[test]
+    fn test_verify_checksums() -> Result<()> {
+        let random_file = RandomFile::new();
+        let (abc, freelist_page) = checksum_db(&random_file)?;
+        let db = OpenOptions::new().pagesize(1024).open(&random_file)?;
+        let report = db.verify(VerifyOptions::new())?;
+        let issue = VerifyIssue::ChecksumMismatch {
+            page_id: abc,
+            bucket: vec![b"abc".to_vec()],
+        };
+        assert_eq!(report.issues, vec![issue.clone()]);
+        assert_eq!(
+            issue.to_string(),
+            format!("Page {} from bucket /abc doesn't match its checksum", abc)
+        );
+        assert_eq!(db.check(), Err(Error::Corruption { page_id: abc }));
+        // without verifying checksums the data can still be read
+        {
+            let tx = db.tx(false)?;
+            let b = tx.get_bucket("abc")?;
+            assert_eq!(b.get_kv("a").unwrap().value(), b"9");
+        }
+        drop(db);
+
+        // a corrupted freelist is found as soon as the database is opened
+        corrupt(
+            &random_file,
+            freelist_page * 1024 + 8,
+            &[Page::TYPE_FREELIST, 1],
+        )?;
+        let db = OpenOptions::new()
+            .pagesize(1024)
+            .verify_checksums(true)
+            .open(&random_file);
+        assert_eq!(
+            db.err(),
+            Some(Error::Corruption {
+                page_id: freelist_page
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_checksums_on_read() -> Result<()> {
+        let random_file = RandomFile::new();
+        let (abc, _) = checksum_db(&random_file)?;
+        let db = OpenOptions::new()
+            .pagesize(1024)
+            .verify_checksums(true)
+            .open(&random_file)?;
+        let corruption = Some(Error::Corruption { page_id: abc });
+        {
+            let tx = db.tx(true)?;
+            let b = tx.get_bucket("abc")?;
+            // reading the corrupted page returns an error
+            assert_eq!(b.try_get("a").err(), corruption);
+            assert_eq!(b.put("c", "3").err(), corruption);
+            // and iterating stops after it
+            let mut cursor = b.cursor();
+            assert_eq!(cursor.try_next().unwrap().err(), corruption);
+            assert!(cursor.try_next().is_none());
+            assert_eq!(b.cursor().count(), 0);
+            // even when the cursor runs into it while seeking
+            let mut cursor = b.cursor();
+            assert!(!cursor.seek("a"));
+            assert_eq!(cursor.try_next().unwrap().err(), corruption);
+            assert!(cursor.try_next().is_none());
+            // the lossy get treats the key as missing
+            assert!(b.get_kv("a").is_none());
+        }
+        let tx = db.read_tx()?;
+        let b = tx.get_bucket("abc")?;
+        // iterators return the error and stop
+        let mut iter = b.iter();
+        assert_eq!(iter.next().unwrap().err(), corruption);
+        assert!(iter.next().is_none());
+        assert_eq!(b.get("a").err(), corruption);
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_checksums_after_old_writer() -> Result<()> {
+        let random_file = RandomFile::new();
+        checksum_db(&random_file)?;
+        // versions without checksums zero everything after the meta page's hash when they commit
+        let flags = HEADER_SIZE + std::mem::size_of::<crate::meta::Meta>() - 8;
+        for meta_page in 0..2 {
+            corrupt(&random_file, meta_page * 1024 + flags as u64, &[0; 8])?;
+        }
+        let db = OpenOptions::new()
+            .pagesize(1024)
+            .verify_checksums(true)
+            .open(&random_file)?;
+        assert!(db.verify(VerifyOptions::new())?.is_ok());
+        {
+            let tx = db.tx(true)?;
+            let b = tx.get_bucket("abc")?;
+            assert_eq!(b.get_kv("a").unwrap().value(), b"9");
+            b.put("c", "3")?;
+            tx.commit()?;
+        }
+        // and the flag isn't turned back on by the next commit
+        assert!(!db.inner.meta()?.has_checksums());
+        assert!(db.verify(VerifyOptions::new())?.is_ok());
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+#![allow(clippy::needless_return, clippy::unnecessary_unwrap, clippy::ptr_arg)]
+
 use std::{
     collections::BTreeMap,
     fs::File,
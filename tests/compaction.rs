@@ -0,0 +1,101 @@
+use jammdb::{Bucket, Data, Error, OpenOptions};
+
+mod common;
+
+#[test]
+fn compact_into() -> Result<(), Error> {
+    let random_file = common::RandomFile::new();
+    let compacted_file = common::RandomFile::new();
+    let db = OpenOptions::new()
+        .pagesize(1024)
+        .strict_mode(true)
+        .open(&random_file)?;
+    {
+        let tx = db.tx(true)?;
+        for bucket in 0..5_u64 {
+            let b = tx.create_bucket(bucket.to_be_bytes())?;
+            for i in 0..2000_u64 {
+                b.put(i.to_be_bytes(), vec![i as u8; 100])?;
+            }
+            let nested = b.create_bucket("nested")?;
+            nested.create_bucket("empty")?;
+            for i in 0..200_u64 {
+                nested.put(i.to_be_bytes(), i.to_string())?;
+            }
+        }
+        tx.commit()?;
+    }
+    {
+        // delete most of the data so the file is mostly empty
+        let tx = db.tx(true)?;
+        for bucket in 1..5_u64 {
+            tx.delete_bucket(bucket.to_be_bytes())?;
+        }
+        let b = tx.get_bucket(0_u64.to_be_bytes())?;
+        for i in 100..2000_u64 {
+            b.delete(i.to_be_bytes())?;
+        }
+        tx.commit()?;
+    }
+
+    let report = db.compact_into(&compacted_file, OpenOptions::new().pagesize(1024))?;
+    assert_eq!(
+        report.src_size,
+        std::fs::metadata(&random_file)?.len(),
+        "source size should match the original file"
+    );
+    assert_eq!(report.dst_size, std::fs::metadata(&compacted_file)?.len());
+    assert!(report.dst_size < report.src_size / 10);
+
+    // compacting into an existing file is not allowed
+    assert!(db
+        .compact_into(&compacted_file, OpenOptions::new().pagesize(1024))
+        .is_err());
+
+    let compacted = OpenOptions::new()
+        .pagesize(1024)
+        .strict_mode(true)
+        .open(&compacted_file)?;
+    compacted.check()?;
+    let tx = db.tx(false)?;
+    let compacted_tx = compacted.tx(false)?;
+    assert_eq!(tx.buckets().count(), compacted_tx.buckets().count());
+    for ((name, b), (compacted_name, compacted_b)) in tx.buckets().zip(compacted_tx.buckets()) {
+        assert_eq!(name, compacted_name);
+        check_same(&b, &compacted_b);
+    }
+    drop(compacted_tx);
+
+    // make sure the compacted database is still writable
+    {
+        let tx = compacted.tx(true)?;
+        let b = tx.get_bucket(0_u64.to_be_bytes())?;
+        for i in 100..2000_u64 {
+            b.put(i.to_be_bytes(), vec![i as u8; 100])?;
+        }
+        tx.commit()?;
+    }
+    compacted.check()
+}
+
+fn check_same(b: &Bucket, compacted: &Bucket) {
+    assert_eq!(b.next_int(), compacted.next_int());
+    let mut data = b.cursor();
+    let mut compacted_data = compacted.cursor();
+    loop {
+        match (data.next(), compacted_data.next()) {
+            (Some(Data::Bucket(name)), Some(Data::Bucket(compacted_name))) => {
+                assert_eq!(name, compacted_name);
+                check_same(
+                    &b.get_bucket(&name).unwrap(),
+                    &compacted.get_bucket(&compacted_name).unwrap(),
+                );
+            }
+            (Some(Data::KeyValue(kv)), Some(Data::KeyValue(compacted_kv))) => {
+                assert_eq!(kv.kv(), compacted_kv.kv());
+            }
+            (None, None) => break,
+            (data, compacted_data) => panic!("{:?} != {:?}", data, compacted_data),
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use jammdb::{Bucket, Data, Error, OpenOptions, DB};
+use std::ops::Bound;
+
+use jammdb::{Bucket, Cursor, Data, Error, OpenOptions, DB};
 use rand::prelude::*;
 
 mod common;
@@ -280,3 +282,131 @@ fn kv_iter() -> Result<(), Error> {
     };
     Ok(())
 }
+
+#[test]
+fn reverse_iter() -> Result<(), Error> {
+    let random_file = common::RandomFile::new();
+    let db = OpenOptions::new()
+        .pagesize(1024)
+        .strict_mode(true)
+        .open(&random_file)?;
+    let keys: Vec<String> = (0..1000).map(|i| format!("{:05}", i)).collect();
+    {
+        let tx = db.tx(true)?;
+        let b = tx.create_bucket("abc")?;
+        let mut random_keys = keys.clone();
+        random_keys.shuffle(&mut rand::thread_rng());
+        for key in random_keys.iter() {
+            b.put(key.clone(), key.clone())?;
+        }
+        check_reverse(&keys, &b);
+        tx.commit()?;
+    }
+    {
+        let tx = db.tx(false)?;
+        let b = tx.get_bucket("abc")?;
+        check_reverse(&keys, &b);
+    }
+    {
+        // delete some keys so some of the nodes are left partially empty
+        let tx = db.tx(true)?;
+        let b = tx.get_bucket("abc")?;
+        for key in keys.iter().skip(100).take(500) {
+            b.delete(key)?;
+        }
+        let keys: Vec<String> = keys[..100]
+            .iter()
+            .chain(keys[600..].iter())
+            .cloned()
+            .collect();
+        check_reverse(&keys, &b);
+    }
+    db.check()
+}
+
+fn check_reverse(keys: &[String], b: &Bucket) {
+    let reversed: Vec<Vec<u8>> = b.kv_pairs().rev().map(|kv| kv.key().to_vec()).collect();
+    let expected: Vec<Vec<u8>> = keys.iter().rev().map(|k| k.as_bytes().to_vec()).collect();
+    assert_eq!(reversed, expected);
+
+    // ranges with excluded bounds on both ends
+    let (start, end) = (keys.len() / 4, keys.len() * 3 / 4);
+    let bounds = (
+        Bound::Excluded(keys[start].clone()),
+        Bound::Excluded(keys[end].clone()),
+    );
+    let forward: Vec<Vec<u8>> = b.range(bounds.clone()).map(|d| d.key().to_vec()).collect();
+    let mut backward: Vec<Vec<u8>> = b.range(bounds).rev().map(|d| d.key().to_vec()).collect();
+    backward.reverse();
+    let expected: Vec<Vec<u8>> = keys[start + 1..end]
+        .iter()
+        .map(|k| k.as_bytes().to_vec())
+        .collect();
+    assert_eq!(forward, expected);
+    assert_eq!(backward, expected);
+
+    // walk the cursor backwards from the end
+    let mut cursor = b.cursor();
+    assert_eq!(
+        Cursor::last(&mut cursor).unwrap().key(),
+        keys.last().unwrap().as_bytes()
+    );
+    for key in keys.iter().rev().skip(1) {
+        assert_eq!(cursor.prev().unwrap().key(), key.as_bytes());
+    }
+    assert!(cursor.prev().is_none());
+    assert_eq!(cursor.first().unwrap().key(), keys[0].as_bytes());
+    assert_eq!(cursor.next().unwrap().key(), keys[1].as_bytes());
+    assert_eq!(cursor.prev().unwrap().key(), keys[0].as_bytes());
+
+    // running off either end and turning around returns the element at that end
+    let mut cursor = b.cursor();
+    assert_eq!(cursor.by_ref().count(), keys.len());
+    assert!(cursor.next().is_none());
+    assert_eq!(
+        cursor.prev().unwrap().key(),
+        keys.last().unwrap().as_bytes()
+    );
+    assert_eq!(
+        cursor.prev().unwrap().key(),
+        keys[keys.len() - 2].as_bytes()
+    );
+    let mut cursor = b.cursor();
+    Cursor::last(&mut cursor);
+    while cursor.prev().is_some() {}
+    assert_eq!(cursor.next().unwrap().key(), keys[0].as_bytes());
+    assert_eq!(cursor.next().unwrap().key(), keys[1].as_bytes());
+
+    // first and last
+    let mut cursor = b.cursor();
+    assert_eq!(cursor.first().unwrap().key(), keys[0].as_bytes());
+    assert_eq!(
+        b.cursor().last().unwrap().key(),
+        keys.last().unwrap().as_bytes()
+    );
+    let mut cursor = b.cursor();
+    cursor.next_back();
+    assert_eq!(
+        cursor.last().unwrap().key(),
+        keys[keys.len() - 2].as_bytes()
+    );
+
+    // iterate from both ends and make sure they meet in the middle
+    let mut cursor = b.cursor();
+    let mut front = Vec::new();
+    let mut back = Vec::new();
+    loop {
+        match cursor.next() {
+            Some(data) => front.push(data.key().to_vec()),
+            None => break,
+        }
+        match cursor.next_back() {
+            Some(data) => back.push(data.key().to_vec()),
+            None => break,
+        }
+    }
+    back.reverse();
+    front.append(&mut back);
+    let expected: Vec<Vec<u8>> = keys.iter().map(|k| k.as_bytes().to_vec()).collect();
+    assert_eq!(front, expected);
+}